@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use esp32_hal::prelude::*;
+
+use esp32_hal::dport::Split;
+use esp32_hal::dprintln;
+use esp32_hal::gpio::{enable_ext0_wakeup, WakeupLevel};
+use esp32_hal::reset;
+use esp32_hal::serial::{config::Config, NoRx, NoTx, Serial};
+use esp32_hal::sleep;
+use esp32_hal::Core;
+
+#[ram(rtc_slow, zeroed)]
+static mut BOOT_COUNT: u32 = 0;
+
+#[entry]
+fn main() -> ! {
+    let dp = esp32::Peripherals::take().unwrap();
+
+    let mut timg0 = dp.TIMG0;
+    let mut timg1 = dp.TIMG1;
+
+    // (https://github.com/espressif/openocd-esp32/blob/97ba3a6bb9eaa898d91df923bbedddfeaaaf28c9/src/target/esp32.c#L431)
+    // openocd disables the watchdog timers on halt
+    // we will do it manually on startup
+    disable_timg_wdts(&mut timg0, &mut timg1);
+
+    let (mut dport, dport_clock_control) = dp.DPORT.split();
+
+    let clkcntrl = esp32_hal::clock_control::ClockControl::new(
+        dp.RTCCNTL,
+        dp.APB_CTRL,
+        dport_clock_control,
+        esp32_hal::clock_control::XTAL_FREQUENCY_AUTO,
+    )
+    .unwrap();
+
+    let (clkcntrl_config, mut watchdog) = clkcntrl.freeze().unwrap();
+    watchdog.disable();
+
+    let mut serial = Serial::uart0(
+        dp.UART0,
+        (NoTx, NoRx),
+        Config::default(),
+        clkcntrl_config,
+        &mut dport,
+    )
+    .unwrap();
+    serial.change_baudrate(115200).unwrap();
+
+    let boot_count = unsafe {
+        BOOT_COUNT += 1;
+        BOOT_COUNT
+    };
+
+    writeln!(serial, "\n\nESP32 Started\n\n").unwrap();
+    writeln!(serial, "Boot count: {}", boot_count).unwrap();
+    writeln!(serial, "Reset reason: {:?}", reset::reset_reason(Core::PRO)).unwrap();
+    writeln!(serial, "Wakeup cause: {:?}", sleep::wakeup_cause()).unwrap();
+
+    let pins = dp.GPIO.split();
+
+    // the boot button on most development boards
+    let button = pins.gpio0.into_rtc_pull_up_input();
+    enable_ext0_wakeup(&button, WakeupLevel::Low);
+
+    writeln!(serial, "Entering deep sleep for 10s or until button press").unwrap();
+    // allow the transmission to finish
+    esp32_hal::clock_control::sleep(10.ms());
+
+    sleep::deep_sleep(
+        sleep::config::Config::default().timer_wakeup(10.s()),
+        clkcntrl_config,
+    );
+}
+
+const WDT_WKEY_VALUE: u32 = 0x50D83AA1;
+
+fn disable_timg_wdts(timg0: &mut esp32::TIMG0, timg1: &mut esp32::TIMG1) {
+    timg0
+        .wdtwprotect
+        .write(|w| unsafe { w.bits(WDT_WKEY_VALUE) });
+    timg1
+        .wdtwprotect
+        .write(|w| unsafe { w.bits(WDT_WKEY_VALUE) });
+
+    timg0.wdtconfig0.write(|w| unsafe { w.bits(0x0) });
+    timg1.wdtconfig0.write(|w| unsafe { w.bits(0x0) });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    dprintln!("\n\n*** {:?}", info);
+    loop {}
+}
@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use esp32_hal::prelude::*;
+
+use esp32_hal::dport::Split;
+use esp32_hal::dprintln;
+use esp32_hal::gpio::{self, Event};
+use esp32_hal::interrupt::Interrupt;
+use esp32_hal::serial::{config::Config, NoRx, NoTx, Serial};
+
+static PRESSED: AtomicUsize = AtomicUsize::new(0);
+
+#[interrupt]
+fn GPIO_INTR() {
+    gpio::handle_interrupt();
+}
+
+fn button_pressed() {
+    PRESSED.fetch_add(1, Ordering::Relaxed);
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = esp32::Peripherals::take().unwrap();
+
+    let mut timg0 = dp.TIMG0;
+    let mut timg1 = dp.TIMG1;
+
+    // (https://github.com/espressif/openocd-esp32/blob/97ba3a6bb9eaa898d91df923bbedddfeaaaf28c9/src/target/esp32.c#L431)
+    // openocd disables the watchdog timers on halt
+    // we will do it manually on startup
+    disable_timg_wdts(&mut timg0, &mut timg1);
+
+    let (mut dport, dport_clock_control) = dp.DPORT.split();
+
+    let clkcntrl = esp32_hal::clock_control::ClockControl::new(
+        dp.RTCCNTL,
+        dp.APB_CTRL,
+        dport_clock_control,
+        esp32_hal::clock_control::XTAL_FREQUENCY_AUTO,
+    )
+    .unwrap();
+
+    let (clkcntrl_config, mut watchdog) = clkcntrl.freeze().unwrap();
+    watchdog.disable();
+
+    let mut serial = Serial::uart0(
+        dp.UART0,
+        (NoTx, NoRx),
+        Config::default(),
+        clkcntrl_config,
+        &mut dport,
+    )
+    .unwrap();
+    serial.change_baudrate(115200).unwrap();
+
+    writeln!(serial, "\n\nESP32 Started\n\n").unwrap();
+
+    let pins = dp.GPIO.split();
+
+    // the boot button on most development boards
+    let mut button = pins.gpio0.into_pull_up_input();
+    button.set_interrupt_handler(Some(button_pressed));
+    button.listen(Event::FallingEdge);
+
+    interrupt::enable(Interrupt::GPIO_INTR).unwrap();
+
+    let mut last = 0;
+    loop {
+        let pressed = PRESSED.load(Ordering::Relaxed);
+        if pressed != last {
+            writeln!(serial, "Button pressed {} times", pressed).unwrap();
+            last = pressed;
+        }
+    }
+}
+
+const WDT_WKEY_VALUE: u32 = 0x50D83AA1;
+
+fn disable_timg_wdts(timg0: &mut esp32::TIMG0, timg1: &mut esp32::TIMG1) {
+    timg0
+        .wdtwprotect
+        .write(|w| unsafe { w.bits(WDT_WKEY_VALUE) });
+    timg1
+        .wdtwprotect
+        .write(|w| unsafe { w.bits(WDT_WKEY_VALUE) });
+
+    timg0.wdtconfig0.write(|w| unsafe { w.bits(0x0) });
+    timg1.wdtconfig0.write(|w| unsafe { w.bits(0x0) });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    dprintln!("\n\n*** {:?}", info);
+    loop {}
+}
@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use esp32_hal::prelude::*;
+
+use embedded_hal::blocking::i2c::WriteRead;
+use esp32_hal::clock_control::sleep;
+use esp32_hal::dport::Split;
+use esp32_hal::dprintln;
+use esp32_hal::i2c::{self, I2c};
+use esp32_hal::serial::{config::Config, NoRx, NoTx, Serial};
+
+#[no_mangle]
+fn main() -> ! {
+    let dp = unsafe { esp32::Peripherals::steal() };
+
+    let mut timg0 = dp.TIMG0;
+    let mut timg1 = dp.TIMG1;
+
+    // (https://github.com/espressif/openocd-esp32/blob/97ba3a6bb9eaa898d91df923bbedddfeaaaf28c9/src/target/esp32.c#L431)
+    // openocd disables the watchdog timers on halt
+    // we will do it manually on startup
+    disable_timg_wdts(&mut timg0, &mut timg1);
+
+    let (mut dport, dport_clock_control) = dp.DPORT.split();
+
+    let clkcntrl = esp32_hal::clock_control::ClockControl::new(
+        dp.RTCCNTL,
+        dp.APB_CTRL,
+        dport_clock_control,
+        esp32_hal::clock_control::XTAL_FREQUENCY_AUTO,
+    )
+    .unwrap();
+
+    let (clkcntrl_config, mut watchdog) = clkcntrl.freeze().unwrap();
+    watchdog.disable();
+
+    let gpios = dp.GPIO.split();
+
+    let mut serial = Serial::uart0(
+        dp.UART0,
+        (NoTx, NoRx),
+        Config::default(),
+        clkcntrl_config,
+        &mut dport,
+    )
+    .unwrap();
+    serial.change_baudrate(115200).unwrap();
+
+    // SDA on GPIO21, SCL on GPIO22 (external pull-ups needed)
+    let sda = gpios.gpio21.into_open_drain_output();
+    let scl = gpios.gpio22.into_open_drain_output();
+
+    let mut i2c = I2c::i2c0(
+        dp.I2C0,
+        (sda, scl),
+        i2c::config::Config::default().fast_mode(),
+        clkcntrl_config,
+        &mut dport,
+    )
+    .unwrap();
+
+    writeln!(serial, "\n\nESP32 Started\n\n").unwrap();
+    writeln!(serial, "I2C frequency: {}", i2c.frequency()).unwrap();
+
+    // read the WHO_AM_I register of an MPU6050 type sensor
+    const ADDRESS: u8 = 0x68;
+    const WHO_AM_I: u8 = 0x75;
+
+    loop {
+        let mut data = [0u8; 1];
+        match i2c.write_read(ADDRESS, &[WHO_AM_I], &mut data) {
+            Ok(()) => writeln!(serial, "WHO_AM_I: {:#04x}", data[0]).unwrap(),
+            Err(err) => writeln!(serial, "Error: {:?}", err).unwrap(),
+        }
+
+        sleep(1.s());
+    }
+}
+
+const WDT_WKEY_VALUE: u32 = 0x50D83AA1;
+
+fn disable_timg_wdts(timg0: &mut esp32::TIMG0, timg1: &mut esp32::TIMG1) {
+    timg0
+        .wdtwprotect
+        .write(|w| unsafe { w.bits(WDT_WKEY_VALUE) });
+    timg1
+        .wdtwprotect
+        .write(|w| unsafe { w.bits(WDT_WKEY_VALUE) });
+
+    timg0.wdtconfig0.write(|w| unsafe { w.bits(0x0) });
+    timg1.wdtconfig0.write(|w| unsafe { w.bits(0x0) });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    dprintln!("\n\n*** {:?}", info);
+    loop {}
+}
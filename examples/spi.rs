@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use esp32_hal::prelude::*;
+
+use embedded_hal::blocking::spi::Transfer;
+use esp32_hal::clock_control::sleep;
+use esp32_hal::dport::Split;
+use esp32_hal::dprintln;
+use esp32_hal::serial::{config::Config, NoRx, NoTx, Serial};
+use esp32_hal::spi::{self, Spi};
+
+#[no_mangle]
+fn main() -> ! {
+    let dp = unsafe { esp32::Peripherals::steal() };
+
+    let mut timg0 = dp.TIMG0;
+    let mut timg1 = dp.TIMG1;
+
+    // (https://github.com/espressif/openocd-esp32/blob/97ba3a6bb9eaa898d91df923bbedddfeaaaf28c9/src/target/esp32.c#L431)
+    // openocd disables the watchdog timers on halt
+    // we will do it manually on startup
+    disable_timg_wdts(&mut timg0, &mut timg1);
+
+    let (mut dport, dport_clock_control) = dp.DPORT.split();
+
+    let clkcntrl = esp32_hal::clock_control::ClockControl::new(
+        dp.RTCCNTL,
+        dp.APB_CTRL,
+        dport_clock_control,
+        esp32_hal::clock_control::XTAL_FREQUENCY_AUTO,
+    )
+    .unwrap();
+
+    let (clkcntrl_config, mut watchdog) = clkcntrl.freeze().unwrap();
+    watchdog.disable();
+
+    let gpios = dp.GPIO.split();
+
+    let mut serial = Serial::uart0(
+        dp.UART0,
+        (NoTx, NoRx),
+        Config::default(),
+        clkcntrl_config,
+        &mut dport,
+    )
+    .unwrap();
+    serial.change_baudrate(115200).unwrap();
+
+    // connect MOSI (GPIO23) to MISO (GPIO19) to receive the transmitted data
+    let sclk = gpios.gpio18.into_alternate_2();
+    let mosi = gpios.gpio23.into_alternate_2();
+    let miso = gpios.gpio19.into_alternate_2();
+    let cs = gpios.gpio5.into_alternate_2();
+
+    let mut spi = Spi::spi3(
+        dp.SPI3,
+        (sclk, mosi, miso, cs),
+        spi::config::Config::default()
+            .baudrate(1.MHz().into())
+            .mode(spi::config::MODE_0),
+        clkcntrl_config,
+        &mut dport,
+    )
+    .unwrap();
+
+    writeln!(serial, "\n\nESP32 Started\n\n").unwrap();
+    writeln!(serial, "SPI frequency: {}", spi.baudrate()).unwrap();
+
+    let mut count: u8 = 0;
+    loop {
+        let mut data = [count, count.wrapping_add(1), count.wrapping_add(2)];
+        spi.transfer(&mut data).unwrap();
+        writeln!(serial, "Received: {:?}", data).unwrap();
+
+        count = count.wrapping_add(1);
+        sleep(1.s());
+    }
+}
+
+const WDT_WKEY_VALUE: u32 = 0x50D83AA1;
+
+fn disable_timg_wdts(timg0: &mut esp32::TIMG0, timg1: &mut esp32::TIMG1) {
+    timg0
+        .wdtwprotect
+        .write(|w| unsafe { w.bits(WDT_WKEY_VALUE) });
+    timg1
+        .wdtwprotect
+        .write(|w| unsafe { w.bits(WDT_WKEY_VALUE) });
+
+    timg0.wdtconfig0.write(|w| unsafe { w.bits(0x0) });
+    timg1.wdtconfig0.write(|w| unsafe { w.bits(0x0) });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    dprintln!("\n\n*** {:?}", info);
+    loop {}
+}
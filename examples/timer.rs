@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use esp32_hal::prelude::*;
+
+use esp32::TIMG1;
+use esp32_hal::dport::Split;
+use esp32_hal::dprintln;
+use esp32_hal::interrupt::{Interrupt, InterruptLevel};
+use esp32_hal::serial::{config::Config, NoRx, NoTx, Serial};
+use esp32_hal::timer::{Event, Timer, Timer1};
+use esp32_hal::Core::PRO;
+
+static TIMER1: spin::Mutex<Option<Timer<TIMG1, Timer1>>> = spin::Mutex::new(None);
+
+#[interrupt]
+fn TG1_T1_LEVEL_INTR() {
+    if let Some(timer) = TIMER1.lock().as_mut() {
+        timer.clear_interrupt();
+        // the alarm is disabled by hardware when triggered
+        timer.enable_alarm();
+    }
+    dprintln!("  Timer 1 interrupt");
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = esp32::Peripherals::take().unwrap();
+
+    let mut timg0 = dp.TIMG0;
+    let mut timg1 = dp.TIMG1;
+
+    // (https://github.com/espressif/openocd-esp32/blob/97ba3a6bb9eaa898d91df923bbedddfeaaaf28c9/src/target/esp32.c#L431)
+    // openocd disables the watchdog timers on halt
+    // we will do it manually on startup
+    disable_timg_wdts(&mut timg0, &mut timg1);
+
+    let (mut dport, dport_clock_control) = dp.DPORT.split();
+
+    let clkcntrl = esp32_hal::clock_control::ClockControl::new(
+        dp.RTCCNTL,
+        dp.APB_CTRL,
+        dport_clock_control,
+        esp32_hal::clock_control::XTAL_FREQUENCY_AUTO,
+    )
+    .unwrap();
+
+    let (clkcntrl_config, mut watchdog) = clkcntrl.freeze().unwrap();
+    watchdog.disable();
+
+    let mut serial = Serial::uart0(
+        dp.UART0,
+        (NoTx, NoRx),
+        Config::default(),
+        clkcntrl_config,
+        &mut dport,
+    )
+    .unwrap();
+    serial.change_baudrate(115200).unwrap();
+
+    writeln!(serial, "\n\nESP32 Started\n\n").unwrap();
+
+    let (mut timer0, mut timer1, mut watchdog1) = Timer::timg1(timg1, clkcntrl_config);
+
+    // reset the system when not fed within 5 seconds
+    watchdog1.start(5.s());
+
+    // timer 1: interrupt every 500ms using the alarm with autoreload
+    timer1.set_divider(80).unwrap();
+    let ticks = u32::from(timer1.frequency()) as u64 / 2;
+    timer1
+        .set_value(0)
+        .set_autoreload(true)
+        .set_alarm(ticks)
+        .enable()
+        .listen(Event::TimeOut);
+    *TIMER1.lock() = Some(timer1);
+
+    interrupt::enable_with_priority(PRO, Interrupt::TG1_T1_LEVEL_INTR, InterruptLevel(1)).unwrap();
+
+    // timer 0: periodic count down of 1 second
+    timer0.start(1.s());
+
+    let mut count = 0;
+    loop {
+        nb::block!(timer0.wait()).unwrap();
+        writeln!(serial, "Timer 0: {}", count).unwrap();
+        watchdog1.feed();
+        count += 1;
+    }
+}
+
+const WDT_WKEY_VALUE: u32 = 0x50D83AA1;
+
+fn disable_timg_wdts(timg0: &mut esp32::TIMG0, timg1: &mut esp32::TIMG1) {
+    timg0
+        .wdtwprotect
+        .write(|w| unsafe { w.bits(WDT_WKEY_VALUE) });
+    timg1
+        .wdtwprotect
+        .write(|w| unsafe { w.bits(WDT_WKEY_VALUE) });
+
+    timg0.wdtconfig0.write(|w| unsafe { w.bits(0x0) });
+    timg1.wdtconfig0.write(|w| unsafe { w.bits(0x0) });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    dprintln!("\n\n*** {:?}", info);
+    loop {}
+}
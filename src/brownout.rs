@@ -0,0 +1,229 @@
+//! Brown-out detector
+//!
+//! The brown-out detector monitors the supply voltage and triggers when it drops below the
+//! selected [Threshold](config::Threshold). On a brown-out either the chip is reset or the
+//! RTC_CORE_INTR interrupt is raised, optionally powering down the RF circuits to reduce the
+//! load on the supply.
+//!
+//! The brown-out interrupt is handled by [handle_interrupt], which has to be called from the
+//! RTC_CORE_INTR interrupt handler defined by the application. It clears the brown-out interrupt
+//! and calls the handler set via [set_interrupt_handler]. Other interrupts sharing RTC_CORE_INTR
+//! (e.g. of the RTC watchdog or touch sensor) are left to the application. The interrupt needs
+//! to be enabled via [interrupt::enable](crate::interrupt::enable) or
+//! [interrupt::enable_with_priority](crate::interrupt::enable_with_priority).
+//!
+//! The handler runs with the supply already below the threshold, so it should only do the
+//! minimum required to save state before the power is lost. The brown-out interrupt is
+//! disabled after it triggered and is rearmed by calling [enable] again.
+//!
+//! # Example
+//! ```
+//! #[interrupt]
+//! fn RTC_CORE_INTR() {
+//!     brownout::handle_interrupt();
+//! }
+//!
+//! fn brown_out() {
+//!     // flush state to flash or RTC memory
+//! }
+//!
+//! brownout::set_interrupt_handler(Some(brown_out));
+//! brownout::enable(
+//!     brownout::config::Config::default()
+//!         .threshold(brownout::config::Threshold::V2_67)
+//!         .action(brownout::config::Action::Interrupt),
+//! );
+//! interrupt::enable(Interrupt::RTC_CORE_INTR).unwrap();
+//! ```
+
+use crate::esp32::RTCCNTL;
+
+// Bits of the RTC_CNTL_BROWN_OUT_REG register
+const BROWN_OUT_CLOSE_FLASH_ENA: u32 = 1 << 14;
+const BROWN_OUT_PD_RF_ENA: u32 = 1 << 15;
+const BROWN_OUT_RST_WAIT_SHIFT: u32 = 16;
+const BROWN_OUT_RST_WAIT_MASK: u32 = 0x3ff << BROWN_OUT_RST_WAIT_SHIFT;
+const BROWN_OUT_RST_ENA: u32 = 1 << 26;
+const BROWN_OUT_THRES_SHIFT: u32 = 27;
+const BROWN_OUT_ENA: u32 = 1 << 30;
+const BROWN_OUT_DET: u32 = 1 << 31;
+
+// Bit of the RTC_CNTL_INT_ENA_REG, RTC_CNTL_INT_ST_REG and RTC_CNTL_INT_CLR_REG registers
+const INT_BROWN_OUT: u32 = 1 << 7;
+
+pub mod config {
+    /// Supply voltage below which the brown-out detector triggers
+    ///
+    /// The actual voltages vary by about ±0.05V.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum Threshold {
+        /// 2.43V
+        V2_43,
+        /// 2.48V
+        V2_48,
+        /// 2.58V
+        V2_58,
+        /// 2.62V
+        V2_62,
+        /// 2.67V
+        V2_67,
+        /// 2.70V
+        V2_70,
+        /// 2.77V
+        V2_77,
+        /// 2.80V
+        V2_80,
+    }
+
+    impl From<Threshold> for u32 {
+        fn from(threshold: Threshold) -> u32 {
+            match threshold {
+                Threshold::V2_43 => 0,
+                Threshold::V2_48 => 1,
+                Threshold::V2_58 => 2,
+                Threshold::V2_62 => 3,
+                Threshold::V2_67 => 4,
+                Threshold::V2_70 => 5,
+                Threshold::V2_77 => 6,
+                Threshold::V2_80 => 7,
+            }
+        }
+    }
+
+    /// Action taken on a brown-out
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum Action {
+        /// Raise the RTC_CORE_INTR interrupt
+        Interrupt,
+        /// Reset the chip, after which the reset reason is
+        /// [ResetReason::BrownOut](crate::reset::ResetReason::BrownOut)
+        Reset,
+    }
+
+    /// Brown-out detector configuration
+    #[derive(Debug, Copy, Clone)]
+    pub struct Config {
+        pub threshold: Threshold,
+        pub action: Action,
+        pub power_down_rf: bool,
+        pub close_flash: bool,
+    }
+
+    impl Config {
+        /// Set the threshold voltage
+        pub fn threshold(mut self, threshold: Threshold) -> Self {
+            self.threshold = threshold;
+            self
+        }
+
+        /// Set the action taken on a brown-out
+        pub fn action(mut self, action: Action) -> Self {
+            self.action = action;
+            self
+        }
+
+        /// Power down the RF circuits on a brown-out
+        pub fn power_down_rf(mut self, power_down_rf: bool) -> Self {
+            self.power_down_rf = power_down_rf;
+            self
+        }
+
+        /// Stop flash accesses before resetting the chip on a brown-out
+        pub fn close_flash(mut self, close_flash: bool) -> Self {
+            self.close_flash = close_flash;
+            self
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Config {
+            Config {
+                threshold: Threshold::V2_43,
+                action: Action::Reset,
+                power_down_rf: true,
+                close_flash: true,
+            }
+        }
+    }
+}
+
+/// Handler called from [handle_interrupt] on a brown-out
+pub type InterruptHandler = fn();
+
+static HANDLER: spin::Mutex<Option<InterruptHandler>> = spin::Mutex::new(None);
+
+/// Enable the brown-out detector with the given configuration
+pub fn enable(config: config::Config) {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    let mut bits = BROWN_OUT_ENA
+        | u32::from(config.threshold) << BROWN_OUT_THRES_SHIFT
+        | BROWN_OUT_RST_WAIT_MASK;
+    if config.action == config::Action::Reset {
+        bits |= BROWN_OUT_RST_ENA;
+    }
+    if config.power_down_rf {
+        bits |= BROWN_OUT_PD_RF_ENA;
+    }
+    if config.close_flash {
+        bits |= BROWN_OUT_CLOSE_FLASH_ENA;
+    }
+
+    xtensa_lx6_rt::interrupt::free(|_| {
+        rtccntl.brown_out.write(|w| unsafe { w.bits(bits) });
+
+        rtccntl.int_clr.write(|w| unsafe { w.bits(INT_BROWN_OUT) });
+        rtccntl.int_ena.modify(|r, w| unsafe {
+            match config.action {
+                config::Action::Interrupt => w.bits(r.bits() | INT_BROWN_OUT),
+                config::Action::Reset => w.bits(r.bits() & !INT_BROWN_OUT),
+            }
+        });
+    });
+}
+
+/// Disable the brown-out detector
+pub fn disable() {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    xtensa_lx6_rt::interrupt::free(|_| {
+        rtccntl
+            .int_ena
+            .modify(|r, w| unsafe { w.bits(r.bits() & !INT_BROWN_OUT) });
+        rtccntl.brown_out.write(|w| unsafe { w.bits(0) });
+        rtccntl.int_clr.write(|w| unsafe { w.bits(INT_BROWN_OUT) });
+    });
+}
+
+/// Return true if the supply voltage is currently below the threshold
+pub fn is_detected() -> bool {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+    rtccntl.brown_out.read().bits() & BROWN_OUT_DET != 0
+}
+
+/// Set or remove the handler called when a brown-out is detected
+pub fn set_interrupt_handler(handler: Option<InterruptHandler>) {
+    xtensa_lx6_rt::interrupt::free(|_| *HANDLER.lock() = handler);
+}
+
+/// Handle the brown-out interrupt
+///
+/// Needs to be called from the RTC_CORE_INTR interrupt handler. Does nothing if the brown-out
+/// interrupt is not set.
+pub fn handle_interrupt() {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    if rtccntl.int_st.read().bits() & INT_BROWN_OUT != 0 {
+        // disable the interrupt as it stays set while the supply is below the threshold
+        rtccntl
+            .int_ena
+            .modify(|r, w| unsafe { w.bits(r.bits() & !INT_BROWN_OUT) });
+        rtccntl.int_clr.write(|w| unsafe { w.bits(INT_BROWN_OUT) });
+
+        // copy the handler, so the lock is released before calling it
+        let handler = *HANDLER.lock();
+        if let Some(handler) = handler {
+            handler();
+        }
+    }
+}
@@ -1,9 +1,13 @@
 //! Dynamic Frequency Switching control
 //!
-//! #TODO
-//! - Sleep functionality/Awake lock
+//! When light sleep is enabled via
+//! [ClockControl::set_light_sleep](super::ClockControl::set_light_sleep),
+//! [ClockControlConfig::idle](super::ClockControlConfig::idle) enters light sleep as long as no
+//! [LockAwake] is held.
 
 use super::Error;
+use crate::sleep::{config::Config as SleepConfig, WakeupCause};
+use crate::units::*;
 
 /// maximum number of callbacks
 pub const MAX_CALLBACKS: usize = 10;
@@ -60,6 +64,16 @@ pub struct LockAwake {}
 /// This structure is created by the lock_plld2 method on ClockControlConfig
 pub struct LockPllD2 {}
 
+/// A RAII implementation of a "scoped lock" for the 8MHz oscillator.
+/// When this structure is dropped (falls out of scope), the lock will be unlocked.
+/// This structure is created by the lock_rtc8m method on ClockControlConfig
+pub struct LockRTC8M {}
+
+/// A RAII implementation of a "scoped lock" for the 8MHz/256 clock.
+/// When this structure is dropped (falls out of scope), the lock will be unlocked.
+/// This structure is created by the lock_rtc8md256 method on ClockControlConfig
+pub struct LockRTC8MD256 {}
+
 /// Drop of the RAII to unlock the CPU frequency
 impl<'a> Drop for LockCPU {
     fn drop(&mut self) {
@@ -102,6 +116,24 @@ impl<'a> Drop for LockPllD2 {
     }
 }
 
+/// Drop of the RAII to unlock the 8MHz oscillator
+impl<'a> Drop for LockRTC8M {
+    fn drop(&mut self) {
+        unsafe {
+            super::CLOCK_CONTROL.as_mut().unwrap().unlock_rtc8m();
+        }
+    }
+}
+
+/// Drop of the RAII to unlock the 8MHz/256 clock
+impl<'a> Drop for LockRTC8MD256 {
+    fn drop(&mut self) {
+        unsafe {
+            super::CLOCK_CONTROL.as_mut().unwrap().unlock_rtc8md256();
+        }
+    }
+}
+
 impl<'a> super::ClockControl {
     /// call all the callbacks
     fn do_callbacks(&self) {
@@ -190,21 +222,72 @@ impl<'a> super::ClockControl {
             data.awake += 1;
         });
 
-        // TODO: implement actual locking
         LockAwake {}
     }
 
     /// unlock from the awake state
+    ///
+    /// Light sleep is entered on the next call to idle once the count drops to zero.
     fn unlock_awake(&'a mut self) {
         xtensa_lx6_rt::interrupt::free(|_| {
             let mut data = DFS_MUTEX.lock();
             data.awake -= 1;
-
-            // TODO: implement actual unlocking
-            unimplemented!();
         });
     }
 
+    /// enter light sleep if enabled and not locked in the awake state
+    pub(crate) fn idle(&'a mut self, timeout: Option<MilliSeconds>) -> Option<WakeupCause> {
+        xtensa_lx6_rt::interrupt::free(|_| {
+            let data = DFS_MUTEX.lock();
+
+            let mut config = self.light_sleep_config?;
+            if data.awake > 0 {
+                return None;
+            }
+            // wake up at the earlier of the timeout and the configured timer wakeup
+            config.timer = match (timeout, config.timer) {
+                (Some(timeout), Some(timer)) => Some(timeout.min(timer)),
+                (timeout, timer) => timeout.or(timer),
+            };
+
+            Some(self.enter_light_sleep(&data, &config))
+        })
+    }
+
+    /// enter light sleep, independent of the awake locks
+    pub(crate) fn light_sleep(&'a mut self, config: &SleepConfig) -> WakeupCause {
+        xtensa_lx6_rt::interrupt::free(|_| {
+            let data = DFS_MUTEX.lock();
+            self.enter_light_sleep(&data, config)
+        })
+    }
+
+    /// enter light sleep and restore the CPU frequency on wakeup
+    fn enter_light_sleep(&'a mut self, data: &Locks, config: &SleepConfig) -> WakeupCause {
+        // the PLL and 8MHz oscillator are powered down during sleep
+        self.set_cpu_frequency(super::CPUSource::Xtal, self.xtal_frequency, data.pll_d2 > 0)
+            .unwrap();
+
+        let sleep_start = crate::rtc::counter();
+        let cause = crate::sleep::light_sleep_start(config, self.slow_rtc_frequency);
+
+        // the system time is stopped during sleep
+        crate::time::advance(crate::rtc::counter() - sleep_start, self.slow_rtc_frequency);
+
+        if data.cpu > 0
+            && (data.apb == 0 || self.cpu_frequency_locked > self.cpu_frequency_apb_locked)
+        {
+            self.set_cpu_frequency_locked(data.pll_d2 > 0).unwrap();
+        } else if data.apb > 0 {
+            self.set_cpu_frequency_apb_locked(data.pll_d2 > 0).unwrap();
+        } else {
+            self.set_cpu_frequency_default(data.pll_d2 > 0).unwrap();
+        }
+        self.do_callbacks();
+
+        cause
+    }
+
     /// lock the PLL/2 frequency
     pub(crate) fn lock_plld2(&'a mut self) -> LockPllD2 {
         xtensa_lx6_rt::interrupt::free(|_| {
@@ -232,6 +315,28 @@ impl<'a> super::ClockControl {
         });
     }
 
+    /// lock the 8MHz oscillator in the enabled state
+    pub(crate) fn lock_rtc8m(&'a mut self) -> LockRTC8M {
+        xtensa_lx6_rt::interrupt::free(|_| self.rtc8m_acquire());
+        LockRTC8M {}
+    }
+
+    /// unlock the 8MHz oscillator, disabling it if it has no other users
+    fn unlock_rtc8m(&'a mut self) {
+        xtensa_lx6_rt::interrupt::free(|_| self.rtc8m_release());
+    }
+
+    /// lock the 8MHz/256 clock in the enabled state
+    pub(crate) fn lock_rtc8md256(&'a mut self) -> LockRTC8MD256 {
+        xtensa_lx6_rt::interrupt::free(|_| self.rtc8md256_acquire());
+        LockRTC8MD256 {}
+    }
+
+    /// unlock the 8MHz/256 clock, disabling it if it has no other users
+    fn unlock_rtc8md256(&'a mut self) {
+        xtensa_lx6_rt::interrupt::free(|_| self.rtc8md256_release());
+    }
+
     /// Add callback which will be called when clock speeds are changed.
     ///
     /// NOTE: these callbacks are called in an interrupt free environment,
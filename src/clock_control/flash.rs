@@ -0,0 +1,136 @@
+//! Flash clock and mode detection and control
+//!
+//! The flash and the external RAM (PSRAM) are accessed via the cache over SPI0, while the ROM
+//! flash functions use SPI1. The clocks of both are derived from the APB clock.
+//!
+//! The flash mode and frequency are stored by esptool in the header of the bootloader image (at
+//! 0x1000 in flash). The bootloader applies them to the SPI0 and SPI1 registers before starting
+//! the application, so the registers are read instead of the header: reading the header itself
+//! would need the ROM flash functions with the cache disabled, and the registers also reflect
+//! any adjustments made by the bootloader.
+//!
+//! The frequency and mode are detected from the registers during initialization. When the APB
+//! frequency changes due to Dynamic Frequency Switching, the dividers are recalculated to keep
+//! the flash clock as close as possible to, but never above, the detected frequency.
+//!
+//! The PSRAM clock and dummy cycles are not adjusted, therefore the APB frequency cannot be
+//! changed by Dynamic Frequency Switching while external RAM is in use (see
+//! [set_cpu_frequencies](super::ClockControl::set_cpu_frequencies)).
+
+use crate::esp32::{SPI0, SPI1};
+use crate::prelude::*;
+
+// Bits of the SPI_CLOCK_REG register
+const SPI_CLK_EQU_SYSCLK: u32 = 1 << 31;
+const SPI_CLKDIV_PRE_SHIFT: u32 = 18;
+const SPI_CLKDIV_PRE_MASK: u32 = 0x1fff;
+const SPI_CLKCNT_N_SHIFT: u32 = 12;
+const SPI_CLKCNT_H_SHIFT: u32 = 6;
+const SPI_CLKCNT_L_SHIFT: u32 = 0;
+const SPI_CLKCNT_MASK: u32 = 0x3f;
+
+// Bits of the SPI_CTRL_REG register
+const SPI_FREAD_QIO: u32 = 1 << 24;
+const SPI_FREAD_DIO: u32 = 1 << 23;
+const SPI_FREAD_QUAD: u32 = 1 << 20;
+const SPI_FREAD_DUAL: u32 = 1 << 14;
+const SPI_FASTRD_MODE: u32 = 1 << 13;
+
+// State bits of the SPI_EXT2_REG register
+const SPI_ST_MASK: u32 = 0x7;
+
+// Maximum divider without using the pre-divider
+const DIVIDER_MAX: u32 = SPI_CLKCNT_MASK + 1;
+
+/// Flash IO mode
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FlashMode {
+    /// Address and data via 4 lines
+    QIO,
+    /// Data via 4 lines
+    QOUT,
+    /// Address and data via 2 lines
+    DIO,
+    /// Data via 2 lines
+    DOUT,
+    /// Single line with fast read command
+    FastRead,
+    /// Single line with slow read command
+    SlowRead,
+}
+
+/// Read the flash IO mode used by the cache
+pub(super) fn mode() -> FlashMode {
+    let spi0 = unsafe { &*SPI0::ptr() };
+    let ctrl = spi0.ctrl.read().bits();
+
+    if ctrl & SPI_FREAD_QIO != 0 {
+        FlashMode::QIO
+    } else if ctrl & SPI_FREAD_QUAD != 0 {
+        FlashMode::QOUT
+    } else if ctrl & SPI_FREAD_DIO != 0 {
+        FlashMode::DIO
+    } else if ctrl & SPI_FREAD_DUAL != 0 {
+        FlashMode::DOUT
+    } else if ctrl & SPI_FASTRD_MODE != 0 {
+        FlashMode::FastRead
+    } else {
+        FlashMode::SlowRead
+    }
+}
+
+/// Read the divider of the flash clock used by the cache
+pub(super) fn divider() -> u32 {
+    let spi0 = unsafe { &*SPI0::ptr() };
+    let clock = spi0.clock.read().bits();
+
+    if clock & SPI_CLK_EQU_SYSCLK != 0 {
+        1
+    } else {
+        (((clock >> SPI_CLKDIV_PRE_SHIFT) & SPI_CLKDIV_PRE_MASK) + 1)
+            * (((clock >> SPI_CLKCNT_N_SHIFT) & SPI_CLKCNT_MASK) + 1)
+    }
+}
+
+/// Calculate the smallest divider keeping the flash clock at or below the maximum frequency
+pub(super) fn divider_for(apb_frequency: Hertz, max_frequency: Hertz) -> u32 {
+    let apb_frequency = u32::from(apb_frequency);
+    let max_frequency = u32::from(max_frequency);
+
+    if max_frequency == 0 {
+        return DIVIDER_MAX;
+    }
+
+    let divider = (apb_frequency + max_frequency - 1) / max_frequency;
+    if divider < 1 {
+        1
+    } else if divider > DIVIDER_MAX {
+        DIVIDER_MAX
+    } else {
+        divider
+    }
+}
+
+/// Set the divider of the flash clock for both the cache (SPI0) and the ROM functions (SPI1)
+///
+/// This function is placed in RAM, as the flash cannot be accessed while the clock changes.
+#[ram]
+pub(super) fn set_divider(divider: u32) {
+    let spi0 = unsafe { &*SPI0::ptr() };
+    let spi1 = unsafe { &*SPI1::ptr() };
+
+    let bits = if divider <= 1 {
+        SPI_CLK_EQU_SYSCLK
+    } else {
+        ((divider - 1) << SPI_CLKCNT_N_SHIFT)
+            | ((divider / 2 - 1) << SPI_CLKCNT_H_SHIFT)
+            | ((divider - 1) << SPI_CLKCNT_L_SHIFT)
+    };
+
+    // wait for any ongoing transfer to finish
+    while spi0.ext2.read().bits() & SPI_ST_MASK != 0 {}
+    while spi1.ext2.read().bits() & SPI_ST_MASK != 0 {}
+
+    spi0.clock.write(|w| unsafe { w.bits(bits) });
+    spi1.clock.write(|w| unsafe { w.bits(bits) });
+}
@@ -4,17 +4,10 @@
 //! Also controls RTC watchdog timer.
 //!
 //! # TODO
-//! - Auto detect flash frequency
 //! - Low Power Clock (LPClock, regs: DPORT_BT_LPCK_DIV_FRAC_REG,DPORT_BT_LPCK_DIV_INT_REG)
 //! - LED clock selection in ledc peripheral
-//! - 8M and 8MD256 enable/disable
-//! - 150kHz enable/disable
-//! - APLL support
-//! - Implement light sleep
-//! - 32kHz Xtal support
-//! - Allow 8.5MHz clock to be tuned
-//! - Global RTC clock reading
-//! - Automatic enabling/disabling of 8MHz source (when not in use for rtc_fast_clk or cpu frequency)
+//! - 150kHz enable/disable: no power down control of the 150kHz oscillator is known, so it is
+//!     kept running and is not reference counted like the 8MHz oscillator
 
 use crate::prelude::*;
 use core::fmt;
@@ -26,6 +19,7 @@ use esp32::{APB_CTRL, RTCCNTL, TIMG0};
 
 pub mod cpu;
 pub mod dfs;
+pub mod flash;
 mod pll;
 pub mod watchdog;
 
@@ -44,6 +38,11 @@ const CPU_FREQ_MAX_DEFAULT: Hertz = Hertz(240_000_000);
 const CPU_SOURCE_APB_LOCKED_DEFAULT: CPUSource = CPUSource::PLL;
 const CPU_FREQ_APB_DEFAULT: Hertz = Hertz(80_000_000);
 
+// number of cycles the 32kHz Xtal pins are driven to help the crystal start up
+const XTAL32K_BOOTSTRAP_CYCLES: u32 = 5;
+// number of attempts to start the 32kHz Xtal before falling back to the 150kHz oscillator
+const XTAL32K_STARTUP_ATTEMPTS: u32 = 3;
+
 /////////////////////////////////
 // Non-configurable constants
 //
@@ -69,8 +68,17 @@ const XTAL_FREQUENCY_40M_THRESHOLD: Hertz = Hertz(33_000_000);
 const XTAL_FREQUENCY_26M_THRESHOLD: Hertz = Hertz(24_500_000);
 const XTAL_FREQUENCY_24M_THRESHOLD: Hertz = Hertz(20_000_000);
 
-// Xtal 32kHz frequency
+// Xtal 32kHz frequency and allowed deviation to consider the Xtal running
 const XTAL32K_FREQUENCY: Hertz = Hertz(32_768);
+const XTAL32K_FREQUENCY_TOLERANCE: Hertz = Hertz(1_000);
+
+// 32kHz Xtal current, resistance and bias settings during normal operation and bootstrap
+const XTAL32K_DAC: u8 = 1;
+const XTAL32K_DRES: u8 = 3;
+const XTAL32K_DBIAS: u8 = 0;
+const XTAL32K_BOOTSTRAP_DAC: u8 = 3;
+const XTAL32K_BOOTSTRAP_DRES: u8 = 3;
+const XTAL32K_BOOTSTRAP_DBIAS: u8 = 0;
 
 // minimum CPU frequency
 const CPU_FREQ_MIN: Hertz = Hertz(1_000);
@@ -86,6 +94,9 @@ const CPU_FREQ_240M: Hertz = Hertz(240_000_000);
 // standard APB frequency when using PLL
 const APB_FREQ_PLL: Hertz = Hertz(80_000_000);
 
+// flash frequency above which a higher digital voltage is required
+const FLASH_FREQ_40M: Hertz = Hertz(40_000_000);
+
 // default 8M frequency
 const RTC_FREQ_8M_DEFAULT: Hertz = Hertz(8_500_000); //With the default value of CK8M_DFREQ, 8M clock frequency is 8.5 MHz +/- 7%
 
@@ -100,6 +111,7 @@ const DELAY_FAST_CLK_SWITCH: MicroSeconds = MicroSeconds(3);
 const DELAY_SLOW_CLK_SWITCH: MicroSeconds = MicroSeconds(300);
 const DELAY_8M_ENABLE: MicroSeconds = MicroSeconds(50);
 const DELAY_DBIAS_RAISE: MicroSeconds = MicroSeconds(3);
+const DELAY_XTAL32K_STARTUP: MicroSeconds = MicroSeconds(100_000);
 
 // number of wait cycles when enabling 8MHz clock
 const CK8M_WAIT_DEFAULT: u8 = 20;
@@ -117,6 +129,12 @@ const CK8M_DFREQ_DEFAULT: u8 = 172;
 // Number of slow cycles to measure for Xtal frequency measurement
 const CYCLES_XTAL_CALIBRATION: u16 = 10;
 
+// Number of 32kHz Xtal cycles to measure to check if the Xtal is running
+const CYCLES_XTAL32K_CALIBRATION: u16 = 1000;
+
+// Number of 8MHz/256 cycles to measure per step when trimming the 8MHz oscillator
+const CYCLES_RTC8M_TRIM: u16 = 100;
+
 // The minimum APB frequency to guarantee proper ref clock (10MHz according to documentation)
 const MINIMUM_APB_FREQ_FOR_STABLE_REF_CLK: Hertz = Hertz(10_000_000);
 // The accuracy of the clock to guarantee proper ref clock (as denominator for fraction,
@@ -139,6 +157,9 @@ pub enum Error {
     InvalidRegisterValue,
     InvalidCore,
     CoreAlreadyRunning,
+    APLLInUse,
+    Xtal32kNotRunning,
+    ExternalRAMInUse,
 }
 
 /// CPU/APB/REF clock source
@@ -148,16 +169,21 @@ pub enum CPUSource {
     Xtal,
     /// PLL generated frequency from high frequency Xtal
     PLL,
-    /// PLL generated frequency from high frequency Xtal
+    /// Audio PLL generated frequency from high frequency Xtal
+    ///
+    /// The CPU runs at APLL/2 or APLL/4 and the APB at half the CPU frequency. The APLL needs to
+    /// be enabled via [set_apll_frequency](ClockControl::set_apll_frequency) before use.
     APLL,
     /// 8MHz internal oscillator
     RTC8M,
 }
 
 /// Slow RTC clock source
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum SlowRTCSource {
     /// 150kHz internal oscillator
+    ///
+    /// This oscillator is always kept running.
     RTC150k,
     /// Low frequency Xtal (32kHz)
     Xtal32k,
@@ -166,7 +192,7 @@ pub enum SlowRTCSource {
 }
 
 /// Fast RTC clock source
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum FastRTCSource {
     /// 8MHz internal oscillator
     RTC8M,
@@ -176,7 +202,6 @@ pub enum FastRTCSource {
 
 /// Slow RTC clock source
 #[derive(Debug, Copy, Clone)]
-#[allow(dead_code)]
 enum CalibrateRTCSource {
     /// Slow RTC Source
     SlowRTC,
@@ -251,6 +276,31 @@ impl<'a> ClockControlConfig {
         unsafe { CLOCK_CONTROL.as_ref().unwrap().apll_frequency }
     }
 
+    /// Enable the APLL and set it to the closest possible frequency, returning the actual
+    /// frequency
+    ///
+    /// The APLL can be used as clock for the I2S peripherals (CLKA_ENA bit of I2S_CLKM_CONF_REG)
+    /// to generate precise audio sample rates. Fails if the APLL is used as CPU source.
+    pub fn set_apll_frequency<T: Into<Hertz>>(&self, frequency: T) -> Result<Hertz, Error> {
+        let frequency = frequency.into();
+        xtensa_lx6_rt::interrupt::free(|_| unsafe {
+            CLOCK_CONTROL
+                .as_mut()
+                .unwrap()
+                .change_apll_frequency(Some(frequency))
+        })
+    }
+
+    /// Disable the APLL
+    ///
+    /// Fails if the APLL is used as CPU source.
+    pub fn disable_apll(&self) -> Result<(), Error> {
+        xtensa_lx6_rt::interrupt::free(|_| unsafe {
+            CLOCK_CONTROL.as_mut().unwrap().change_apll_frequency(None)
+        })?;
+        Ok(())
+    }
+
     /// The current PLL/2 frequency
     pub fn pll_d2_frequency(&self) -> Hertz {
         unsafe { CLOCK_CONTROL.as_ref().unwrap().pll_d2_frequency }
@@ -286,6 +336,37 @@ impl<'a> ClockControlConfig {
         unsafe { CLOCK_CONTROL.as_ref().unwrap().rtc_frequency }
     }
 
+    /// Calibrate the frequency of the current slow RTC clock against the Xtal
+    ///
+    /// See [ClockControl::calibrate_slow_rtc_frequency]. Interrupts are disabled during the
+    /// measurement.
+    pub fn calibrate_slow_rtc_frequency(&self, cycles: u16) -> Result<Hertz, Error> {
+        xtensa_lx6_rt::interrupt::free(|_| unsafe {
+            CLOCK_CONTROL
+                .as_mut()
+                .unwrap()
+                .calibrate_slow_rtc_frequency(cycles)
+        })
+    }
+
+    /// The current flash frequency
+    pub fn flash_frequency(&self) -> Hertz {
+        unsafe { CLOCK_CONTROL.as_ref().unwrap().flash_frequency }
+    }
+
+    /// The flash frequency as configured by the bootloader
+    ///
+    /// The flash frequency will never exceed this frequency, but will be lower if the APB
+    /// frequency does not allow it to be reached.
+    pub fn flash_frequency_max(&self) -> Hertz {
+        unsafe { CLOCK_CONTROL.as_ref().unwrap().flash_frequency_max }
+    }
+
+    /// The flash IO mode as configured by the bootloader
+    pub fn flash_mode(&self) -> flash::FlashMode {
+        unsafe { CLOCK_CONTROL.as_ref().unwrap().flash_mode }
+    }
+
     /// The current source for the CPU and APB frequencies
     pub fn cpu_source(&self) -> CPUSource {
         unsafe { CLOCK_CONTROL.as_ref().unwrap().cpu_source }
@@ -321,6 +402,16 @@ impl<'a> ClockControlConfig {
         unsafe { CLOCK_CONTROL.as_mut().unwrap().lock_plld2() }
     }
 
+    /// Obtain a RAII lock to keep the 8MHz oscillator enabled
+    pub fn lock_rtc8m(&self) -> dfs::LockRTC8M {
+        unsafe { CLOCK_CONTROL.as_mut().unwrap().lock_rtc8m() }
+    }
+
+    /// Obtain a RAII lock to keep the 8MHz/256 clock (and therefore the 8MHz oscillator) enabled
+    pub fn lock_rtc8md256(&self) -> dfs::LockRTC8MD256 {
+        unsafe { CLOCK_CONTROL.as_mut().unwrap().lock_rtc8md256() }
+    }
+
     /// Add callback which will be called when clock speeds are changed.
     ///
     /// NOTE: these callbacks are called in an interrupt free environment,
@@ -338,6 +429,23 @@ impl<'a> ClockControlConfig {
         unsafe { CLOCK_CONTROL.as_mut().unwrap().get_lock_count() }
     }
 
+    /// Enter light sleep if enabled and no awake lock is held, to be called when idle
+    ///
+    /// Sleeps until the timeout expires or one of the wakeup sources in the light sleep
+    /// configuration triggers. A timer wakeup in the configuration stays in effect, the earlier
+    /// of it and the timeout is used. Returns the wakeup cause or None if light sleep was not entered.
+    pub fn idle(&self, timeout: Option<MilliSeconds>) -> Option<crate::sleep::WakeupCause> {
+        unsafe { CLOCK_CONTROL.as_mut().unwrap().idle(timeout) }
+    }
+
+    /// Enter light sleep, independent of the awake locks
+    pub(crate) fn light_sleep(
+        &self,
+        config: crate::sleep::config::Config,
+    ) -> crate::sleep::WakeupCause {
+        unsafe { CLOCK_CONTROL.as_mut().unwrap().light_sleep(&config) }
+    }
+
     pub unsafe fn park_core(&mut self, core: crate::Core) {
         CLOCK_CONTROL.as_mut().unwrap().park_core(core)
     }
@@ -383,6 +491,8 @@ impl fmt::Debug for ClockControl {
             .field("rtc8md256_frequency", &self.rtc8md256_frequency)
             .field("rtc_frequency", &self.rtc_frequency)
             .field("pll_frequency", &self.pll_frequency)
+            .field("flash_frequency", &self.flash_frequency)
+            .field("flash_mode", &self.flash_mode)
             .field("cpu_source", &self.cpu_source)
             .field("slow_rtc_source", &self.slow_rtc_source)
             .field("fast_rtc_source", &self.fast_rtc_source)
@@ -405,8 +515,7 @@ pub struct ClockControl {
     cpu_source_locked: CPUSource,
     cpu_frequency_apb_locked: Hertz,
     cpu_source_apb_locked: CPUSource,
-    #[allow(dead_code)]
-    light_sleep_enabled: bool,
+    light_sleep_config: Option<crate::sleep::config::Config>,
 
     apb_frequency_apb_locked: Hertz,
 
@@ -427,9 +536,16 @@ pub struct ClockControl {
     rtc8m_frequency: Hertz,
     rtc8md256_frequency: Hertz,
 
+    rtc8m_users: usize,
+    rtc8md256_users: usize,
+
     rtc_frequency: Hertz,
     pll_frequency: Hertz,
 
+    flash_frequency: Hertz,
+    flash_frequency_max: Hertz,
+    flash_mode: flash::FlashMode,
+
     cpu_source: CPUSource,
     slow_rtc_source: SlowRTCSource,
     fast_rtc_source: FastRTCSource,
@@ -440,6 +556,9 @@ pub struct ClockControl {
 }
 
 /// Function only available once clock if frozen
+///
+/// This delay counts CPU cycles, so is only accurate if the CPU frequency does not change
+/// during the delay. See [Delay](crate::time::Delay) for a delay based on the system time.
 pub fn sleep<T: Into<NanoSeconds>>(time: T) {
     unsafe { CLOCK_CONTROL.as_ref().unwrap().delay(time) };
 }
@@ -463,7 +582,7 @@ impl ClockControl {
             cpu_source_locked: CPU_SOURCE_LOCKED_DEFAULT,
             cpu_frequency_apb_locked: CPU_FREQ_APB_DEFAULT,
             cpu_source_apb_locked: CPU_SOURCE_APB_LOCKED_DEFAULT,
-            light_sleep_enabled: false,
+            light_sleep_config: None,
 
             apb_frequency_apb_locked: APB_FREQ_PLL,
 
@@ -484,9 +603,16 @@ impl ClockControl {
             rtc8m_frequency: FREQ_OFF,
             rtc8md256_frequency: FREQ_OFF,
 
+            rtc8m_users: 0,
+            rtc8md256_users: 0,
+
             rtc_frequency: FREQ_OFF,
             pll_frequency: FREQ_OFF,
 
+            flash_frequency: FREQ_OFF,
+            flash_frequency_max: FREQ_OFF,
+            flash_mode: flash::FlashMode::SlowRead,
+
             cpu_source: CPUSource::Xtal,
             slow_rtc_source: SlowRTCSource::RTC150k,
             fast_rtc_source: FastRTCSource::XtalD4,
@@ -506,9 +632,19 @@ impl ClockControl {
         unsafe { CLOCK_CONTROL = Some(self) };
 
         let res = ClockControlConfig {};
+        crate::time::init(res)?;
         Ok((res, watchdog::WatchDog::new(res)))
     }
 
+    /// Enable (Some) or disable (None) light sleep when idle
+    ///
+    /// With light sleep enabled, [ClockControlConfig::idle] enters light sleep with the given
+    /// configuration as long as no awake lock is held.
+    pub fn set_light_sleep(&mut self, config: Option<crate::sleep::config::Config>) -> &mut Self {
+        self.light_sleep_config = config;
+        self
+    }
+
     // TODO: check what dig_clk8m and dig_clk8m_256 are used for
 
     /// Check if 8MHz oscillator is enabled
@@ -567,7 +703,6 @@ impl ClockControl {
     }
 
     /// Disable 8MHz/256
-    #[allow(dead_code)]
     fn rtc8md256_disable(&mut self) -> &mut Self {
         self.rtc_control
             .clk_conf
@@ -579,7 +714,6 @@ impl ClockControl {
     }
 
     /// Disable 8MHz oscillator (and therefore also 8MHz/256)
-    #[allow(dead_code)]
     fn rtc8m_disable(&mut self) -> &mut Self {
         self.rtc_control.clk_conf.modify(|_, w| {
             w.ck8m_force_pu()
@@ -609,6 +743,186 @@ impl ClockControl {
         self
     }
 
+    /// Add a user of the 8MHz oscillator, enabling it for the first user
+    fn rtc8m_acquire(&mut self) {
+        self.rtc8m_users += 1;
+        if self.rtc8m_users == 1 {
+            self.rtc8m_enable();
+        }
+    }
+
+    /// Remove a user of the 8MHz oscillator, disabling it when the last user is removed
+    fn rtc8m_release(&mut self) {
+        self.rtc8m_users -= 1;
+        if self.rtc8m_users == 0 {
+            self.rtc8m_disable();
+        }
+    }
+
+    /// Add a user of the 8MHz/256 clock, enabling it (and the 8MHz oscillator) for the first user
+    fn rtc8md256_acquire(&mut self) {
+        self.rtc8m_acquire();
+        self.rtc8md256_users += 1;
+        if self.rtc8md256_users == 1 {
+            self.rtc8md256_enable();
+        }
+    }
+
+    /// Remove a user of the 8MHz/256 clock, disabling it when the last user is removed
+    fn rtc8md256_release(&mut self) {
+        self.rtc8md256_users -= 1;
+        if self.rtc8md256_users == 0 {
+            self.rtc8md256_disable();
+        }
+        self.rtc8m_release();
+    }
+
+    /// Set the frequency setting of the 8MHz oscillator and measure the resulting frequency
+    fn measure_rtc8m_frequency(&mut self, dfreq: u8) -> Result<Hertz, Error> {
+        unsafe {
+            self.rtc_control
+                .clk_conf
+                .modify(|_, w| w.ck8m_dfreq().bits(dfreq))
+        };
+        self.delay(DELAY_8M_ENABLE);
+
+        Ok(self.measure_slow_frequency(CalibrateRTCSource::RTC8MD256, CYCLES_RTC8M_TRIM)? * 256)
+    }
+
+    /// Search the frequency setting of the 8MHz oscillator closest to the target frequency
+    fn search_rtc8m_dfreq(&mut self, target: Hertz) -> Result<(u8, Hertz), Error> {
+        let distance = |frequency: Hertz| {
+            let (frequency, target) = (u32::from(frequency), u32::from(target));
+            core::cmp::max(frequency, target) - core::cmp::min(frequency, target)
+        };
+
+        let low = self.measure_rtc8m_frequency(0)?;
+        let high = self.measure_rtc8m_frequency(255)?;
+        let increasing = high > low;
+
+        let mut best = if distance(low) <= distance(high) {
+            (0, low)
+        } else {
+            (255, high)
+        };
+
+        let (mut min, mut max) = (0u8, 255u8);
+        while min < max {
+            let dfreq = min + (max - min) / 2;
+            let frequency = self.measure_rtc8m_frequency(dfreq)?;
+
+            if distance(frequency) < distance(best.1) {
+                best = (dfreq, frequency);
+            }
+
+            if (frequency < target) == increasing {
+                min = dfreq + 1;
+            } else {
+                max = dfreq;
+            }
+        }
+
+        Ok(best)
+    }
+
+    /// Trim the 8MHz oscillator as close as possible to the given frequency
+    ///
+    /// The oscillator frequency setting is tuned while measuring the frequency against the Xtal
+    /// with the calibration unit. Returns the resulting frequency.
+    ///
+    /// The oscillator cannot be trimmed while it is (configured to be) used as CPU source, so
+    /// this needs to be called before selecting it in
+    /// [set_cpu_frequencies](ClockControl::set_cpu_frequencies).
+    pub fn trim_rtc8m_frequency<T: Into<Hertz>>(&mut self, frequency: T) -> Result<Hertz, Error> {
+        if self.cpu_source == CPUSource::RTC8M
+            || self.cpu_source_default == CPUSource::RTC8M
+            || self.cpu_source_locked == CPUSource::RTC8M
+            || self.cpu_source_apb_locked == CPUSource::RTC8M
+        {
+            return Err(Error::UnsupportedFreqConfig);
+        }
+
+        let dfreq_original = self.rtc_control.clk_conf.read().ck8m_dfreq().bits();
+
+        self.rtc8md256_acquire();
+        let result = self.search_rtc8m_dfreq(frequency.into());
+        let dfreq = match result {
+            Ok((dfreq, _)) => dfreq,
+            Err(_) => dfreq_original,
+        };
+        unsafe {
+            self.rtc_control
+                .clk_conf
+                .modify(|_, w| w.ck8m_dfreq().bits(dfreq))
+        };
+        self.delay(DELAY_8M_ENABLE);
+        self.rtc8md256_release();
+
+        let (_, frequency) = result?;
+
+        self.rtc8m_frequency_measured = frequency;
+        self.rtc8md256_frequency_measured = frequency / 256;
+        if self.rtc8m_users > 0 {
+            self.rtc8m_frequency = self.rtc8m_frequency_measured;
+        }
+        if self.rtc8md256_users > 0 {
+            self.rtc8md256_frequency = self.rtc8md256_frequency_measured;
+        }
+        if self.fast_rtc_source == FastRTCSource::RTC8M {
+            self.fast_rtc_frequency = self.rtc8m_frequency_measured;
+        }
+        if self.slow_rtc_source == SlowRTCSource::RTC8MD256 {
+            self.slow_rtc_frequency = self.rtc8md256_frequency_measured;
+        }
+
+        Ok(frequency)
+    }
+
+    /// Check if the 32kHz Xtal oscillator is enabled and running
+    pub fn is_xtal32k_enabled(&self) -> bool {
+        crate::gpio::is_xtal32k_powered_up() && self.xtal32k_frequency != FREQ_OFF
+    }
+
+    /// Start the 32kHz Xtal oscillator
+    ///
+    /// There is no direct way to check if the crystal oscillates, so its frequency is measured
+    /// against the Xtal. If it is not running, the crystal is bootstrapped and powered up again,
+    /// until the number of attempts is exhausted.
+    fn xtal32k_enable(&mut self) -> Result<Hertz, Error> {
+        // when already powered up (e.g. after deep sleep) check first before bootstrapping
+        let mut bootstrap = !crate::gpio::is_xtal32k_powered_up();
+
+        for _ in 0..XTAL32K_STARTUP_ATTEMPTS {
+            if bootstrap {
+                crate::gpio::xtal32k_bootstrap(
+                    XTAL32K_BOOTSTRAP_CYCLES,
+                    XTAL32K_BOOTSTRAP_DAC,
+                    XTAL32K_BOOTSTRAP_DRES,
+                    XTAL32K_BOOTSTRAP_DBIAS,
+                    |time| self.delay(time),
+                );
+                crate::gpio::xtal32k_enable(XTAL32K_DAC, XTAL32K_DRES, XTAL32K_DBIAS);
+                self.delay(DELAY_XTAL32K_STARTUP);
+            }
+            bootstrap = true;
+
+            if let Ok(frequency) =
+                self.measure_slow_frequency(CalibrateRTCSource::Xtal32k, CYCLES_XTAL32K_CALIBRATION)
+            {
+                if frequency + XTAL32K_FREQUENCY_TOLERANCE >= XTAL32K_FREQUENCY
+                    && frequency <= XTAL32K_FREQUENCY + XTAL32K_FREQUENCY_TOLERANCE
+                {
+                    self.xtal32k_frequency = frequency;
+                    return Ok(frequency);
+                }
+            }
+        }
+
+        crate::gpio::xtal32k_disable();
+        self.xtal32k_frequency = FREQ_OFF;
+        Err(Error::Xtal32kNotRunning)
+    }
+
     /// Function to calibrate clocks against each other.
     /// Returns the number of XTAL clock cycles within the number of slow clock cycles.
     /// Clock must already be enabled on entry to this routine
@@ -629,7 +943,8 @@ impl ClockControl {
             CalibrateRTCSource::Xtal32k => XTAL32K_FREQUENCY,
         };
 
-        let estimated_time = (Hertz(1_000_000) * (slow_cycles as u32) / slow_freq).us();
+        let estimated_time =
+            ((1_000_000 * slow_cycles as u64 / u32::from(slow_freq) as u64) as u32).us();
         let estimated_cycle_count = 2 * self.time_to_cpu_cycles(estimated_time);
 
         let max_cycle_count = 0x01FFFFFF; // bit 7:31 = 25 bits
@@ -707,10 +1022,49 @@ impl ClockControl {
     }
 
     /// Measure the frequency of one of the clock oscillators based on the Xtal frequency
-    fn measure_slow_frequency(&mut self, source: CalibrateRTCSource) -> Result<Hertz, Error> {
-        let ticks = self.measure_clock_ticks(source, CYCLES_XTAL_CALIBRATION)?;
+    fn measure_slow_frequency(
+        &mut self,
+        source: CalibrateRTCSource,
+        cycles: u16,
+    ) -> Result<Hertz, Error> {
+        let ticks = self.measure_clock_ticks(source, cycles)?;
+        if ticks == 0 {
+            return Err(Error::CalibrationTimeOut);
+        }
+
+        Ok(Hertz(
+            (u32::from(self.xtal_frequency) as u64 * cycles as u64 / ticks as u64) as u32,
+        ))
+    }
 
-        Ok(self.xtal_frequency * (CYCLES_XTAL_CALIBRATION as u32) / ticks)
+    /// Calibrate the frequency of the current slow RTC clock against the Xtal
+    ///
+    /// The measurement takes the given number of slow clock cycles (at most 32767), more cycles
+    /// give a more accurate result. The calibrated frequency is used for all timing based on the
+    /// slow RTC clock, like the RTC watchdog and the sleep timer.
+    pub fn calibrate_slow_rtc_frequency(&mut self, cycles: u16) -> Result<Hertz, Error> {
+        // measure the source directly, so the timeout matches its nominal frequency
+        let source = match self.slow_rtc_source {
+            SlowRTCSource::RTC150k => CalibrateRTCSource::SlowRTC,
+            SlowRTCSource::Xtal32k => CalibrateRTCSource::Xtal32k,
+            SlowRTCSource::RTC8MD256 => CalibrateRTCSource::RTC8MD256,
+        };
+        let frequency = self.measure_slow_frequency(source, cycles)?;
+
+        match self.slow_rtc_source {
+            SlowRTCSource::RTC150k => {
+                self.rtc_frequency_measured = frequency;
+                self.rtc_frequency = frequency;
+            }
+            SlowRTCSource::Xtal32k => self.xtal32k_frequency = frequency,
+            SlowRTCSource::RTC8MD256 => {
+                self.rtc8md256_frequency_measured = frequency;
+                self.rtc8md256_frequency = frequency;
+            }
+        }
+        self.slow_rtc_frequency = frequency;
+
+        Ok(frequency)
     }
 
     /// Initialize clock configuration
@@ -726,6 +1080,9 @@ impl ClockControl {
             self.xtal_frequency = xtal_frequency.into();
         }
 
+        // detect the flash configuration before the APB frequency is changed
+        self.detect_flash();
+
         // switch from pll to xtal (pll can still be enabled when previously in deep sleep)
         // xtal_frequency might be incorrect here, but by setting teh cpu to current xtal frequency
         // divider will be initialized to 1
@@ -750,30 +1107,73 @@ impl ClockControl {
         // SET_PERI_REG_BITS(ANA_CONFIG_REG, ANA_CONFIG_M, ANA_CONFIG_M, ANA_CONFIG_S);
         // CLEAR_PERI_REG_MASK(ANA_CONFIG_REG, I2C_APLL_M | I2C_BBPLL_M);
 
-        self.rtc8md256_enable();
+        // keep the 8MHz/256 clock enabled for the measurements
+        self.rtc8md256_acquire();
 
         if xtal_frequency.into() == XTAL_FREQUENCY_AUTO {
             self.detect_xtal_frequency()?;
         }
 
         self.rtc8md256_frequency_measured =
-            self.measure_slow_frequency(CalibrateRTCSource::RTC8MD256)?;
+            self.measure_slow_frequency(CalibrateRTCSource::RTC8MD256, CYCLES_XTAL_CALIBRATION)?;
         self.rtc8md256_frequency = self.rtc8md256_frequency_measured;
         self.rtc8m_frequency_measured = self.rtc8md256_frequency_measured * 256;
         self.rtc8m_frequency = self.rtc8m_frequency_measured;
 
         self.set_slow_rtc_source(SlowRTCSource::RTC150k);
-        self.rtc_frequency_measured = self.measure_slow_frequency(CalibrateRTCSource::SlowRTC)?;
+        self.rtc_frequency_measured =
+            self.measure_slow_frequency(CalibrateRTCSource::SlowRTC, CYCLES_XTAL_CALIBRATION)?;
         self.rtc_frequency = self.rtc_frequency_measured;
 
         self.set_slow_rtc_source(SlowRTCSource::RTC8MD256);
         self.set_fast_rtc_source(FastRTCSource::RTC8M);
+        self.rtc8md256_release();
 
         self.set_cpu_frequency_default(false)?;
 
         Ok(self)
     }
 
+    /// Detect the flash frequency and mode as configured by the bootloader
+    fn detect_flash(&mut self) {
+        self.flash_mode = flash::mode();
+        self.flash_frequency = self.apb_frequency() / flash::divider();
+        self.flash_frequency_max = self.flash_frequency;
+    }
+
+    /// Return true if external RAM is present and accessed via SPI0
+    fn external_ram_in_use() -> bool {
+        #[cfg(feature = "external_ram")]
+        {
+            crate::external_ram::get_size() > 0
+        }
+        #[cfg(not(feature = "external_ram"))]
+        {
+            false
+        }
+    }
+
+    /// Calculate the APB frequency for a CPU source and (rounded) frequency
+    fn apb_frequency_for(source: CPUSource, cpu_frequency: Hertz) -> Hertz {
+        match source {
+            CPUSource::PLL => APB_FREQ_PLL,
+            CPUSource::APLL => cpu_frequency / 2,
+            _ => cpu_frequency,
+        }
+    }
+
+    /// Set the flash clock divider for the given APB frequency
+    ///
+    /// Keeps the flash frequency as close as possible to, but never above, the frequency
+    /// configured by the bootloader.
+    fn set_flash_clock(&mut self, apb_frequency: Hertz) {
+        let divider = flash::divider_for(apb_frequency, self.flash_frequency_max);
+        if divider != flash::divider() {
+            flash::set_divider(divider);
+        }
+        self.flash_frequency = apb_frequency / divider;
+    }
+
     /// calculate the number of cpu cycles from a time at the current CPU frequency
     fn time_to_cpu_cycles<T: Into<NanoSeconds>>(&self, time: T) -> u32 {
         (((self.cpu_frequency / Hertz(1_000_000)) as u64) * (u32::from(time.into()) as u64) / 1000)
@@ -802,6 +1202,9 @@ impl ClockControl {
     ///
     /// This function switches to the default source & frequency (locks can not have been
     /// acquired yet as this can only be done from ClockControlConfig).
+    ///
+    /// While external RAM is in use, all three configurations need to result in the same APB
+    /// frequency, as the PSRAM timing is not adjusted when the SPI0 clock changes.
     pub fn set_cpu_frequencies<T1, T2, T3>(
         &mut self,
         cpu_source_default: CPUSource,
@@ -816,17 +1219,12 @@ impl ClockControl {
         T2: Into<Hertz> + Copy + PartialOrd,
         T3: Into<Hertz> + Copy + PartialOrd,
     {
-        match cpu_source_default {
-            CPUSource::APLL => return Err(Error::UnsupportedFreqConfig),
-            _ => {}
-        }
-        match cpu_source_locked {
-            CPUSource::APLL => return Err(Error::UnsupportedFreqConfig),
-            _ => {}
-        }
-        match cpu_source_apb_locked {
-            CPUSource::APLL => return Err(Error::UnsupportedFreqConfig),
-            _ => {}
+        if self.apll_frequency == FREQ_OFF
+            && (cpu_source_default == CPUSource::APLL
+                || cpu_source_locked == CPUSource::APLL
+                || cpu_source_apb_locked == CPUSource::APLL)
+        {
+            return Err(Error::UnsupportedFreqConfig);
         }
 
         if cpu_frequency_default.into() < CPU_FREQ_MIN
@@ -843,20 +1241,31 @@ impl ClockControl {
             return Err(Error::FrequencyTooHigh);
         }
 
-        self.cpu_source_default = cpu_source_default;
-        self.cpu_frequency_default =
+        let cpu_frequency_default =
             self.round_cpu_frequency(cpu_source_default, cpu_frequency_default);
-        self.cpu_source_locked = cpu_source_locked;
-        self.cpu_frequency_locked =
+        let cpu_frequency_locked =
             self.round_cpu_frequency(cpu_source_locked, cpu_frequency_locked);
-        self.cpu_source_apb_locked = cpu_source_apb_locked;
-        self.cpu_frequency_apb_locked =
+        let cpu_frequency_apb_locked =
             self.round_cpu_frequency(cpu_source_apb_locked, cpu_frequency_apb_locked);
+        let apb_frequency_apb_locked =
+            Self::apb_frequency_for(cpu_source_apb_locked, cpu_frequency_apb_locked);
+
+        if Self::external_ram_in_use()
+            && (Self::apb_frequency_for(cpu_source_default, cpu_frequency_default)
+                != apb_frequency_apb_locked
+                || Self::apb_frequency_for(cpu_source_locked, cpu_frequency_locked)
+                    != apb_frequency_apb_locked)
+        {
+            return Err(Error::ExternalRAMInUse);
+        }
 
-        self.apb_frequency_apb_locked = match cpu_source_apb_locked {
-            CPUSource::PLL => APB_FREQ_PLL,
-            _ => self.cpu_frequency_apb_locked,
-        };
+        self.cpu_source_default = cpu_source_default;
+        self.cpu_frequency_default = cpu_frequency_default;
+        self.cpu_source_locked = cpu_source_locked;
+        self.cpu_frequency_locked = cpu_frequency_locked;
+        self.cpu_source_apb_locked = cpu_source_apb_locked;
+        self.cpu_frequency_apb_locked = cpu_frequency_apb_locked;
+        self.apb_frequency_apb_locked = apb_frequency_apb_locked;
 
         self.ref_clock_stable = self
             .check_ref_clock_stable(self.cpu_source_default, self.cpu_frequency_default)
@@ -873,15 +1282,20 @@ impl ClockControl {
         match source {
             CPUSource::PLL => true,
             CPUSource::Xtal => f_hz >= MINIMUM_APB_FREQ_FOR_STABLE_REF_CLK,
-            CPUSource::RTC8M => {
+            CPUSource::RTC8M | CPUSource::APLL => {
+                let f_apb = if source == CPUSource::APLL {
+                    f_hz / 2
+                } else {
+                    f_hz
+                };
                 let round = MINIMUM_APB_FREQ_FOR_STABLE_REF_CLK
                     / MINIMUM_REF_CLOCK_ACCURACY_FOR_STABLE_REF_CLOCK;
-                let f_rounded = (f_hz + round / 2) / round * round;
+                let f_rounded = (f_apb + round / 2) / round * round;
 
                 f_rounded >= MINIMUM_APB_FREQ_FOR_STABLE_REF_CLK
-                    && f_rounded == (f_hz + REF_CLK_FREQ_1M / 2) / REF_CLK_FREQ_1M * REF_CLK_FREQ_1M
+                    && f_rounded
+                        == (f_apb + REF_CLK_FREQ_1M / 2) / REF_CLK_FREQ_1M * REF_CLK_FREQ_1M
             }
-            CPUSource::APLL => unimplemented!(),
         }
     }
 
@@ -914,7 +1328,13 @@ impl ClockControl {
 
                 self.rtc8m_frequency_measured / div
             }
-            _ => unimplemented!(),
+            CPUSource::APLL => {
+                if f_hz <= self.apll_frequency / 4 {
+                    self.apll_frequency / 4
+                } else {
+                    self.apll_frequency / 2
+                }
+            }
         }
     }
 
@@ -952,13 +1372,36 @@ impl ClockControl {
         frequency: T,
         keep_pll_enabled: bool,
     ) -> Result<&mut Self, Error> {
-        match source {
-            CPUSource::Xtal => self.set_cpu_frequency_to_xtal(frequency)?,
-            CPUSource::PLL => self.set_cpu_frequency_to_pll(frequency)?,
-            CPUSource::RTC8M => self.set_cpu_frequency_to_8m(frequency)?,
-            CPUSource::APLL => return Err(Error::UnsupportedFreqConfig),
+        let rtc8m_was_used = self.cpu_source == CPUSource::RTC8M;
+        if source == CPUSource::RTC8M && !rtc8m_was_used {
+            self.rtc8m_acquire();
+        }
+
+        // keep the flash clock within spec during the switch by using the divider for the
+        // highest APB frequency, the final divider is set after the switch
+        let apb_frequency =
+            Self::apb_frequency_for(source, self.round_cpu_frequency(source, frequency));
+        let apb_frequency_current = self.apb_frequency();
+        if apb_frequency > apb_frequency_current {
+            self.set_flash_clock(apb_frequency);
+        } else {
+            self.set_flash_clock(apb_frequency_current);
+        }
+
+        let result = match source {
+            CPUSource::Xtal => self.set_cpu_frequency_to_xtal(frequency),
+            CPUSource::PLL => self.set_cpu_frequency_to_pll(frequency),
+            CPUSource::RTC8M => self.set_cpu_frequency_to_8m(frequency),
+            CPUSource::APLL => self.set_cpu_frequency_to_apll(frequency),
         };
 
+        // release the 8MHz oscillator when no longer used (or when switching to it failed)
+        if self.cpu_source != CPUSource::RTC8M && (rtc8m_was_used || source == CPUSource::RTC8M) {
+            self.rtc8m_release();
+        }
+        self.set_flash_clock(self.apb_frequency());
+        result?;
+
         if source != CPUSource::PLL && !keep_pll_enabled {
             self.pll_disable();
         }
@@ -1004,22 +1447,6 @@ impl ClockControl {
                 .modify(|_, w| w.dig_dbias_wak().variant(DIG_DBIAS_XTAL))
         };
 
-        // TODO: check if this is all needed
-        self.rtc_control.clk_conf.modify(|_, w| {
-            w.ck8m_force_pu()
-                .set_bit()
-                .ck8m_force_pd()
-                .clear_bit()
-                .enb_ck8m()
-                .clear_bit()
-                .enb_ck8m_div()
-                .clear_bit()
-                .dig_clk8m_en()
-                .set_bit()
-                .dig_clk8m_d256_en()
-                .set_bit()
-        });
-
         // set divider from 8MHz to CPU clock
         self.apb_control
             .sysclk_conf
@@ -1131,7 +1558,6 @@ impl ClockControl {
     where
         T: Into<Hertz> + Copy + PartialOrd,
     {
-        // TODO: adjust bias if flash at 80MHz
         let (cpu_freq, pll_frequency_high, cpuperiod_sel, dbias) = match frequency.into() {
             f if f <= CPU_FREQ_80M => (
                 CPU_FREQ_80M,
@@ -1153,6 +1579,13 @@ impl ClockControl {
             ),
         };
 
+        // flash at 80MHz requires the higher voltage as well
+        let dbias = if self.flash_frequency_max > FLASH_FREQ_40M {
+            DIG_DBIAS_240M_OR_FLASH_80M
+        } else {
+            dbias
+        };
+
         // when pll frequency needs to be switched, temporarily go to Xtal to avoid lock-ups
         // TODO only needed on rev0? (ESP32 Errata 3.5)
         if (self.cpu_source == CPUSource::PLL)
@@ -1161,6 +1594,11 @@ impl ClockControl {
             self.set_cpu_frequency_to_xtal(self.xtal_frequency)?;
         }
 
+        // the cpu period selection is shared with the APLL, so temporarily go to Xtal
+        if self.cpu_source == CPUSource::APLL {
+            self.set_cpu_frequency_to_xtal(self.xtal_frequency)?;
+        }
+
         // pll frequency changes
         if self.pll_frequency == FREQ_OFF
             || pll_frequency_high != (self.pll_frequency == PLL_FREQ_480M)
@@ -1200,6 +1638,14 @@ impl ClockControl {
                     .cpu_per_conf()
                     .modify(|_, w| w.cpuperiod_sel().variant(cpuperiod_sel));
             }
+        } else {
+            self.rtc_control
+                .cntl
+                .modify(|_, w| w.dig_dbias_wak().variant(dbias));
+
+            self.dport_control
+                .cpu_per_conf()
+                .modify(|_, w| w.cpuperiod_sel().variant(cpuperiod_sel));
         }
 
         // switch clock source
@@ -1218,6 +1664,91 @@ impl ClockControl {
         Ok(())
     }
 
+    /// Sets the CPU frequency using the APLL to closest possible frequency (rounding up).
+    ///
+    /// The CPU frequency is APLL/4 or APLL/2, the APB frequency is half the CPU frequency.
+    /// The ref clock is not guaranteed to be at 1MHz
+    fn set_cpu_frequency_to_apll<T: Into<Hertz> + Copy + PartialOrd>(
+        &mut self,
+        frequency: T,
+    ) -> Result<(), Error> {
+        if self.apll_frequency == FREQ_OFF {
+            return Err(Error::UnsupportedFreqConfig);
+        }
+
+        let (cpu_freq, cpuperiod_sel) = if frequency.into() <= self.apll_frequency / 4 {
+            (self.apll_frequency / 4, CPUPERIOD_SEL_A::SEL_80)
+        } else {
+            (self.apll_frequency / 2, CPUPERIOD_SEL_A::SEL_160)
+        };
+
+        // the cpu period selection is shared with the PLL, so temporarily go to Xtal
+        if self.cpu_source == CPUSource::PLL || self.cpu_source == CPUSource::APLL {
+            self.set_cpu_frequency_to_xtal(self.xtal_frequency)?;
+        }
+
+        self.rtc_control
+            .cntl
+            .modify(|_, w| w.dig_dbias_wak().variant(DIG_DBIAS_80M_160M));
+
+        self.dport_control
+            .cpu_per_conf()
+            .modify(|_, w| w.cpuperiod_sel().variant(cpuperiod_sel));
+
+        let apb_frequency = cpu_freq / 2;
+        let div_1m = core::cmp::max(1, (apb_frequency + REF_CLK_FREQ_1M / 2) / REF_CLK_FREQ_1M);
+
+        // adjust ref tick
+        self.apb_control
+            .apll_tick_conf
+            .write(|w| unsafe { w.apll_tick_num().bits(div_1m as u8 - 1) });
+
+        // switch clock source
+        self.rtc_control
+            .clk_conf
+            .modify(|_, w| w.soc_clk_sel().apll());
+
+        self.cpu_source = CPUSource::APLL;
+        self.cpu_frequency = cpu_freq;
+        self.apb_frequency = apb_frequency;
+        self.ref_frequency = apb_frequency / div_1m;
+        self.set_apb_frequency_to_scratch(self.apb_frequency);
+
+        self.wait_for_slow_cycle();
+
+        Ok(())
+    }
+
+    /// Set the APLL frequency or disable the APLL (None)
+    ///
+    /// Fails if the APLL is used as CPU source.
+    fn change_apll_frequency(&mut self, frequency: Option<Hertz>) -> Result<Hertz, Error> {
+        if self.cpu_source == CPUSource::APLL
+            || self.cpu_source_default == CPUSource::APLL
+            || self.cpu_source_locked == CPUSource::APLL
+            || self.cpu_source_apb_locked == CPUSource::APLL
+        {
+            return Err(Error::APLLInUse);
+        }
+
+        match frequency {
+            Some(frequency) => self.apll_enable(frequency)?,
+            None => self.apll_disable(),
+        }
+
+        Ok(self.apll_frequency)
+    }
+
+    /// Enable the APLL and set it to the closest possible frequency
+    ///
+    /// The APLL can generate frequencies between about 5.3MHz and 125MHz. It needs to be enabled
+    /// before it can be used as CPU source in
+    /// [set_cpu_frequencies](ClockControl::set_cpu_frequencies).
+    pub fn set_apll_frequency<T: Into<Hertz>>(&mut self, frequency: T) -> Result<&mut Self, Error> {
+        self.change_apll_frequency(Some(frequency.into()))?;
+        Ok(self)
+    }
+
     /// wait for slow clock cycle to synchronize
     fn wait_for_slow_cycle(&mut self) {
         // get timer group 0 registers, do it this way instead of
@@ -1265,7 +1796,16 @@ impl ClockControl {
 
                 self.apb_frequency / (div + 1) as u32
             }
-            CPUSource::APLL => unimplemented!(),
+            CPUSource::APLL => {
+                let div = self
+                    .apb_control
+                    .apll_tick_conf
+                    .read()
+                    .apll_tick_num()
+                    .bits();
+
+                self.apb_frequency / (div + 1) as u32
+            }
             CPUSource::RTC8M => {
                 let div = self
                     .apb_control
@@ -1285,6 +1825,7 @@ impl ClockControl {
     pub fn apb_frequency(&self) -> Hertz {
         match self.cpu_source() {
             CPUSource::PLL => APB_FREQ_PLL,
+            CPUSource::APLL => self.cpu_frequency() / 2,
             _ => self.cpu_frequency(),
         }
     }
@@ -1307,7 +1848,7 @@ impl ClockControl {
     pub fn slow_rtc_frequency(&self) -> Hertz {
         match self.slow_rtc_source() {
             Ok(SlowRTCSource::RTC150k) => self.rtc_frequency_measured,
-            Ok(SlowRTCSource::Xtal32k) => XTAL32K_FREQUENCY,
+            Ok(SlowRTCSource::Xtal32k) => self.xtal32k_frequency,
             Ok(SlowRTCSource::RTC8MD256) => self.rtc8md256_frequency_measured,
             _ => FREQ_OFF,
         }
@@ -1324,7 +1865,13 @@ impl ClockControl {
     }
 
     /// Set the Slow RTC clock source
+    ///
+    /// Selecting the 32kHz Xtal starts the crystal oscillator. If the crystal does not
+    /// oscillate, the 150kHz oscillator is selected instead, which can be checked via
+    /// [slow_rtc_source](ClockControl::slow_rtc_source).
     pub fn set_slow_rtc_source(&mut self, source: SlowRTCSource) -> &mut Self {
+        let rtc8md256_was_used = self.slow_rtc_source == SlowRTCSource::RTC8MD256;
+
         match source {
             SlowRTCSource::RTC150k => {
                 self.rtc_control
@@ -1334,12 +1881,20 @@ impl ClockControl {
                 self.slow_rtc_frequency = self.rtc_frequency_measured;
             }
             SlowRTCSource::Xtal32k => {
+                if !self.is_xtal32k_enabled() && self.xtal32k_enable().is_err() {
+                    return self.set_slow_rtc_source(SlowRTCSource::RTC150k);
+                }
+
                 self.rtc_control
                     .clk_conf
                     .modify(|_, w| w.ana_clk_rtc_sel().ck_xtal_32k());
-                self.slow_rtc_frequency = XTAL32K_FREQUENCY;
+                self.slow_rtc_frequency = self.xtal32k_frequency;
             }
             SlowRTCSource::RTC8MD256 => {
+                if !rtc8md256_was_used {
+                    self.rtc8md256_acquire();
+                }
+
                 self.rtc_control
                     .clk_conf
                     .modify(|_, w| w.ana_clk_rtc_sel().ck8m_d256_out());
@@ -1348,6 +1903,10 @@ impl ClockControl {
         }
         self.delay(DELAY_SLOW_CLK_SWITCH);
         self.slow_rtc_source = source;
+
+        if rtc8md256_was_used && source != SlowRTCSource::RTC8MD256 {
+            self.rtc8md256_release();
+        }
         self
     }
 
@@ -1375,8 +1934,14 @@ impl ClockControl {
 
     /// Set the Fast RTC clock source
     pub fn set_fast_rtc_source(&mut self, source: FastRTCSource) -> &mut Self {
+        let rtc8m_was_used = self.fast_rtc_source == FastRTCSource::RTC8M;
+
         match source {
             FastRTCSource::RTC8M => {
+                if !rtc8m_was_used {
+                    self.rtc8m_acquire();
+                }
+
                 self.rtc_control
                     .clk_conf
                     .modify(|_, w| w.fast_clk_rtc_sel().ck8m());
@@ -1391,6 +1956,10 @@ impl ClockControl {
         }
         self.delay(DELAY_FAST_CLK_SWITCH);
         self.fast_rtc_source = source;
+
+        if rtc8m_was_used && source != FastRTCSource::RTC8M {
+            self.rtc8m_release();
+        }
         self
     }
 
@@ -1454,7 +2023,17 @@ impl ClockControl {
                 _ => FREQ_OFF,
             },
             CPUSource::RTC8M => self.rtc8m_frequency_measured,
-            CPUSource::APLL => unimplemented!(),
+            CPUSource::APLL => match self
+                .dport_control
+                .cpu_per_conf()
+                .read()
+                .cpuperiod_sel()
+                .variant()
+            {
+                Val(CPUPERIOD_SEL_A::SEL_80) => self.apll_frequency / 4,
+                Val(CPUPERIOD_SEL_A::SEL_160) => self.apll_frequency / 2,
+                _ => FREQ_OFF,
+            },
         }
     }
 }
@@ -1,5 +1,10 @@
-//! PLL control
+//! PLL and APLL control
 //!
+//! The audio PLL (APLL) output frequency is given by:
+//! `xtal_frequency * (4 + sdm2 + sdm1 / 2^8 + sdm0 / 2^16) / (2 * (o_div + 2))`,
+//! where the VCO frequency `xtal_frequency * (4 + sdm2 + sdm1 / 2^8 + sdm0 / 2^16)` needs to be
+//! between 350MHz and 500MHz. On revision 0 chips the fractional part (sdm0 and sdm1) is not
+//! supported.
 
 use super::Error;
 use crate::prelude::*;
@@ -10,8 +15,21 @@ use esp32::generic::Variant::Val;
 const DELAY_PLL_ENABLE_WITH_150K: MicroSeconds = MicroSeconds(80);
 const DELAY_PLL_ENABLE_WITH_32K: MicroSeconds = MicroSeconds(160);
 
-// Addresses for internal I2C bus for PLL
-const I2C_BLOCK: u8 = 0x66;
+// Delay (in microseconds) between polls of the APLL calibration
+const DELAY_APLL_CALIBRATION_POLL: MicroSeconds = MicroSeconds(1);
+
+// Addresses for internal I2C bus for PLL and APLL
+const I2C_BLOCK_BBPLL: u8 = 0x66;
+const I2C_BLOCK_APLL: u8 = 0x6d;
+
+// APLL frequency limits
+const APLL_VCO_FREQ_MIN: Hertz = Hertz(350_000_000);
+const APLL_VCO_FREQ_MAX: Hertz = Hertz(500_000_000);
+const APLL_O_DIV_MAX: u32 = 31;
+const APLL_SDM2_MAX: u32 = 63;
+
+// Chip revision 1 bit in the EFUSE_BLK0_RDATA3_REG register
+const EFUSE_CHIP_VER_REV1: u32 = 1 << 15;
 
 // Register addresses for internal I2C bus
 mod i2c {
@@ -27,6 +45,20 @@ mod i2c {
     pub const BBADC_CAL_7_0: u8 = 12;
 }
 
+// Register addresses for internal I2C bus of the APLL
+mod i2c_apll {
+    pub const IR_CAL_DELAY: u8 = 0;
+    pub const OR_CAL_END: u8 = 3;
+    pub const OR_CAL_END_BIT: u8 = 1 << 7;
+    pub const OR_OUTPUT_DIV: u8 = 4;
+    pub const OR_OUTPUT_DIV_MASK: u8 = 0x1f;
+    pub const SDM_STOP: u8 = 5;
+    pub const DSDM2: u8 = 7;
+    pub const DSDM2_MASK: u8 = 0x3f;
+    pub const DSDM1: u8 = 8;
+    pub const DSDM0: u8 = 9;
+}
+
 // Values for internal I2C registers
 mod val {
     pub const ENDIV5_VAL_320M: u8 = 0x43;
@@ -38,6 +70,75 @@ mod val {
     pub const OC_ENB_FCAL_VAL: u8 = 0x9a;
     pub const OC_ENB_VCON_VAL: u8 = 0x00;
     pub const BBADC_CAL_7_0_VAL: u8 = 0x00;
+
+    pub const APLL_SDM_STOP_VAL_1: u8 = 0x09;
+    pub const APLL_SDM_STOP_VAL_2_REV0: u8 = 0x69;
+    pub const APLL_SDM_STOP_VAL_2_REV1: u8 = 0x49;
+    pub const APLL_CAL_DELAY_1: u8 = 0x0f;
+    pub const APLL_CAL_DELAY_2: u8 = 0x3f;
+    pub const APLL_CAL_DELAY_3: u8 = 0x1f;
+}
+
+/// APLL configuration: (sdm0, sdm1, sdm2, o_div)
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ApllConfig(u8, u8, u8, u8);
+
+impl ApllConfig {
+    /// Calculate the APLL configuration closest to the requested frequency
+    ///
+    /// The smallest output divider giving a valid VCO frequency is used, which gives the
+    /// finest resolution. Returns an error if no output divider gives a valid VCO frequency.
+    fn calculate(
+        xtal_frequency: Hertz,
+        frequency: Hertz,
+        fractional: bool,
+    ) -> Result<ApllConfig, Error> {
+        let xtal = u32::from(xtal_frequency) as u64;
+        let f = u32::from(frequency) as u64;
+
+        if f * 2 * (APLL_O_DIV_MAX as u64 + 2) < u32::from(APLL_VCO_FREQ_MIN) as u64 {
+            return Err(Error::FrequencyTooLow);
+        }
+        if f * 2 * 2 > u32::from(APLL_VCO_FREQ_MAX) as u64 {
+            return Err(Error::FrequencyTooHigh);
+        }
+
+        let o_div = (0..=APLL_O_DIV_MAX as u64)
+            .find(|o_div| f * 2 * (o_div + 2) >= u32::from(APLL_VCO_FREQ_MIN) as u64)
+            .ok_or(Error::FrequencyTooLow)?;
+
+        // for some frequencies (e.g. 83.4-87.5MHz) no output divider gives a valid VCO frequency
+        let vco = f * 2 * (o_div + 2);
+        if vco > u32::from(APLL_VCO_FREQ_MAX) as u64 {
+            return Err(Error::UnsupportedPLLConfig);
+        }
+
+        // sdm as 16.16 fixed point value, rounded to the nearest value
+        let sdm = ((vco << 16) + xtal / 2) / xtal;
+        let sdm = if fractional {
+            sdm
+        } else {
+            (sdm + (1 << 15)) & !0xffff
+        };
+
+        if sdm < 4 << 16 || (sdm >> 16) - 4 > APLL_SDM2_MAX as u64 {
+            return Err(Error::UnsupportedPLLConfig);
+        }
+
+        Ok(ApllConfig(
+            sdm as u8,
+            (sdm >> 8) as u8,
+            ((sdm >> 16) - 4) as u8,
+            o_div as u8,
+        ))
+    }
+
+    /// The output frequency of the APLL with this configuration
+    fn frequency(&self, xtal_frequency: Hertz) -> Hertz {
+        let sdm = ((self.2 as u64 + 4) << 16) | (self.1 as u64) << 8 | self.0 as u64;
+        let div = (2 * (self.3 as u64 + 2)) << 16;
+        Hertz(((u32::from(xtal_frequency) as u64 * sdm + div / 2) / div) as u32)
+    }
 }
 
 // COnfiguration values depending on Xtal frequency for internal I2C registers
@@ -66,10 +167,10 @@ impl Config {
 
 impl super::ClockControl {
     /// write to internal I2C PLL bus
-    fn write_i2c(&mut self, address: u8, data: u8) {
+    fn write_i2c(&mut self, block: u8, address: u8, data: u8) {
         self.rtc_control.pll.write(|w| unsafe {
             w.block()
-                .bits(I2C_BLOCK)
+                .bits(block)
                 .addr()
                 .bits(address)
                 .data()
@@ -82,10 +183,10 @@ impl super::ClockControl {
     }
 
     /// read from internal I2C PLL bus
-    fn _read_i2c(&mut self, address: u8) -> u8 {
+    fn read_i2c(&mut self, block: u8, address: u8) -> u8 {
         self.rtc_control.pll.write(|w| unsafe {
             w.block()
-                .bits(I2C_BLOCK)
+                .bits(block)
                 .addr()
                 .bits(address)
                 .write()
@@ -97,6 +198,12 @@ impl super::ClockControl {
         self.rtc_control.pll.read().data().bits()
     }
 
+    /// write the masked bits of a register on the internal I2C PLL bus
+    fn write_i2c_mask(&mut self, block: u8, address: u8, mask: u8, data: u8) {
+        let value = self.read_i2c(block, address) & !mask | data & mask;
+        self.write_i2c(block, address, value);
+    }
+
     /// disable the PLL
     pub(crate) fn pll_disable(&mut self) {
         self.rtc_control.options0.modify(|_, w| {
@@ -128,11 +235,15 @@ impl super::ClockControl {
         });
 
         // reset BBPLL configuration
-        self.write_i2c(i2c::IR_CAL_DELAY, val::IR_CAL_DELAY_VAL);
-        self.write_i2c(i2c::IR_CAL_EXT_CAP, val::IR_CAL_EXT_CAP_VAL);
-        self.write_i2c(i2c::OC_ENB_FCAL, val::OC_ENB_FCAL_VAL);
-        self.write_i2c(i2c::OC_ENB_VCON, val::OC_ENB_VCON_VAL);
-        self.write_i2c(i2c::BBADC_CAL_7_0, val::BBADC_CAL_7_0_VAL);
+        self.write_i2c(I2C_BLOCK_BBPLL, i2c::IR_CAL_DELAY, val::IR_CAL_DELAY_VAL);
+        self.write_i2c(
+            I2C_BLOCK_BBPLL,
+            i2c::IR_CAL_EXT_CAP,
+            val::IR_CAL_EXT_CAP_VAL,
+        );
+        self.write_i2c(I2C_BLOCK_BBPLL, i2c::OC_ENB_FCAL, val::OC_ENB_FCAL_VAL);
+        self.write_i2c(I2C_BLOCK_BBPLL, i2c::OC_ENB_VCON, val::OC_ENB_VCON_VAL);
+        self.write_i2c(I2C_BLOCK_BBPLL, i2c::BBADC_CAL_7_0, val::BBADC_CAL_7_0_VAL);
 
         self.set_pll_frequency(high)
     }
@@ -144,8 +255,8 @@ impl super::ClockControl {
                 self.pll_frequency = super::PLL_FREQ_320M;
                 self.pll_d2_frequency = self.pll_frequency / 2;
 
-                self.write_i2c(i2c::ENDIV5, val::ENDIV5_VAL_320M);
-                self.write_i2c(i2c::BBADC_DSMP, val::BBADC_DSMP_VAL_320M);
+                self.write_i2c(I2C_BLOCK_BBPLL, i2c::ENDIV5, val::ENDIV5_VAL_320M);
+                self.write_i2c(I2C_BLOCK_BBPLL, i2c::BBADC_DSMP, val::BBADC_DSMP_VAL_320M);
 
                 match self.xtal_frequency {
                     Hertz(40_000_000) => Config::PLL_320M_XTAL_40M,
@@ -158,8 +269,8 @@ impl super::ClockControl {
                 self.pll_frequency = super::PLL_FREQ_480M;
                 self.pll_d2_frequency = self.pll_frequency / 2;
 
-                self.write_i2c(i2c::ENDIV5, val::ENDIV5_VAL_480M);
-                self.write_i2c(i2c::BBADC_DSMP, val::BBADC_DSMP_VAL_480M);
+                self.write_i2c(I2C_BLOCK_BBPLL, i2c::ENDIV5, val::ENDIV5_VAL_480M);
+                self.write_i2c(I2C_BLOCK_BBPLL, i2c::BBADC_DSMP, val::BBADC_DSMP_VAL_480M);
 
                 match self.xtal_frequency {
                     Hertz(40_000_000) => Config::PLL_480M_XTAL_40M,
@@ -170,9 +281,9 @@ impl super::ClockControl {
             }
         };
 
-        self.write_i2c(i2c::OC_LREF, pll_config.get_lref());
-        self.write_i2c(i2c::OC_DIV_7_0, pll_config.get_div7_0());
-        self.write_i2c(i2c::OC_DCUR, pll_config.get_dcur());
+        self.write_i2c(I2C_BLOCK_BBPLL, i2c::OC_LREF, pll_config.get_lref());
+        self.write_i2c(I2C_BLOCK_BBPLL, i2c::OC_DIV_7_0, pll_config.get_div7_0());
+        self.write_i2c(I2C_BLOCK_BBPLL, i2c::OC_DCUR, pll_config.get_dcur());
 
         let delay_us = if let Ok(super::SlowRTCSource::RTC150k) = self.slow_rtc_source() {
             DELAY_PLL_ENABLE_WITH_150K
@@ -203,4 +314,83 @@ impl super::ClockControl {
             _ => super::FREQ_OFF,
         }
     }
+
+    /// Return true if the chip is revision 0, which does not support a fractional APLL divider
+    fn is_chip_rev0(&self) -> bool {
+        let efuse = unsafe { &*esp32::EFUSE::ptr() };
+        efuse.blk0_rdata3.read().bits() & EFUSE_CHIP_VER_REV1 == 0
+    }
+
+    /// enable the APLL and set it to the closest possible frequency
+    pub(crate) fn apll_enable<T: Into<Hertz>>(&mut self, frequency: T) -> Result<(), Error> {
+        let rev0 = self.is_chip_rev0();
+        let config = ApllConfig::calculate(self.xtal_frequency, frequency.into(), !rev0)?;
+
+        self.rtc_control
+            .ana_conf
+            .modify(|_, w| w.plla_force_pd().clear_bit().plla_force_pu().set_bit());
+        self.rtc_control
+            .options0
+            .modify(|_, w| w.bias_i2c_force_pd().clear_bit());
+
+        let sdm_stop_val_2 = if rev0 {
+            val::APLL_SDM_STOP_VAL_2_REV0
+        } else {
+            val::APLL_SDM_STOP_VAL_2_REV1
+        };
+
+        self.write_i2c_mask(
+            I2C_BLOCK_APLL,
+            i2c_apll::DSDM2,
+            i2c_apll::DSDM2_MASK,
+            config.2,
+        );
+        self.write_i2c(I2C_BLOCK_APLL, i2c_apll::DSDM0, config.0);
+        self.write_i2c(I2C_BLOCK_APLL, i2c_apll::DSDM1, config.1);
+        self.write_i2c(I2C_BLOCK_APLL, i2c_apll::SDM_STOP, val::APLL_SDM_STOP_VAL_1);
+        self.write_i2c(I2C_BLOCK_APLL, i2c_apll::SDM_STOP, sdm_stop_val_2);
+        self.write_i2c_mask(
+            I2C_BLOCK_APLL,
+            i2c_apll::OR_OUTPUT_DIV,
+            i2c_apll::OR_OUTPUT_DIV_MASK,
+            config.3,
+        );
+
+        // calibration
+        self.write_i2c(
+            I2C_BLOCK_APLL,
+            i2c_apll::IR_CAL_DELAY,
+            val::APLL_CAL_DELAY_1,
+        );
+        self.write_i2c(
+            I2C_BLOCK_APLL,
+            i2c_apll::IR_CAL_DELAY,
+            val::APLL_CAL_DELAY_2,
+        );
+        self.write_i2c(
+            I2C_BLOCK_APLL,
+            i2c_apll::IR_CAL_DELAY,
+            val::APLL_CAL_DELAY_3,
+        );
+
+        while self.read_i2c(I2C_BLOCK_APLL, i2c_apll::OR_CAL_END) & i2c_apll::OR_CAL_END_BIT == 0 {
+            self.delay(DELAY_APLL_CALIBRATION_POLL); // prevent flooding of RTC bus
+        }
+
+        self.apll_frequency = config.frequency(self.xtal_frequency);
+        Ok(())
+    }
+
+    /// disable the APLL
+    pub(crate) fn apll_disable(&mut self) {
+        self.rtc_control
+            .ana_conf
+            .modify(|_, w| w.plla_force_pd().set_bit().plla_force_pu().clear_bit());
+        self.rtc_control.options0.modify(|_, w| {
+            // is PLL under force power down? then also power down the internal I2C bus
+            w.bias_i2c_force_pd()
+                .bit(self.rtc_control.options0.read().bbpll_force_pd().bit())
+        });
+        self.apll_frequency = super::FREQ_OFF;
+    }
 }
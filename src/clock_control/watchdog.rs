@@ -1,14 +1,16 @@
-//! RTC Watchdog implementation
+//! RTC Watchdog and Timer Group Watchdog implementation
 //!
 //! # TODO:
 //! - Add convenience methods for configuration
 //! - Consider add default configuration for start with time only
 
+use core::marker::PhantomData;
+
 use crate::prelude::*;
 use embedded_hal::watchdog::{Watchdog, WatchdogDisable, WatchdogEnable};
 use esp32::generic::Variant::Val;
 use esp32::rtccntl::wdtconfig0::*;
-use esp32::RTCCNTL;
+use esp32::{RTCCNTL, TIMG0, TIMG1};
 
 pub type WatchdogAction = WDT_STG0_A;
 pub type WatchDogResetDuration = WDT_CPU_RESET_LENGTH_A;
@@ -27,7 +29,7 @@ pub struct WatchDog {
 /// Each of these stages can take a configurable action after expiry of the corresponding period.
 /// When this action is done, it will move to the next stage.
 /// The stage is reset to the first when the watchdog timer is fed.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct WatchdogConfig {
     // Delay before the first action to be taken
     pub period1: MicroSeconds,
@@ -226,3 +228,217 @@ impl Watchdog for WatchDog {
         });
     }
 }
+
+// Bits of the timer group watchdog configuration register
+const TIMG_WDT_EN: u32 = 1 << 31;
+const TIMG_WDT_STG0_SHIFT: u32 = 29;
+const TIMG_WDT_STG1_SHIFT: u32 = 27;
+const TIMG_WDT_STG2_SHIFT: u32 = 25;
+const TIMG_WDT_STG3_SHIFT: u32 = 23;
+const TIMG_WDT_LEVEL_INT_EN: u32 = 1 << 21;
+const TIMG_WDT_CPU_RESET_LENGTH_SHIFT: u32 = 18;
+const TIMG_WDT_SYS_RESET_LENGTH_SHIFT: u32 = 15;
+const TIMG_WDT_RESET_LENGTH_MASK: u32 = 0x3f << TIMG_WDT_SYS_RESET_LENGTH_SHIFT;
+
+/// Timer group watchdog interrupt bit
+const TIMG_WDT_INT: u32 = 1 << 2;
+
+/// Timer Group main watchdog (MWDT)
+///
+/// Uses the same four stage [WatchdogConfig] as the RTC watchdog, however the
+/// [RESETRTC](WatchdogAction::RESETRTC) action, the cpu selection and pausing in sleep are not
+/// supported.
+/// The [INTERRUPT](WatchdogAction::INTERRUPT) action raises the TG*_WDT_LEVEL_INTR interrupt when
+/// enabled via [listen](TimerGroupWatchdog::listen).
+///
+/// The watchdog is clocked from the APB clock, so the APB frequency is locked while it is enabled.
+pub struct TimerGroupWatchdog<TIMG> {
+    clock_control_config: super::ClockControlConfig,
+    config: Option<WatchdogConfig>,
+    _apb_lock: Option<super::dfs::LockAPB>,
+    _timg: PhantomData<TIMG>,
+}
+
+macro_rules! halTimerGroupWatchdog {
+    ($(
+        $TIMGX:ident,
+    )+) => {
+        $(
+            impl TimerGroupWatchdog<$TIMGX> {
+                /// internal function to create new watchdog structure
+                pub(crate) fn new(clock_control_config: super::ClockControlConfig) -> Self {
+                    TimerGroupWatchdog {
+                        clock_control_config,
+                        config: None,
+                        _apb_lock: None,
+                        _timg: PhantomData,
+                    }
+                }
+
+                /// function to unlock the watchdog (write unblock key) and lock after use
+                fn access_registers<A, F: FnMut(&esp32::timg::RegisterBlock) -> A>(
+                    &mut self,
+                    mut f: F,
+                ) -> A {
+                    // Unprotect write access to registers
+                    let timg = unsafe { &(*$TIMGX::ptr()) };
+
+                    timg.wdtwprotect
+                        .write(|w| unsafe { w.bits(WATCHDOG_UNBLOCK_KEY) });
+
+                    let a = f(timg);
+
+                    // Protect again
+                    timg.wdtwprotect
+                        .write(|w| unsafe { w.bits(WATCHDOG_BLOCK_VALUE) });
+
+                    a
+                }
+
+                /// Lock the APB frequency and set the prescaler to 1 tick per microsecond
+                fn lock_clock(&mut self) {
+                    if self._apb_lock.is_none() {
+                        self._apb_lock = Some(self.clock_control_config.lock_apb_frequency());
+                    }
+                    let prescaler = self.clock_control_config.apb_frequency() / Hertz(1_000_000);
+
+                    self.access_registers(|timg| {
+                        timg.wdtconfig1.write(|w| unsafe { w.bits(prescaler << 16) });
+                    });
+                }
+
+                /// Get watchdog configuration
+                ///
+                /// Only available after the watchdog has been configured via
+                /// [set_config](Self::set_config).
+                pub fn config(&self) -> Result<WatchdogConfig, super::Error> {
+                    self.config.ok_or(super::Error::UnsupportedWatchdogConfig)
+                }
+
+                /// Change watchdog timer configuration and start
+                pub fn set_config(&mut self, config: &WatchdogConfig) -> Result<(), super::Error> {
+                    let actions = [config.action1, config.action2, config.action3, config.action4];
+                    if actions.contains(&WatchdogAction::RESETRTC) {
+                        return Err(super::Error::UnsupportedWatchdogConfig);
+                    }
+
+                    let interrupt = if actions.contains(&WatchdogAction::INTERRUPT) {
+                        TIMG_WDT_LEVEL_INT_EN
+                    } else {
+                        0
+                    };
+
+                    let config0 = TIMG_WDT_EN
+                        | interrupt
+                        | (u8::from(config.action1) as u32) << TIMG_WDT_STG0_SHIFT
+                        | (u8::from(config.action2) as u32) << TIMG_WDT_STG1_SHIFT
+                        | (u8::from(config.action3) as u32) << TIMG_WDT_STG2_SHIFT
+                        | (u8::from(config.action4) as u32) << TIMG_WDT_STG3_SHIFT
+                        | (u8::from(config.cpu_reset_duration) as u32)
+                            << TIMG_WDT_CPU_RESET_LENGTH_SHIFT
+                        | (u8::from(config.sys_reset_duration) as u32)
+                            << TIMG_WDT_SYS_RESET_LENGTH_SHIFT;
+
+                    self.lock_clock();
+
+                    // the clock runs at 1 tick per microsecond
+                    self.access_registers(|timg| unsafe {
+                        timg.wdtfeed.write(|w| w.bits(1));
+                        timg.wdtconfig2.write(|w| w.bits(u32::from(config.period1)));
+                        timg.wdtconfig3.write(|w| w.bits(u32::from(config.period2)));
+                        timg.wdtconfig4.write(|w| w.bits(u32::from(config.period3)));
+                        timg.wdtconfig5.write(|w| w.bits(u32::from(config.period4)));
+                        timg.wdtconfig0.write(|w| w.bits(config0));
+                    });
+
+                    self.config = Some(*config);
+                    Ok(())
+                }
+
+                /// Enable the interrupt raised by the [INTERRUPT](WatchdogAction::INTERRUPT) action
+                pub fn listen(&mut self) {
+                    let timg = unsafe { &(*$TIMGX::ptr()) };
+                    // the register is shared with the general purpose and LACT timers
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        timg.int_ena_timers
+                            .modify(|r, w| unsafe { w.bits(r.bits() | TIMG_WDT_INT) })
+                    });
+                }
+
+                /// Disable the watchdog interrupt
+                pub fn unlisten(&mut self) {
+                    let timg = unsafe { &(*$TIMGX::ptr()) };
+                    // the register is shared with the general purpose and LACT timers
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        timg.int_ena_timers
+                            .modify(|r, w| unsafe { w.bits(r.bits() & !TIMG_WDT_INT) })
+                    });
+                }
+
+                /// Clear the watchdog interrupt
+                pub fn clear_interrupt(&mut self) {
+                    let timg = unsafe { &(*$TIMGX::ptr()) };
+                    timg.int_clr_timers.write(|w| unsafe { w.bits(TIMG_WDT_INT) });
+                }
+            }
+
+            /// Enable watchdog timer, only stage 1 with a system reset is used
+            impl WatchdogEnable for TimerGroupWatchdog<$TIMGX> {
+                type Time = MicroSeconds;
+
+                fn start<T: Into<Self::Time>>(&mut self, period: T) {
+                    let period: MicroSeconds = period.into();
+
+                    self.lock_clock();
+
+                    // keep the reset durations, disable the other stages
+                    self.access_registers(|timg| {
+                        timg.wdtfeed.write(|w| unsafe { w.bits(1) });
+                        timg.wdtconfig2.write(|w| unsafe { w.bits(u32::from(period)) });
+                        timg.wdtconfig0.modify(|r, w| unsafe {
+                            w.bits(
+                                r.bits() & TIMG_WDT_RESET_LENGTH_MASK
+                                    | TIMG_WDT_EN
+                                    | (u8::from(WatchdogAction::RESETSYSTEM) as u32)
+                                        << TIMG_WDT_STG0_SHIFT,
+                            )
+                        });
+                    });
+
+                    if let Some(config) = &mut self.config {
+                        config.period1 = period;
+                        config.action1 = WatchdogAction::RESETSYSTEM;
+                        config.action2 = WatchdogAction::OFF;
+                        config.action3 = WatchdogAction::OFF;
+                        config.action4 = WatchdogAction::OFF;
+                    }
+                }
+            }
+
+            /// Disable watchdog timer
+            impl WatchdogDisable for TimerGroupWatchdog<$TIMGX> {
+                fn disable(&mut self) {
+                    self.access_registers(|timg| {
+                        timg.wdtfeed.write(|w| unsafe { w.bits(1) });
+                        timg.wdtconfig0.write(|w| unsafe { w.bits(0) });
+                    });
+                    self._apb_lock = None;
+                }
+            }
+
+            /// Feed (=reset) the watchdog timer
+            impl Watchdog for TimerGroupWatchdog<$TIMGX> {
+                fn feed(&mut self) {
+                    self.access_registers(|timg| {
+                        timg.wdtfeed.write(|w| unsafe { w.bits(1) });
+                    });
+                }
+            }
+        )+
+    }
+}
+
+halTimerGroupWatchdog! {
+    TIMG0,
+    TIMG1,
+}
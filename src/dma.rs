@@ -0,0 +1,307 @@
+//! DMA buffers and descriptors
+//!
+//! Peripherals with DMA support (e.g. SPI) transfer data via linked lists of descriptors
+//! pointing to the data buffers. Both the descriptors and the buffers have to be located in DRAM:
+//! IRAM and external RAM are not DMA capable.
+//!
+//! This is guaranteed by the [DmaBuffer] type, which can only be created from a static buffer in
+//! DRAM or allocated via the [DRAM_ALLOCATOR](crate::alloc::DRAM_ALLOCATOR).
+//!
+//! The descriptors are stored in static DRAM per DMA channel. Each channel can be used by only one
+//! peripheral at a time, which is enforced by the [SpiDmaChannel1] and [SpiDmaChannel2] tokens.
+
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+/// Start of the DMA capable memory region (DRAM)
+const DMA_MEMORY_START: usize = 0x3FFA_E000;
+/// End of the DMA capable memory region (DRAM)
+const DMA_MEMORY_END: usize = 0x4000_0000;
+
+/// Maximum number of bytes per descriptor (12 bits, rounded down to a multiple of 4)
+const DESCRIPTOR_MAX_SIZE: usize = 4092;
+
+/// Number of descriptors per direction per channel
+pub const MAX_DESCRIPTORS: usize = 16;
+
+/// Maximum number of bytes in a single DMA transfer
+pub const MAX_TRANSFER_SIZE: usize = MAX_DESCRIPTORS * DESCRIPTOR_MAX_SIZE;
+
+/// DMA errors
+#[derive(Debug)]
+pub enum Error {
+    /// Buffer is not located in DMA capable memory
+    NotDmaCapable,
+    /// Buffer is not word aligned or its length is not a multiple of 4
+    MisalignedBuffer,
+    /// Buffer is larger than [MAX_TRANSFER_SIZE]
+    BufferTooLarge,
+    /// Buffer has a length of 0
+    EmptyBuffer,
+    /// Allocation of the buffer failed
+    OutOfMemory,
+    /// DMA channel already taken
+    ChannelInUse,
+}
+
+/// Buffer located in DMA capable memory
+///
+/// The buffer is always word aligned and its length is a multiple of 4 bytes.
+pub struct DmaBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    allocated: bool,
+}
+
+unsafe impl Send for DmaBuffer {}
+
+impl DmaBuffer {
+    /// Create a DMA buffer from a static buffer.
+    ///
+    /// The buffer needs to be located in DRAM, word aligned and have a non-zero length which is a
+    /// multiple of 4.
+    pub fn from_static(buffer: &'static mut [u8]) -> Result<Self, Error> {
+        let start = buffer.as_ptr() as usize;
+        let end = start + buffer.len();
+
+        if buffer.is_empty() {
+            return Err(Error::EmptyBuffer);
+        }
+        if start < DMA_MEMORY_START || end > DMA_MEMORY_END {
+            return Err(Error::NotDmaCapable);
+        }
+        if start % 4 != 0 || buffer.len() % 4 != 0 {
+            return Err(Error::MisalignedBuffer);
+        }
+
+        Ok(DmaBuffer {
+            // NOTE(unsafe) pointer from a slice is never null
+            ptr: unsafe { NonNull::new_unchecked(buffer.as_mut_ptr()) },
+            len: buffer.len(),
+            allocated: false,
+        })
+    }
+
+    /// Allocate a zero initialized DMA buffer from DRAM.
+    ///
+    /// The length is rounded up to a multiple of 4.
+    #[cfg(feature = "alloc")]
+    pub fn new(len: usize) -> Result<Self, Error> {
+        let len = (len + 3) & !3;
+        if len == 0 {
+            return Err(Error::EmptyBuffer);
+        }
+
+        let ptr = unsafe {
+            core::alloc::GlobalAlloc::alloc_zeroed(&crate::alloc::DRAM_ALLOCATOR, Self::layout(len))
+        };
+
+        Ok(DmaBuffer {
+            ptr: NonNull::new(ptr).ok_or(Error::OutOfMemory)?,
+            len,
+            allocated: true,
+        })
+    }
+
+    #[cfg(feature = "alloc")]
+    fn layout(len: usize) -> core::alloc::Layout {
+        // NOTE(unsafe) alignment of 4 is a power of 2 and len is rounded to a multiple of it
+        unsafe { core::alloc::Layout::from_size_align_unchecked(len, 4) }
+    }
+
+    /// Release a DMA buffer created from a static buffer
+    ///
+    /// Returns None if the buffer was allocated (the memory will be freed).
+    pub fn into_static(self) -> Option<&'static mut [u8]> {
+        if self.allocated {
+            return None;
+        }
+        let buffer = unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) };
+        core::mem::forget(self);
+        Some(buffer)
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const u8 {
+        self.ptr.as_ptr()
+    }
+}
+
+impl Deref for DmaBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for DmaBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        #[cfg(feature = "alloc")]
+        if self.allocated {
+            unsafe {
+                core::alloc::GlobalAlloc::dealloc(
+                    &crate::alloc::DRAM_ALLOCATOR,
+                    self.ptr.as_ptr(),
+                    Self::layout(self.len),
+                )
+            };
+        }
+    }
+}
+
+/// DMA linked list descriptor (lldesc_t in esp-idf)
+#[doc(hidden)]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Descriptor {
+    config: u32,
+    buffer: *const u8,
+    next: *const Descriptor,
+}
+
+impl Descriptor {
+    const EMPTY: Descriptor = Descriptor {
+        config: 0,
+        buffer: core::ptr::null(),
+        next: core::ptr::null(),
+    };
+
+    const OWNER_DMA: u32 = 1 << 31;
+    const EOF: u32 = 1 << 30;
+}
+
+/// Fill a chain of descriptors for a buffer to be transmitted and return the address of the first
+/// descriptor
+pub(crate) fn fill_tx_descriptors(
+    descriptors: &mut [Descriptor],
+    buffer: &[u8],
+) -> Result<*const Descriptor, Error> {
+    fill_descriptors(descriptors, buffer.as_ptr(), buffer.len(), true)
+}
+
+/// Fill a chain of descriptors for a buffer to receive into and return the address of the first
+/// descriptor
+///
+/// The buffer is borrowed mutably, as the DMA writes into it.
+pub(crate) fn fill_rx_descriptors(
+    descriptors: &mut [Descriptor],
+    buffer: &mut [u8],
+) -> Result<*const Descriptor, Error> {
+    fill_descriptors(descriptors, buffer.as_ptr(), buffer.len(), false)
+}
+
+/// Fill a chain of descriptors for a buffer and return the address of the first descriptor
+///
+/// For transmission the length is set to the length of the data and the last descriptor is marked
+/// as end of frame. For reception the length is set to 0 and will be filled in by the DMA.
+fn fill_descriptors(
+    descriptors: &mut [Descriptor],
+    buffer: *const u8,
+    len: usize,
+    transmit: bool,
+) -> Result<*const Descriptor, Error> {
+    if len == 0 {
+        return Err(Error::EmptyBuffer);
+    }
+
+    let count = (len + DESCRIPTOR_MAX_SIZE - 1) / DESCRIPTOR_MAX_SIZE;
+    if count > descriptors.len() {
+        return Err(Error::BufferTooLarge);
+    }
+
+    for i in 0..count {
+        let offset = i * DESCRIPTOR_MAX_SIZE;
+        let size = core::cmp::min(len - offset, DESCRIPTOR_MAX_SIZE) as u32;
+        let length = if transmit { size } else { 0 };
+        let eof = if transmit && i == count - 1 {
+            Descriptor::EOF
+        } else {
+            0
+        };
+
+        descriptors[i] = Descriptor {
+            config: Descriptor::OWNER_DMA | eof | (length << 12) | size,
+            // NOTE(unsafe) offset is within the buffer
+            buffer: unsafe { buffer.add(offset) },
+            next: if i == count - 1 {
+                core::ptr::null()
+            } else {
+                &descriptors[i + 1] as *const Descriptor
+            },
+        };
+    }
+
+    Ok(&descriptors[0] as *const Descriptor)
+}
+
+/// Descriptors for transmission and reception per SPI DMA channel
+static mut SPI_DMA_DESCRIPTORS: [[Descriptor; 2 * MAX_DESCRIPTORS]; 2] =
+    [[Descriptor::EMPTY; 2 * MAX_DESCRIPTORS]; 2];
+
+static SPI_DMA_CHANNELS_TAKEN: spin::Mutex<bool> = spin::Mutex::new(false);
+
+/// SPI DMA channel
+pub trait SpiDmaChannel {
+    /// Channel number as used in the channel selection register
+    const NUMBER: u8;
+
+    /// Descriptors for transmission and reception
+    #[doc(hidden)]
+    fn descriptors(&mut self) -> (&mut [Descriptor], &mut [Descriptor]);
+}
+
+/// SPI DMA channel 1
+pub struct SpiDmaChannel1 {
+    _private: (),
+}
+
+/// SPI DMA channel 2
+pub struct SpiDmaChannel2 {
+    _private: (),
+}
+
+impl SpiDmaChannel for SpiDmaChannel1 {
+    const NUMBER: u8 = 1;
+
+    fn descriptors(&mut self) -> (&mut [Descriptor], &mut [Descriptor]) {
+        // NOTE(unsafe) only one instance of the channel token exists
+        unsafe { SPI_DMA_DESCRIPTORS[0].split_at_mut(MAX_DESCRIPTORS) }
+    }
+}
+
+impl SpiDmaChannel for SpiDmaChannel2 {
+    const NUMBER: u8 = 2;
+
+    fn descriptors(&mut self) -> (&mut [Descriptor], &mut [Descriptor]) {
+        // NOTE(unsafe) only one instance of the channel token exists
+        unsafe { SPI_DMA_DESCRIPTORS[1].split_at_mut(MAX_DESCRIPTORS) }
+    }
+}
+
+/// The two SPI DMA channels
+pub struct SpiDmaChannels {
+    pub channel1: SpiDmaChannel1,
+    pub channel2: SpiDmaChannel2,
+}
+
+impl SpiDmaChannels {
+    /// Take the SPI DMA channels. This can only be done once.
+    pub fn take() -> Result<Self, Error> {
+        let mut taken = SPI_DMA_CHANNELS_TAKEN.lock();
+        if *taken {
+            return Err(Error::ChannelInUse);
+        }
+        *taken = true;
+
+        Ok(SpiDmaChannels {
+            channel1: SpiDmaChannel1 { _private: () },
+            channel2: SpiDmaChannel2 { _private: () },
+        })
+    }
+}
@@ -0,0 +1,211 @@
+//! Type erased pins
+//!
+//! [AnyPin] carries the pin number at runtime, so pins of different GPIOs can be stored in an
+//! array. Pins are converted via `degrade()` after being configured in the required mode.
+//!
+//! # Example
+//! ```
+//! let mut leds = [
+//!     pins.gpio2.into_push_pull_output().degrade(),
+//!     pins.gpio4.into_push_pull_output().degrade(),
+//!     pins.gpio5.into_push_pull_output().degrade(),
+//! ];
+//! for led in leds.iter_mut() {
+//!     led.set_high().unwrap();
+//! }
+//! ```
+
+use core::convert::Infallible;
+use core::marker::PhantomData;
+
+use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+
+use super::{
+    interrupt, pad, DriveStrength, Event, Input, InterruptHandler, MatrixInputPin, MatrixOutputPin,
+    OpenDrain, Output, Pin, SleepConfig, WakeupLevel,
+};
+use crate::esp32::GPIO;
+
+/// Set the output level of a pin
+pub(super) fn set_output_level(pin_number: u8, high: bool) {
+    let gpio = unsafe { &*GPIO::ptr() };
+    // NOTE(unsafe) atomic write to a stateless register
+    unsafe {
+        match (pin_number < 32, high) {
+            (true, true) => gpio.out_w1ts.write(|w| w.bits(1 << pin_number)),
+            (true, false) => gpio.out_w1tc.write(|w| w.bits(1 << pin_number)),
+            (false, true) => gpio.out1_w1ts.write(|w| w.bits(1 << (pin_number - 32))),
+            (false, false) => gpio.out1_w1tc.write(|w| w.bits(1 << (pin_number - 32))),
+        }
+    }
+}
+
+/// Return true if the output level of a pin is set high
+pub(super) fn is_output_level_high(pin_number: u8) -> bool {
+    let gpio = unsafe { &*GPIO::ptr() };
+    if pin_number < 32 {
+        gpio.out.read().bits() & (1 << pin_number) != 0
+    } else {
+        gpio.out1.read().bits() & (1 << (pin_number - 32)) != 0
+    }
+}
+
+/// Return true if the input level of a pin is high
+pub(super) fn is_input_level_high(pin_number: u8) -> bool {
+    let gpio = unsafe { &*GPIO::ptr() };
+    if pin_number < 32 {
+        gpio.in_.read().bits() & (1 << pin_number) != 0
+    } else {
+        gpio.in1.read().bits() & (1 << (pin_number - 32)) != 0
+    }
+}
+
+/// Enable or disable the output driver of a pin
+pub(super) fn enable_output(pin_number: u8, enable: bool) {
+    let gpio = unsafe { &*GPIO::ptr() };
+    // NOTE(unsafe) atomic write to a stateless register
+    unsafe {
+        match (pin_number < 32, enable) {
+            (true, true) => gpio.enable_w1ts.write(|w| w.bits(1 << pin_number)),
+            (true, false) => gpio.enable_w1tc.write(|w| w.bits(1 << pin_number)),
+            (false, true) => gpio.enable1_w1ts.write(|w| w.bits(1 << (pin_number - 32))),
+            (false, false) => gpio.enable1_w1tc.write(|w| w.bits(1 << (pin_number - 32))),
+        }
+    }
+}
+
+/// Pin with the GPIO number erased from the type
+pub struct AnyPin<MODE> {
+    pin_number: u8,
+    _mode: PhantomData<MODE>,
+}
+
+impl<MODE> AnyPin<MODE> {
+    pub(super) fn new(pin_number: u8) -> Self {
+        AnyPin {
+            pin_number,
+            _mode: PhantomData,
+        }
+    }
+
+    /// Set the configuration applied while the chip is in light sleep
+    pub fn set_sleep_config(&mut self, config: Option<SleepConfig>) -> &mut Self {
+        pad::set_sleep_config(self.pin_number, config);
+        self
+    }
+}
+
+impl<MODE> AnyPin<Output<MODE>> {
+    /// Set the output drive strength
+    pub fn set_drive_strength(&mut self, strength: DriveStrength) -> &mut Self {
+        pad::set_drive_strength(self.pin_number, strength);
+        self
+    }
+
+    /// Output drive strength
+    pub fn drive_strength(&self) -> DriveStrength {
+        pad::drive_strength(self.pin_number)
+    }
+}
+
+impl<MODE> Pin for AnyPin<MODE> {
+    fn pin_number(&self) -> u8 {
+        self.pin_number
+    }
+}
+
+impl<MODE> InputPin for AnyPin<Input<MODE>> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(is_input_level_high(self.pin_number))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.is_high()?)
+    }
+}
+
+impl<MODE> OutputPin for AnyPin<Output<MODE>> {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        set_output_level(self.pin_number, true);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        set_output_level(self.pin_number, false);
+        Ok(())
+    }
+}
+
+impl<MODE> StatefulOutputPin for AnyPin<Output<MODE>> {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(is_output_level_high(self.pin_number))
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.is_set_high()?)
+    }
+}
+
+impl<MODE> ToggleableOutputPin for AnyPin<Output<MODE>> {
+    type Error = Infallible;
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        if self.is_set_high()? {
+            Ok(self.set_low()?)
+        } else {
+            Ok(self.set_high()?)
+        }
+    }
+}
+
+impl<MODE> MatrixInputPin for AnyPin<Input<MODE>> {}
+
+impl MatrixInputPin for AnyPin<Output<OpenDrain>> {}
+
+impl<MODE> MatrixOutputPin for AnyPin<Output<MODE>> {}
+
+impl<MODE> AnyPin<Input<MODE>> {
+    /// Start listening for an interrupt event on the current core
+    pub fn listen(&mut self, event: Event) {
+        self.listen_on_core(event, crate::get_core());
+    }
+
+    /// Start listening for an interrupt event on the given core
+    pub fn listen_on_core(&mut self, event: Event, core: crate::Core) {
+        interrupt::listen(self.pin_number, event, core);
+    }
+
+    /// Stop listening for interrupt events and clear the interrupt
+    pub fn unlisten(&mut self) {
+        interrupt::unlisten(self.pin_number);
+    }
+
+    /// Return true if the interrupt of the pin is set
+    pub fn is_interrupt_set(&self) -> bool {
+        interrupt::is_interrupt_set(self.pin_number)
+    }
+
+    /// Clear the interrupt of the pin
+    pub fn clear_interrupt(&mut self) {
+        interrupt::clear_interrupt(self.pin_number);
+    }
+
+    /// Set or remove the handler called when the interrupt of the pin is set
+    pub fn set_interrupt_handler(&mut self, handler: Option<InterruptHandler>) {
+        interrupt::set_interrupt_handler(self.pin_number, handler);
+    }
+
+    /// Enable wakeup from light sleep when the pin reaches the given level
+    pub fn enable_wakeup(&mut self, level: WakeupLevel) {
+        interrupt::enable_wakeup(self.pin_number, level);
+    }
+
+    /// Disable wakeup from light sleep
+    pub fn disable_wakeup(&mut self) {
+        interrupt::disable_wakeup(self.pin_number);
+    }
+}
@@ -0,0 +1,155 @@
+//! Pin with runtime selectable direction
+//!
+//! A [Flex] pin can switch between input, push pull output and open drain output at runtime,
+//! e.g. for bit-banged bidirectional protocols. The input is always enabled, so the level of the
+//! pin can also be read while it is an output.
+//!
+//! # Example
+//! ```
+//! let mut data = pins.gpio4.into_flex();
+//! data.set_pull(Pull::Up);
+//!
+//! data.set_low().unwrap();
+//! data.set_as_output();
+//! // ...
+//! data.set_as_input();
+//! let bit = data.is_high().unwrap();
+//! ```
+
+use core::convert::Infallible;
+
+use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+
+use super::any_pin::{enable_output, is_input_level_high, is_output_level_high, set_output_level};
+use super::{
+    modify_iomux_register, modify_pin_register, pad, DriveStrength, MatrixInputPin,
+    MatrixOutputPin, Pin, SleepConfig, IO_MUX_FUN_WPD, IO_MUX_FUN_WPU, PAD_DRIVER_BIT,
+};
+
+/// Pull resistor configuration
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Pull {
+    /// No pull resistor
+    None,
+    /// Pull up resistor
+    Up,
+    /// Pull down resistor
+    Down,
+}
+
+/// Pin with runtime selectable direction
+pub struct Flex {
+    pin_number: u8,
+}
+
+impl Flex {
+    pub(super) fn new(pin_number: u8) -> Self {
+        Flex { pin_number }
+    }
+
+    /// Disable the output driver
+    pub fn set_as_input(&mut self) -> &mut Self {
+        enable_output(self.pin_number, false);
+        self
+    }
+
+    /// Enable the output driver in push pull mode
+    pub fn set_as_output(&mut self) -> &mut Self {
+        modify_pin_register(self.pin_number, PAD_DRIVER_BIT, 0);
+        enable_output(self.pin_number, true);
+        self
+    }
+
+    /// Enable the output driver in open drain mode
+    pub fn set_as_open_drain(&mut self) -> &mut Self {
+        modify_pin_register(self.pin_number, 0, PAD_DRIVER_BIT);
+        enable_output(self.pin_number, true);
+        self
+    }
+
+    /// Select the pull resistor
+    pub fn set_pull(&mut self, pull: Pull) -> &mut Self {
+        let set = match pull {
+            Pull::None => 0,
+            Pull::Up => IO_MUX_FUN_WPU,
+            Pull::Down => IO_MUX_FUN_WPD,
+        };
+        modify_iomux_register(self.pin_number, IO_MUX_FUN_WPU | IO_MUX_FUN_WPD, set);
+        self
+    }
+
+    /// Set the output drive strength
+    pub fn set_drive_strength(&mut self, strength: DriveStrength) -> &mut Self {
+        pad::set_drive_strength(self.pin_number, strength);
+        self
+    }
+
+    /// Output drive strength
+    pub fn drive_strength(&self) -> DriveStrength {
+        pad::drive_strength(self.pin_number)
+    }
+
+    /// Set the configuration applied while the chip is in light sleep
+    pub fn set_sleep_config(&mut self, config: Option<SleepConfig>) -> &mut Self {
+        pad::set_sleep_config(self.pin_number, config);
+        self
+    }
+}
+
+impl Pin for Flex {
+    fn pin_number(&self) -> u8 {
+        self.pin_number
+    }
+}
+
+impl InputPin for Flex {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(is_input_level_high(self.pin_number))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.is_high()?)
+    }
+}
+
+impl OutputPin for Flex {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        set_output_level(self.pin_number, true);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        set_output_level(self.pin_number, false);
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for Flex {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(is_output_level_high(self.pin_number))
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.is_set_high()?)
+    }
+}
+
+impl ToggleableOutputPin for Flex {
+    type Error = Infallible;
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        if self.is_set_high()? {
+            Ok(self.set_low()?)
+        } else {
+            Ok(self.set_high()?)
+        }
+    }
+}
+
+impl MatrixInputPin for Flex {}
+
+impl MatrixOutputPin for Flex {}
@@ -0,0 +1,167 @@
+//! GPIO interrupts
+//!
+//! The GPIO interrupt is handled by [handle_interrupt](super::handle_interrupt), which has to be
+//! called from the GPIO_INTR interrupt handler defined by the application. It clears the
+//! interrupts of the pins which triggered and calls the handlers set per pin via
+//! `set_interrupt_handler`.
+//!
+//! The interrupt of a pin is routed to the core selected when listening. The GPIO_INTR
+//! interrupt needs to be enabled on that core via
+//! [interrupt::enable](crate::interrupt::enable) or
+//! [interrupt::enable_with_priority](crate::interrupt::enable_with_priority).
+//!
+//! # Example
+//! ```
+//! #[interrupt]
+//! fn GPIO_INTR() {
+//!     gpio::handle_interrupt();
+//! }
+//!
+//! fn button_pressed() {
+//!     dprintln!("Button pressed");
+//! }
+//!
+//! let mut button = pins.gpio0.into_pull_up_input();
+//! button.set_interrupt_handler(Some(button_pressed));
+//! button.listen(Event::FallingEdge);
+//! interrupt::enable(Interrupt::GPIO_INTR).unwrap();
+//! ```
+
+use super::{modify_pin_register, WakeupLevel};
+use crate::esp32::GPIO;
+use crate::Core;
+
+/// Number of GPIO pins (including the non-existing pins 20, 24 and 28-31)
+const GPIO_COUNT: usize = 40;
+
+// Bits of the GPIO_PINx registers
+const PIN_INT_TYPE_SHIFT: u32 = 7;
+const PIN_INT_TYPE_MASK: u32 = 0x7 << PIN_INT_TYPE_SHIFT;
+const PIN_WAKEUP_ENABLE: u32 = 1 << 10;
+const PIN_INT_ENA_MASK: u32 = 0x1f << 13;
+const PIN_INT_ENA_APP: u32 = 1 << 13;
+const PIN_INT_ENA_PRO: u32 = 1 << 15;
+
+/// GPIO interrupt event
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Event {
+    /// Rising edge
+    RisingEdge,
+    /// Falling edge
+    FallingEdge,
+    /// Rising or falling edge
+    AnyEdge,
+    /// Low level
+    LowLevel,
+    /// High level
+    HighLevel,
+}
+
+impl Event {
+    fn int_type(self) -> u32 {
+        match self {
+            Event::RisingEdge => 1,
+            Event::FallingEdge => 2,
+            Event::AnyEdge => 3,
+            Event::LowLevel => 4,
+            Event::HighLevel => 5,
+        }
+    }
+}
+
+/// Handler called from the GPIO interrupt handler when the interrupt of the pin is set
+pub type InterruptHandler = fn();
+
+static HANDLERS: spin::Mutex<[Option<InterruptHandler>; GPIO_COUNT]> =
+    spin::Mutex::new([None; GPIO_COUNT]);
+
+/// Enable the interrupt of a pin for the given event on the given core
+pub(super) fn listen(pin_number: u8, event: Event, core: Core) {
+    let enable = match core {
+        Core::PRO => PIN_INT_ENA_PRO,
+        Core::APP => PIN_INT_ENA_APP,
+    };
+    modify_pin_register(
+        pin_number,
+        PIN_INT_TYPE_MASK | PIN_INT_ENA_MASK,
+        event.int_type() << PIN_INT_TYPE_SHIFT | enable,
+    );
+}
+
+/// Disable the interrupt of a pin
+pub(super) fn unlisten(pin_number: u8) {
+    modify_pin_register(pin_number, PIN_INT_TYPE_MASK | PIN_INT_ENA_MASK, 0);
+    clear_interrupt(pin_number);
+}
+
+/// Enable wakeup from light sleep when the pin reaches the given level
+///
+/// This replaces the interrupt event with the corresponding level event.
+pub(super) fn enable_wakeup(pin_number: u8, level: WakeupLevel) {
+    let event = match level {
+        WakeupLevel::Low => Event::LowLevel,
+        WakeupLevel::High => Event::HighLevel,
+    };
+    modify_pin_register(
+        pin_number,
+        PIN_INT_TYPE_MASK,
+        event.int_type() << PIN_INT_TYPE_SHIFT | PIN_WAKEUP_ENABLE,
+    );
+}
+
+/// Disable wakeup from light sleep
+pub(super) fn disable_wakeup(pin_number: u8) {
+    modify_pin_register(pin_number, PIN_WAKEUP_ENABLE, 0);
+}
+
+pub(super) fn is_interrupt_set(pin_number: u8) -> bool {
+    let gpio = unsafe { &*GPIO::ptr() };
+    if pin_number < 32 {
+        gpio.status.read().bits() & (1 << pin_number) != 0
+    } else {
+        gpio.status1.read().bits() & (1 << (pin_number - 32)) != 0
+    }
+}
+
+pub(super) fn clear_interrupt(pin_number: u8) {
+    let gpio = unsafe { &*GPIO::ptr() };
+    if pin_number < 32 {
+        gpio.status_w1tc
+            .write(|w| unsafe { w.bits(1 << pin_number) });
+    } else {
+        gpio.status1_w1tc
+            .write(|w| unsafe { w.bits(1 << (pin_number - 32)) });
+    }
+}
+
+pub(super) fn set_interrupt_handler(pin_number: u8, handler: Option<InterruptHandler>) {
+    xtensa_lx6_rt::interrupt::free(|_| HANDLERS.lock()[pin_number as usize] = handler);
+}
+
+/// Handle the GPIO interrupt
+///
+/// Needs to be called from the GPIO_INTR interrupt handler on each core listening to pins.
+pub fn handle_interrupt() {
+    let gpio = unsafe { &*GPIO::ptr() };
+
+    // only handle the pins routed to this core
+    let (status, status1) = match crate::get_core() {
+        Core::PRO => (gpio.pcpu_int.read().bits(), gpio.pcpu_int1.read().bits()),
+        Core::APP => (gpio.acpu_int.read().bits(), gpio.acpu_int1.read().bits()),
+    };
+
+    // clear before calling the handlers, so edges occurring during handling are not lost
+    gpio.status_w1tc.write(|w| unsafe { w.bits(status) });
+    gpio.status1_w1tc.write(|w| unsafe { w.bits(status1) });
+
+    let handlers = *HANDLERS.lock();
+
+    let mut pending = status as u64 | (status1 as u64) << 32;
+    while pending != 0 {
+        let pin_number = pending.trailing_zeros() as usize;
+        if let Some(Some(handler)) = handlers.get(pin_number) {
+            handler();
+        }
+        pending &= !(1 << pin_number);
+    }
+}
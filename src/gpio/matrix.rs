@@ -0,0 +1,339 @@
+//! GPIO matrix
+//!
+//! The GPIO matrix can route any peripheral input signal from any pin and any peripheral output
+//! signal to any output capable pin (GPIO34-39 are input only). Compared to the IO_MUX
+//! alternate functions this adds a small delay, which limits the maximum frequency of e.g. SPI.
+//!
+//! Pins implementing [MatrixInputPin] or [MatrixOutputPin] can be connected to the
+//! [InputSignal]s and [OutputSignal]s. This allows drivers to accept any pin for a signal.
+//!
+//! # Example
+//! ```
+//! let mut led = pins.gpio2.into_push_pull_output();
+//! led.connect_peripheral_to_output(OutputSignal::LEDC_HS_SIG0);
+//! ```
+
+use super::Pin;
+use crate::esp32::GPIO;
+
+/// Peripheral input signals
+///
+/// Only the most commonly used signals are listed, for the full list see the technical reference
+/// manual.
+#[allow(non_camel_case_types)]
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum InputSignal {
+    SPICLK = 0,
+    SPIQ = 1,
+    SPID = 2,
+    SPIHD = 3,
+    SPIWP = 4,
+    SPICS0 = 5,
+    SPICS1 = 6,
+    SPICS2 = 7,
+    HSPICLK = 8,
+    HSPIQ = 9,
+    HSPID = 10,
+    HSPICS0 = 11,
+    HSPIHD = 12,
+    HSPIWP = 13,
+    U0RXD = 14,
+    U0CTS = 15,
+    U0DSR = 16,
+    U1RXD = 17,
+    U1CTS = 18,
+    I2S0O_BCK = 23,
+    I2S1O_BCK = 24,
+    I2S0O_WS = 25,
+    I2S1O_WS = 26,
+    I2S0I_BCK = 27,
+    I2S0I_WS = 28,
+    I2CEXT0_SCL = 29,
+    I2CEXT0_SDA = 30,
+    PWM0_SYNC0 = 31,
+    PWM0_SYNC1 = 32,
+    PWM0_SYNC2 = 33,
+    PWM0_F0 = 34,
+    PWM0_F1 = 35,
+    PWM0_F2 = 36,
+    PCNT_SIG_CH0_IN0 = 39,
+    PCNT_SIG_CH1_IN0 = 40,
+    PCNT_CTRL_CH0_IN0 = 41,
+    PCNT_CTRL_CH1_IN0 = 42,
+    PCNT_SIG_CH0_IN1 = 43,
+    PCNT_SIG_CH1_IN1 = 44,
+    PCNT_CTRL_CH0_IN1 = 45,
+    PCNT_CTRL_CH1_IN1 = 46,
+    PCNT_SIG_CH0_IN2 = 47,
+    PCNT_SIG_CH1_IN2 = 48,
+    PCNT_CTRL_CH0_IN2 = 49,
+    PCNT_CTRL_CH1_IN2 = 50,
+    PCNT_SIG_CH0_IN3 = 51,
+    PCNT_SIG_CH1_IN3 = 52,
+    PCNT_CTRL_CH0_IN3 = 53,
+    PCNT_CTRL_CH1_IN3 = 54,
+    PCNT_SIG_CH0_IN4 = 55,
+    PCNT_SIG_CH1_IN4 = 56,
+    PCNT_CTRL_CH0_IN4 = 57,
+    PCNT_CTRL_CH1_IN4 = 58,
+    HSPICS1 = 61,
+    HSPICS2 = 62,
+    VSPICLK = 63,
+    VSPIQ = 64,
+    VSPID = 65,
+    VSPIHD = 66,
+    VSPIWP = 67,
+    VSPICS0 = 68,
+    VSPICS1 = 69,
+    VSPICS2 = 70,
+    PCNT_SIG_CH0_IN5 = 71,
+    PCNT_SIG_CH1_IN5 = 72,
+    PCNT_CTRL_CH0_IN5 = 73,
+    PCNT_CTRL_CH1_IN5 = 74,
+    PCNT_SIG_CH0_IN6 = 75,
+    PCNT_SIG_CH1_IN6 = 76,
+    PCNT_CTRL_CH0_IN6 = 77,
+    PCNT_CTRL_CH1_IN6 = 78,
+    PCNT_SIG_CH0_IN7 = 79,
+    PCNT_SIG_CH1_IN7 = 80,
+    PCNT_CTRL_CH0_IN7 = 81,
+    PCNT_CTRL_CH1_IN7 = 82,
+    RMT_SIG0 = 83,
+    RMT_SIG1 = 84,
+    RMT_SIG2 = 85,
+    RMT_SIG3 = 86,
+    RMT_SIG4 = 87,
+    RMT_SIG5 = 88,
+    RMT_SIG6 = 89,
+    RMT_SIG7 = 90,
+    I2CEXT1_SCL = 95,
+    I2CEXT1_SDA = 96,
+    U2RXD = 198,
+    U2CTS = 199,
+}
+
+/// Peripheral output signals
+///
+/// Only the most commonly used signals are listed, for the full list see the technical reference
+/// manual.
+#[allow(non_camel_case_types)]
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum OutputSignal {
+    SPICLK = 0,
+    SPIQ = 1,
+    SPID = 2,
+    SPIHD = 3,
+    SPIWP = 4,
+    SPICS0 = 5,
+    SPICS1 = 6,
+    SPICS2 = 7,
+    HSPICLK = 8,
+    HSPIQ = 9,
+    HSPID = 10,
+    HSPICS0 = 11,
+    HSPIHD = 12,
+    HSPIWP = 13,
+    U0TXD = 14,
+    U0RTS = 15,
+    U0DTR = 16,
+    U1TXD = 17,
+    U1RTS = 18,
+    I2S0O_BCK = 23,
+    I2S1O_BCK = 24,
+    I2S0O_WS = 25,
+    I2S1O_WS = 26,
+    I2S0I_BCK = 27,
+    I2S0I_WS = 28,
+    I2CEXT0_SCL = 29,
+    I2CEXT0_SDA = 30,
+    PWM0_0A = 32,
+    PWM0_0B = 33,
+    PWM0_1A = 34,
+    PWM0_1B = 35,
+    PWM0_2A = 36,
+    PWM0_2B = 37,
+    HSPICS1 = 61,
+    HSPICS2 = 62,
+    VSPICLK = 63,
+    VSPIQ = 64,
+    VSPID = 65,
+    VSPIHD = 66,
+    VSPIWP = 67,
+    VSPICS0 = 68,
+    VSPICS1 = 69,
+    VSPICS2 = 70,
+    LEDC_HS_SIG0 = 71,
+    LEDC_HS_SIG1 = 72,
+    LEDC_HS_SIG2 = 73,
+    LEDC_HS_SIG3 = 74,
+    LEDC_HS_SIG4 = 75,
+    LEDC_HS_SIG5 = 76,
+    LEDC_HS_SIG6 = 77,
+    LEDC_HS_SIG7 = 78,
+    LEDC_LS_SIG0 = 79,
+    LEDC_LS_SIG1 = 80,
+    LEDC_LS_SIG2 = 81,
+    LEDC_LS_SIG3 = 82,
+    LEDC_LS_SIG4 = 83,
+    LEDC_LS_SIG5 = 84,
+    LEDC_LS_SIG6 = 85,
+    LEDC_LS_SIG7 = 86,
+    RMT_SIG0 = 87,
+    RMT_SIG1 = 88,
+    RMT_SIG2 = 89,
+    RMT_SIG3 = 90,
+    RMT_SIG4 = 91,
+    RMT_SIG5 = 92,
+    RMT_SIG6 = 93,
+    RMT_SIG7 = 94,
+    I2CEXT1_SCL = 95,
+    I2CEXT1_SDA = 96,
+    U2TXD = 198,
+    U2RTS = 199,
+}
+
+// Bits of the GPIO_FUNCx_IN_SEL_CFG registers
+const IN_SEL_INV: u32 = 1 << 6;
+const IN_SEL_SIG_IN_SEL: u32 = 1 << 7;
+/// Input selection of a constant low level instead of a pin
+const IN_SEL_CONSTANT_LOW: u32 = 0x30;
+/// Input selection of a constant high level instead of a pin
+const IN_SEL_CONSTANT_HIGH: u32 = 0x38;
+
+// Bits of the GPIO_FUNCx_OUT_SEL_CFG registers
+const OUT_SEL_INV: u32 = 1 << 9;
+const OUT_SEL_OEN_SEL: u32 = 1 << 10;
+const OUT_SEL_OEN_INV: u32 = 1 << 11;
+/// Output selection of the GPIO_OUT register instead of a peripheral signal
+pub(super) const OUT_SEL_GPIO: u32 = 0x100;
+
+fn write_input_selection(signal: InputSignal, bits: u32) {
+    let gpio = unsafe { &*GPIO::ptr() };
+    // NOTE(unsafe) the GPIO_FUNCx_IN_SEL_CFG registers are consecutive and indexed by signal
+    unsafe { core::ptr::write_volatile(gpio.func0_in_sel_cfg.as_ptr().add(signal as usize), bits) };
+}
+
+fn write_output_selection(pin_number: u8, bits: u32) {
+    let gpio = unsafe { &*GPIO::ptr() };
+    // NOTE(unsafe) the GPIO_FUNCx_OUT_SEL_CFG registers are consecutive and indexed by pin
+    unsafe {
+        core::ptr::write_volatile(
+            gpio.func0_out_sel_cfg.as_ptr().add(pin_number as usize),
+            bits,
+        )
+    };
+}
+
+/// Route a peripheral input signal from a pin via the GPIO matrix
+pub(crate) fn connect_input_signal(signal: InputSignal, pin_number: u8) {
+    connect_input_signal_with_options(signal, pin_number, false);
+}
+
+fn connect_input_signal_with_options(signal: InputSignal, pin_number: u8, invert: bool) {
+    let invert = if invert { IN_SEL_INV } else { 0 };
+    write_input_selection(signal, IN_SEL_SIG_IN_SEL | invert | pin_number as u32);
+}
+
+/// Disconnect a peripheral input signal from its pin, the peripheral sees a constant level
+pub(crate) fn disconnect_input_signal(signal: InputSignal, level: bool) {
+    let constant = if level {
+        IN_SEL_CONSTANT_HIGH
+    } else {
+        IN_SEL_CONSTANT_LOW
+    };
+    write_input_selection(signal, IN_SEL_SIG_IN_SEL | constant);
+}
+
+/// Route a peripheral output signal to a pin via the GPIO matrix
+///
+/// The output enable is controlled by the peripheral.
+pub(crate) fn connect_output_signal(pin_number: u8, signal: OutputSignal) {
+    connect_output_signal_with_options(pin_number, signal, false, false, false);
+}
+
+/// With `enable_from_gpio` the output enable is controlled by the GPIO_ENABLE register instead
+/// of the peripheral.
+fn connect_output_signal_with_options(
+    pin_number: u8,
+    signal: OutputSignal,
+    invert: bool,
+    invert_enable: bool,
+    enable_from_gpio: bool,
+) {
+    let mut bits = signal as u32;
+    if invert {
+        bits |= OUT_SEL_INV;
+    }
+    if invert_enable {
+        bits |= OUT_SEL_OEN_INV;
+    }
+    if enable_from_gpio {
+        bits |= OUT_SEL_OEN_SEL;
+    }
+    write_output_selection(pin_number, bits);
+}
+
+/// Disconnect the peripheral output signal from a pin, the pin is driven by the GPIO_OUT register
+pub(crate) fn disconnect_output_signal(pin_number: u8) {
+    write_output_selection(pin_number, OUT_SEL_GPIO);
+}
+
+/// Pin which can be connected to peripheral input signals via the GPIO matrix
+pub trait MatrixInputPin: Pin {
+    /// Connect the pin to a peripheral input signal
+    fn connect_input_to_peripheral(&mut self, signal: InputSignal) -> &mut Self {
+        self.connect_input_to_peripheral_with_options(signal, false)
+    }
+
+    /// Connect the pin to a peripheral input signal, optionally inverting the signal
+    fn connect_input_to_peripheral_with_options(
+        &mut self,
+        signal: InputSignal,
+        invert: bool,
+    ) -> &mut Self {
+        connect_input_signal_with_options(signal, self.pin_number(), invert);
+        self
+    }
+
+    /// Disconnect a peripheral input signal from the pin, the peripheral sees a low level
+    fn disconnect_input_from_peripheral(&mut self, signal: InputSignal) -> &mut Self {
+        disconnect_input_signal(signal, false);
+        self
+    }
+}
+
+/// Pin which can be driven by peripheral output signals via the GPIO matrix
+pub trait MatrixOutputPin: Pin {
+    /// Connect a peripheral output signal to the pin, the output enable is controlled by the
+    /// peripheral
+    fn connect_peripheral_to_output(&mut self, signal: OutputSignal) -> &mut Self {
+        self.connect_peripheral_to_output_with_options(signal, false, false, false)
+    }
+
+    /// Connect a peripheral output signal to the pin
+    ///
+    /// The output and output enable can be inverted. With `enable_from_gpio` the output is
+    /// always enabled instead of being controlled by the peripheral.
+    fn connect_peripheral_to_output_with_options(
+        &mut self,
+        signal: OutputSignal,
+        invert: bool,
+        invert_enable: bool,
+        enable_from_gpio: bool,
+    ) -> &mut Self {
+        connect_output_signal_with_options(
+            self.pin_number(),
+            signal,
+            invert,
+            invert_enable,
+            enable_from_gpio,
+        );
+        self
+    }
+
+    /// Disconnect the peripheral output signal, the pin is driven as a normal GPIO output again
+    fn disconnect_peripheral_from_output(&mut self) -> &mut Self {
+        disconnect_output_signal(self.pin_number());
+        self
+    }
+}
@@ -4,6 +4,36 @@ use {
     embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin},
 };
 
+mod any_pin;
+mod flex;
+mod interrupt;
+mod matrix;
+mod pad;
+mod pin_group;
+mod rtc_io;
+
+pub use any_pin::AnyPin;
+pub use flex::{Flex, Pull};
+pub use interrupt::{handle_interrupt, Event, InterruptHandler};
+pub(crate) use matrix::{connect_input_signal, connect_output_signal};
+pub use matrix::{InputSignal, MatrixInputPin, MatrixOutputPin, OutputSignal};
+pub use pad::{set_deep_sleep_hold, DriveStrength, SleepConfig};
+pub use pin_group::PinGroup;
+pub use rtc_io::{
+    disable_ext0_wakeup, disable_ext1_wakeup, enable_ext0_wakeup, enable_ext1_wakeup,
+    ext1_wakeup_status, Ext1WakeupMode, RTCPin, WakeupLevel,
+};
+pub(crate) use rtc_io::{
+    is_xtal32k_powered_up, xtal32k_bootstrap, xtal32k_disable, xtal32k_enable,
+};
+
+/// GPIO error
+#[derive(Debug)]
+pub enum Error {
+    /// The pins of a [PinGroup] need to be in the same bank (GPIO0-31 or GPIO32-39)
+    DifferentBanks,
+}
+
 /// Extension trait to split a GPIO peripheral in independent pins and registers
 pub trait GpioExt {
     /// The to split the GPIO into
@@ -13,6 +43,52 @@ pub trait GpioExt {
     fn split(self) -> Self::Parts;
 }
 
+/// Functionality common to all pins, independent of their mode
+pub trait Pin {
+    /// GPIO number of the pin
+    fn pin_number(&self) -> u8;
+}
+
+/// Bit in the GPIO_PINx registers selecting open drain output
+const PAD_DRIVER_BIT: u32 = 1 << 2;
+
+// Bits of the IO_MUX_x_REG registers
+const IO_MUX_FUN_WPD: u32 = 1 << 7;
+const IO_MUX_FUN_WPU: u32 = 1 << 8;
+const IO_MUX_FUN_IE: u32 = 1 << 9;
+const IO_MUX_MCU_SEL_SHIFT: u32 = 12;
+const IO_MUX_MCU_SEL_MASK: u32 = 0x7 << IO_MUX_MCU_SEL_SHIFT;
+/// Function selecting the GPIO matrix
+const IO_MUX_MCU_SEL_GPIO: u32 = 2 << IO_MUX_MCU_SEL_SHIFT;
+
+/// Offsets of the IO_MUX_x_REG registers indexed by GPIO number (0 for non-existing GPIOs)
+const IO_MUX_OFFSETS: [usize; 40] = [
+    0x44, 0x88, 0x40, 0x84, 0x48, 0x6c, 0x60, 0x64, 0x68, 0x54, 0x58, 0x5c, 0x34, 0x38, 0x30, 0x3c,
+    0x4c, 0x50, 0x70, 0x74, 0x78, 0x7c, 0x80, 0x8c, 0, 0x24, 0x28, 0x2c, 0, 0, 0, 0, 0x1c, 0x20,
+    0x14, 0x18, 0x04, 0x08, 0x0c, 0x10,
+];
+
+/// Read-modify-write of the GPIO_PINx register of a pin
+fn modify_pin_register(pin_number: u8, clear: u32, set: u32) {
+    let gpio = unsafe { &*GPIO::ptr() };
+    // NOTE(unsafe) the GPIO_PINx registers are consecutive and indexed by pin
+    let reg = unsafe { gpio.pin0.as_ptr().add(pin_number as usize) };
+    xtensa_lx6_rt::interrupt::free(|_| unsafe {
+        core::ptr::write_volatile(reg, core::ptr::read_volatile(reg) & !clear | set)
+    });
+}
+
+/// Read-modify-write of the IO_MUX_x_REG register of a pin
+fn modify_iomux_register(pin_number: u8, clear: u32, set: u32) {
+    // NOTE(unsafe) the offset is within the IO_MUX register block
+    let reg = unsafe {
+        (IO_MUX::ptr() as *const u8).add(IO_MUX_OFFSETS[pin_number as usize]) as *mut u32
+    };
+    xtensa_lx6_rt::interrupt::free(|_| unsafe {
+        core::ptr::write_volatile(reg, core::ptr::read_volatile(reg) & !clear | set)
+    });
+}
+
 /// Input mode (type state)
 pub struct Input<MODE> {
     _mode: PhantomData<MODE>,
@@ -41,6 +117,16 @@ pub struct Output<MODE> {
     _mode: PhantomData<MODE>,
 }
 
+/// RTC IO input mode, functional during deep sleep (type state)
+pub struct RTCInput<MODE> {
+    _mode: PhantomData<MODE>,
+}
+
+/// RTC IO output mode, functional during deep sleep (type state)
+pub struct RTCOutput<MODE> {
+    _mode: PhantomData<MODE>,
+}
+
 /// Alternate function
 pub struct Alternate<MODE> {
     _mode: PhantomData<MODE>,
@@ -173,6 +259,11 @@ macro_rules! impl_output {
                 }
             }
 
+            impl<MODE> MatrixOutputPin for $pxi<Output<MODE>> {}
+
+            // input is enabled for open drain outputs, e.g. for I2C
+            impl MatrixInputPin for $pxi<Output<OpenDrain>> {}
+
             impl<MODE> ToggleableOutputPin for $pxi<Output<MODE>> {
                 type Error = Infallible;
 
@@ -193,6 +284,7 @@ macro_rules! impl_output {
 
                     gpio.$en.modify(|_, w| unsafe  { w.bits(0x1 << $i) });
                     gpio.$funcXout.modify(|_, w| unsafe { w.bits(0x100) });
+                    gpio.$pin.modify(|r, w| unsafe { w.bits(r.bits() & !PAD_DRIVER_BIT) });
 
                     iomux.$iomux.modify(|_, w| unsafe { w.mcu_sel().bits(0b10) });
                     iomux.$iomux.modify(|_, w| w.fun_wpd().set_bit());
@@ -207,13 +299,46 @@ macro_rules! impl_output {
 
                     gpio.$en.modify(|_, w| unsafe  { w.bits(0x1 << $i) });
                     gpio.$funcXout.modify(|_, w| unsafe { w.bits(0x100) });
+                    gpio.$pin.modify(|r, w| unsafe { w.bits(r.bits() | PAD_DRIVER_BIT) });
 
                     iomux.$iomux.modify(|_, w| unsafe { w.mcu_sel().bits(0b10) });
+                    // enable input, so the actual level of the line can be read (e.g. for I2C)
+                    iomux.$iomux.modify(|_, w| w.fun_ie().set_bit());
                     iomux.$iomux.modify(|_, w| w.fun_wpd().clear_bit());
                     iomux.$iomux.modify(|_, w| w.fun_wpu().clear_bit());
                     $pxi { _mode: PhantomData }
                 }
 
+                /// Set the output drive strength
+                pub fn set_drive_strength(&mut self, strength: DriveStrength) -> &mut Self {
+                    pad::set_drive_strength(self.pin_number(), strength);
+                    self
+                }
+
+                /// Output drive strength
+                pub fn drive_strength(&self) -> DriveStrength {
+                    pad::drive_strength(self.pin_number())
+                }
+
+                /// Convert into a pin with runtime selectable direction
+                ///
+                /// The pin starts as floating input.
+                pub fn into_flex(self) -> Flex {
+                    let gpio = unsafe{ &*GPIO::ptr() };
+                    self.disable_analog();
+
+                    gpio.$funcXout.modify(|_, w| unsafe { w.bits(0x100) });
+                    modify_iomux_register(
+                        self.pin_number(),
+                        IO_MUX_MCU_SEL_MASK | IO_MUX_FUN_WPD | IO_MUX_FUN_WPU,
+                        IO_MUX_MCU_SEL_GPIO | IO_MUX_FUN_IE,
+                    );
+
+                    let mut flex = Flex::new(self.pin_number());
+                    flex.set_as_input();
+                    flex
+                }
+
                 fn set_alternate(&self, n: u8) {
                     let gpio = unsafe{ &*GPIO::ptr() };
                     let iomux = unsafe{ &*IO_MUX::ptr() };
@@ -223,6 +348,8 @@ macro_rules! impl_output {
                     gpio.$funcXout.modify(|_, w| unsafe { w.bits(0x100) });
 
                     iomux.$iomux.modify(|_, w| unsafe { w.mcu_sel().bits(n) });
+                    // enable input, as alternate functions can also be inputs
+                    iomux.$iomux.modify(|_, w| w.fun_ie().set_bit());
                     iomux.$iomux.modify(|_, w| w.fun_wpd().clear_bit());
                     iomux.$iomux.modify(|_, w| w.fun_wpu().clear_bit());
                 }
@@ -373,6 +500,41 @@ macro_rules! impl_input {
             $sig_in_sel:ident, $func_in_sel:ident, $iomux:ident, $resistors:ident),)+
     ]) => {
         $(
+            impl<MODE> Pin for $pxi<MODE> {
+                fn pin_number(&self) -> u8 {
+                    $pin_num
+                }
+            }
+
+            impl<MODE> $pxi<MODE> {
+                /// Erase the GPIO number from the type
+                pub fn degrade(self) -> AnyPin<MODE> {
+                    AnyPin::new($pin_num)
+                }
+
+                /// Erase the GPIO number from the type, same as [degrade](Self::degrade)
+                pub fn downgrade(self) -> AnyPin<MODE> {
+                    self.degrade()
+                }
+
+                /// Hold (latch) the configuration and output level of the pad
+                ///
+                /// The pad stays held through reset and, for RTC pads or when enabled via
+                /// [set_deep_sleep_hold], deep sleep until hold is disabled.
+                pub fn set_hold(&mut self, enable: bool) -> &mut Self {
+                    self.hold_pad(enable);
+                    self
+                }
+
+                /// Set the configuration applied while the chip is in light sleep
+                ///
+                /// None uses the normal configuration during sleep.
+                pub fn set_sleep_config(&mut self, config: Option<SleepConfig>) -> &mut Self {
+                    pad::set_sleep_config($pin_num, config);
+                    self
+                }
+            }
+
             impl<MODE> InputPin for $pxi<Input<MODE>> {
                 type Error = Infallible;
 
@@ -385,6 +547,58 @@ macro_rules! impl_input {
                 }
             }
 
+            impl<MODE> MatrixInputPin for $pxi<Input<MODE>> {}
+
+            impl<MODE> $pxi<Input<MODE>> {
+                /// Start listening for an interrupt event on the current core
+                ///
+                /// See [listen_on_core](Self::listen_on_core).
+                pub fn listen(&mut self, event: Event) {
+                    self.listen_on_core(event, crate::get_core());
+                }
+
+                /// Start listening for an interrupt event on the given core
+                ///
+                /// The handler set via [set_interrupt_handler](Self::set_interrupt_handler) is
+                /// called from [handle_interrupt] in the GPIO_INTR interrupt on that core.
+                pub fn listen_on_core(&mut self, event: Event, core: crate::Core) {
+                    interrupt::listen($pin_num, event, core);
+                }
+
+                /// Stop listening for interrupt events and clear the interrupt
+                pub fn unlisten(&mut self) {
+                    interrupt::unlisten($pin_num);
+                }
+
+                /// Return true if the interrupt of the pin is set
+                pub fn is_interrupt_set(&self) -> bool {
+                    interrupt::is_interrupt_set($pin_num)
+                }
+
+                /// Clear the interrupt of the pin
+                pub fn clear_interrupt(&mut self) {
+                    interrupt::clear_interrupt($pin_num);
+                }
+
+                /// Set or remove the handler called when the interrupt of the pin is set
+                pub fn set_interrupt_handler(&mut self, handler: Option<InterruptHandler>) {
+                    interrupt::set_interrupt_handler($pin_num, handler);
+                }
+
+                /// Enable wakeup from light sleep when the pin reaches the given level
+                ///
+                /// This changes the interrupt event to the corresponding level event. GPIO
+                /// wakeup needs to be enabled in the sleep configuration as well.
+                pub fn enable_wakeup(&mut self, level: WakeupLevel) {
+                    interrupt::enable_wakeup($pin_num, level);
+                }
+
+                /// Disable wakeup from light sleep
+                pub fn disable_wakeup(&mut self) {
+                    interrupt::disable_wakeup($pin_num);
+                }
+            }
+
             impl<MODE> $pxi<MODE> {
                 pub fn into_floating_input(self) -> $pxi<Input<Floating>> {
                     let gpio = unsafe{ &*GPIO::ptr() };
@@ -472,6 +686,10 @@ macro_rules! impl_no_analog {
                 fn disable_analog(&self) {
                     /* No analog functionality on this pin, so nothing to do */
                 }
+
+                fn hold_pad(&self, enable: bool) {
+                    pad::set_digital_hold(self.pin_number(), enable);
+                }
             }
         )+
     };
@@ -480,7 +698,7 @@ macro_rules! impl_no_analog {
 macro_rules! impl_analog {
     ([
         $($pxi:ident: ($i:expr, $pin_reg:ident, $gpio_reg:ident, $mux_sel:ident, $fun_select:ident,
-          $pad_driver:ident, $in_enable:ident, $($rue:ident, $rde:ident)?),)+
+          $pad_driver:ident, $hold:ident, $in_enable:ident, $($rue:ident, $rde:ident)?),)+
     ]) => {
         $(
             impl<MODE> $pxi<MODE> {
@@ -522,6 +740,123 @@ macro_rules! impl_analog {
                     let rtcio = unsafe{ &*RTCIO::ptr() };
                     rtcio.$pin_reg.modify(|_,w| w.$mux_sel().clear_bit());
                 }
+
+                fn hold_pad(&self, enable: bool) {
+                    let rtcio = unsafe{ &*RTCIO::ptr() };
+                    rtcio.$pin_reg.modify(|_,w| w.$hold().bit(enable));
+                }
+
+                /// Connect the pin to the RTC IO, configured as input without pull resistors
+                fn set_rtc_input(&self) {
+                    let rtcio = unsafe{ &*RTCIO::ptr() };
+
+                    rtcio.$pin_reg.modify(|_,w| {
+                        w.$mux_sel().set_bit();
+                        // Select function "RTC function 1" (GPIO)
+                        unsafe { w.$fun_select().bits(0b00) }
+                    });
+
+                    rtcio.rtc_gpio_enable_w1tc.modify(|_,w| {
+                        unsafe { w.rtc_gpio_enable_w1tc().bits(1u32 << $i) }
+                    });
+
+                    rtcio.$pin_reg.modify(|_,w| w.$in_enable().set_bit());
+
+                    $(
+                        rtcio.$pin_reg.modify(|_,w| {
+                            w.$rue().clear_bit();
+                            w.$rde().clear_bit()
+                        });
+                    )?
+                }
+
+                /// Configure as input in the RTC domain, which stays functional in deep sleep
+                pub fn into_rtc_input(self) -> $pxi<RTCInput<Floating>> {
+                    self.set_rtc_input();
+                    $pxi { _mode: PhantomData }
+                }
+
+                $(
+                    /// Configure as input with pull up resistor in the RTC domain
+                    pub fn into_rtc_pull_up_input(self) -> $pxi<RTCInput<PullUp>> {
+                        self.set_rtc_input();
+                        unsafe{ &*RTCIO::ptr() }.$pin_reg.modify(|_,w| w.$rue().set_bit());
+                        $pxi { _mode: PhantomData }
+                    }
+
+                    /// Configure as input with pull down resistor in the RTC domain
+                    pub fn into_rtc_pull_down_input(self) -> $pxi<RTCInput<PullDown>> {
+                        self.set_rtc_input();
+                        unsafe{ &*RTCIO::ptr() }.$pin_reg.modify(|_,w| w.$rde().set_bit());
+                        $pxi { _mode: PhantomData }
+                    }
+
+                    /// Configure as push pull output in the RTC domain, which keeps driving
+                    /// the pin in deep sleep
+                    pub fn into_rtc_output(self) -> $pxi<RTCOutput<PushPull>> {
+                        let rtcio = unsafe{ &*RTCIO::ptr() };
+                        self.set_rtc_input();
+                        rtcio.$gpio_reg.modify(|_,w| w.$pad_driver().clear_bit());
+                        rtcio.rtc_gpio_enable_w1ts.modify(|_,w| {
+                            unsafe { w.rtc_gpio_enable_w1ts().bits(1u32 << $i) }
+                        });
+                        $pxi { _mode: PhantomData }
+                    }
+
+                    /// Configure as open drain output in the RTC domain, which keeps driving
+                    /// the pin in deep sleep
+                    pub fn into_rtc_open_drain_output(self) -> $pxi<RTCOutput<OpenDrain>> {
+                        let rtcio = unsafe{ &*RTCIO::ptr() };
+                        self.set_rtc_input();
+                        rtcio.$gpio_reg.modify(|_,w| w.$pad_driver().set_bit());
+                        rtcio.rtc_gpio_enable_w1ts.modify(|_,w| {
+                            unsafe { w.rtc_gpio_enable_w1ts().bits(1u32 << $i) }
+                        });
+                        $pxi { _mode: PhantomData }
+                    }
+                )?
+            }
+
+            impl<MODE> RTCPin for $pxi<RTCInput<MODE>> {
+                fn rtc_pin_number(&self) -> u8 {
+                    $i
+                }
+            }
+
+            impl<MODE> InputPin for $pxi<RTCInput<MODE>> {
+                type Error = Infallible;
+
+                fn is_high(&self) -> Result<bool, Self::Error> {
+                    Ok(rtc_io::is_input_level_high($i))
+                }
+
+                fn is_low(&self) -> Result<bool, Self::Error> {
+                    Ok(!self.is_high()?)
+                }
+            }
+
+            impl<MODE> OutputPin for $pxi<RTCOutput<MODE>> {
+                type Error = Infallible;
+
+                fn set_high(&mut self) -> Result<(), Self::Error> {
+                    rtc_io::set_output_level($i, true);
+                    Ok(())
+                }
+
+                fn set_low(&mut self) -> Result<(), Self::Error> {
+                    rtc_io::set_output_level($i, false);
+                    Ok(())
+                }
+            }
+
+            impl<MODE> StatefulOutputPin for $pxi<RTCOutput<MODE>> {
+                fn is_set_high(&self) -> Result<bool, Self::Error> {
+                    Ok(rtc_io::is_output_level_high($i))
+                }
+
+                fn is_set_low(&self) -> Result<bool, Self::Error> {
+                    Ok(!self.is_set_high()?)
+                }
             }
         )+
     }
@@ -533,22 +868,22 @@ impl_no_analog! {[
 ]}
 
 impl_analog! {[
-    Gpio36: (0, rtc_io_sensor_pads, rtc_gpio_pin0, rtc_io_sense1_mux_sel, rtc_io_sense1_fun_sel, rtc_gpio_pin0_pad_driver, rtc_io_sense1_fun_ie,),
-    Gpio37: (1, rtc_io_sensor_pads, rtc_gpio_pin1, rtc_io_sense2_mux_sel, rtc_io_sense2_fun_sel, rtc_gpio_pin1_pad_driver, rtc_io_sense2_fun_ie,),
-    Gpio38: (2, rtc_io_sensor_pads, rtc_gpio_pin2, rtc_io_sense3_mux_sel, rtc_io_sense3_fun_sel, rtc_gpio_pin2_pad_driver, rtc_io_sense3_fun_ie,),
-    Gpio39: (3, rtc_io_sensor_pads, rtc_gpio_pin3, rtc_io_sense4_mux_sel, rtc_io_sense4_fun_sel, rtc_gpio_pin3_pad_driver, rtc_io_sense4_fun_ie,),
-    Gpio34: (4, rtc_io_adc_pad, rtc_gpio_pin4, rtc_io_adc1_mux_sel, rtc_io_adc1_fun_sel, rtc_gpio_pin4_pad_driver, rtc_io_adc1_fun_ie,),
-    Gpio35: (5, rtc_io_adc_pad, rtc_gpio_pin5, rtc_io_adc2_mux_sel, rtc_io_adc2_fun_sel, rtc_gpio_pin5_pad_driver, rtc_io_adc1_fun_ie,),
-    Gpio25: (6, rtc_io_pad_dac1, rtc_gpio_pin6, rtc_io_pdac1_mux_sel, rtc_io_pdac1_fun_sel, rtc_gpio_pin6_pad_driver, rtc_io_pdac1_fun_ie, rtc_io_pdac1_rue, rtc_io_pdac1_rde),
-    Gpio26: (7, rtc_io_pad_dac2, rtc_gpio_pin7, rtc_io_pdac2_mux_sel, rtc_io_pdac2_fun_sel, rtc_gpio_pin7_pad_driver, rtc_io_pdac2_fun_ie, rtc_io_pdac2_rue, rtc_io_pdac2_rde),
-    Gpio33: (8, rtc_io_xtal_32k_pad, rtc_gpio_pin8, rtc_io_x32n_mux_sel, rtc_io_x32n_fun_sel, rtc_gpio_pin8_pad_driver, rtc_io_x32n_fun_ie, rtc_io_x32n_rue, rtc_io_x32n_rde),
-    Gpio32: (9, rtc_io_xtal_32k_pad, rtc_gpio_pin9, rtc_io_x32p_mux_sel, rtc_io_x32p_fun_sel, rtc_gpio_pin9_pad_driver, rtc_io_x32p_fun_ie, rtc_io_x32p_rue, rtc_io_x32p_rde),
-    Gpio4:  (10, rtc_io_touch_pad0, rtc_gpio_pin10, rtc_io_touch_pad0_mux_sel, rtc_io_touch_pad0_fun_sel, rtc_gpio_pin10_pad_driver, rtc_io_touch_pad0_fun_ie, rtc_io_touch_pad0_rue, rtc_io_touch_pad0_rde),
-    Gpio0:  (11, rtc_io_touch_pad1, rtc_gpio_pin11, rtc_io_touch_pad1_mux_sel, rtc_io_touch_pad1_fun_sel, rtc_gpio_pin11_pad_driver, rtc_io_touch_pad1_fun_ie, rtc_io_touch_pad1_rue, rtc_io_touch_pad1_rde),
-    Gpio2:  (12, rtc_io_touch_pad2, rtc_gpio_pin12, rtc_io_touch_pad2_mux_sel, rtc_io_touch_pad2_fun_sel, rtc_gpio_pin12_pad_driver, rtc_io_touch_pad2_fun_ie, rtc_io_touch_pad2_rue, rtc_io_touch_pad2_rde),
-    Gpio15: (13, rtc_io_touch_pad3, rtc_gpio_pin13, rtc_io_touch_pad3_mux_sel, rtc_io_touch_pad3_fun_sel, rtc_gpio_pin13_pad_driver, rtc_io_touch_pad3_fun_ie, rtc_io_touch_pad3_rue, rtc_io_touch_pad3_rde),
-    Gpio13: (14, rtc_io_touch_pad4, rtc_gpio_pin14, rtc_io_touch_pad4_mux_sel, rtc_io_touch_pad4_fun_sel, rtc_gpio_pin14_pad_driver, rtc_io_touch_pad4_fun_ie, rtc_io_touch_pad4_rue, rtc_io_touch_pad4_rde),
-    Gpio12: (15, rtc_io_touch_pad5, rtc_gpio_pin15, rtc_io_touch_pad5_mux_sel, rtc_io_touch_pad5_fun_sel, rtc_gpio_pin15_pad_driver, rtc_io_touch_pad5_fun_ie, rtc_io_touch_pad5_rue, rtc_io_touch_pad5_rde),
-    Gpio14: (16, rtc_io_touch_pad6, rtc_gpio_pin16, rtc_io_touch_pad6_mux_sel, rtc_io_touch_pad6_fun_sel, rtc_gpio_pin16_pad_driver, rtc_io_touch_pad6_fun_ie, rtc_io_touch_pad6_rue, rtc_io_touch_pad6_rde),
-    Gpio27: (17, rtc_io_touch_pad7, rtc_gpio_pin17, rtc_io_touch_pad7_mux_sel, rtc_io_touch_pad7_fun_sel, rtc_gpio_pin17_pad_driver, rtc_io_touch_pad7_fun_ie, rtc_io_touch_pad7_rue, rtc_io_touch_pad7_rde),
+    Gpio36: (0, rtc_io_sensor_pads, rtc_gpio_pin0, rtc_io_sense1_mux_sel, rtc_io_sense1_fun_sel, rtc_gpio_pin0_pad_driver, rtc_io_sense1_hold, rtc_io_sense1_fun_ie,),
+    Gpio37: (1, rtc_io_sensor_pads, rtc_gpio_pin1, rtc_io_sense2_mux_sel, rtc_io_sense2_fun_sel, rtc_gpio_pin1_pad_driver, rtc_io_sense2_hold, rtc_io_sense2_fun_ie,),
+    Gpio38: (2, rtc_io_sensor_pads, rtc_gpio_pin2, rtc_io_sense3_mux_sel, rtc_io_sense3_fun_sel, rtc_gpio_pin2_pad_driver, rtc_io_sense3_hold, rtc_io_sense3_fun_ie,),
+    Gpio39: (3, rtc_io_sensor_pads, rtc_gpio_pin3, rtc_io_sense4_mux_sel, rtc_io_sense4_fun_sel, rtc_gpio_pin3_pad_driver, rtc_io_sense4_hold, rtc_io_sense4_fun_ie,),
+    Gpio34: (4, rtc_io_adc_pad, rtc_gpio_pin4, rtc_io_adc1_mux_sel, rtc_io_adc1_fun_sel, rtc_gpio_pin4_pad_driver, rtc_io_adc1_hold, rtc_io_adc1_fun_ie,),
+    Gpio35: (5, rtc_io_adc_pad, rtc_gpio_pin5, rtc_io_adc2_mux_sel, rtc_io_adc2_fun_sel, rtc_gpio_pin5_pad_driver, rtc_io_adc2_hold, rtc_io_adc1_fun_ie,),
+    Gpio25: (6, rtc_io_pad_dac1, rtc_gpio_pin6, rtc_io_pdac1_mux_sel, rtc_io_pdac1_fun_sel, rtc_gpio_pin6_pad_driver, rtc_io_pdac1_hold, rtc_io_pdac1_fun_ie, rtc_io_pdac1_rue, rtc_io_pdac1_rde),
+    Gpio26: (7, rtc_io_pad_dac2, rtc_gpio_pin7, rtc_io_pdac2_mux_sel, rtc_io_pdac2_fun_sel, rtc_gpio_pin7_pad_driver, rtc_io_pdac2_hold, rtc_io_pdac2_fun_ie, rtc_io_pdac2_rue, rtc_io_pdac2_rde),
+    Gpio33: (8, rtc_io_xtal_32k_pad, rtc_gpio_pin8, rtc_io_x32n_mux_sel, rtc_io_x32n_fun_sel, rtc_gpio_pin8_pad_driver, rtc_io_x32n_hold, rtc_io_x32n_fun_ie, rtc_io_x32n_rue, rtc_io_x32n_rde),
+    Gpio32: (9, rtc_io_xtal_32k_pad, rtc_gpio_pin9, rtc_io_x32p_mux_sel, rtc_io_x32p_fun_sel, rtc_gpio_pin9_pad_driver, rtc_io_x32p_hold, rtc_io_x32p_fun_ie, rtc_io_x32p_rue, rtc_io_x32p_rde),
+    Gpio4:  (10, rtc_io_touch_pad0, rtc_gpio_pin10, rtc_io_touch_pad0_mux_sel, rtc_io_touch_pad0_fun_sel, rtc_gpio_pin10_pad_driver, rtc_io_touch_pad0_hold, rtc_io_touch_pad0_fun_ie, rtc_io_touch_pad0_rue, rtc_io_touch_pad0_rde),
+    Gpio0:  (11, rtc_io_touch_pad1, rtc_gpio_pin11, rtc_io_touch_pad1_mux_sel, rtc_io_touch_pad1_fun_sel, rtc_gpio_pin11_pad_driver, rtc_io_touch_pad1_hold, rtc_io_touch_pad1_fun_ie, rtc_io_touch_pad1_rue, rtc_io_touch_pad1_rde),
+    Gpio2:  (12, rtc_io_touch_pad2, rtc_gpio_pin12, rtc_io_touch_pad2_mux_sel, rtc_io_touch_pad2_fun_sel, rtc_gpio_pin12_pad_driver, rtc_io_touch_pad2_hold, rtc_io_touch_pad2_fun_ie, rtc_io_touch_pad2_rue, rtc_io_touch_pad2_rde),
+    Gpio15: (13, rtc_io_touch_pad3, rtc_gpio_pin13, rtc_io_touch_pad3_mux_sel, rtc_io_touch_pad3_fun_sel, rtc_gpio_pin13_pad_driver, rtc_io_touch_pad3_hold, rtc_io_touch_pad3_fun_ie, rtc_io_touch_pad3_rue, rtc_io_touch_pad3_rde),
+    Gpio13: (14, rtc_io_touch_pad4, rtc_gpio_pin14, rtc_io_touch_pad4_mux_sel, rtc_io_touch_pad4_fun_sel, rtc_gpio_pin14_pad_driver, rtc_io_touch_pad4_hold, rtc_io_touch_pad4_fun_ie, rtc_io_touch_pad4_rue, rtc_io_touch_pad4_rde),
+    Gpio12: (15, rtc_io_touch_pad5, rtc_gpio_pin15, rtc_io_touch_pad5_mux_sel, rtc_io_touch_pad5_fun_sel, rtc_gpio_pin15_pad_driver, rtc_io_touch_pad5_hold, rtc_io_touch_pad5_fun_ie, rtc_io_touch_pad5_rue, rtc_io_touch_pad5_rde),
+    Gpio14: (16, rtc_io_touch_pad6, rtc_gpio_pin16, rtc_io_touch_pad6_mux_sel, rtc_io_touch_pad6_fun_sel, rtc_gpio_pin16_pad_driver, rtc_io_touch_pad6_hold, rtc_io_touch_pad6_fun_ie, rtc_io_touch_pad6_rue, rtc_io_touch_pad6_rde),
+    Gpio27: (17, rtc_io_touch_pad7, rtc_gpio_pin17, rtc_io_touch_pad7_mux_sel, rtc_io_touch_pad7_fun_sel, rtc_gpio_pin17_pad_driver, rtc_io_touch_pad7_hold, rtc_io_touch_pad7_fun_ie, rtc_io_touch_pad7_rue, rtc_io_touch_pad7_rde),
 ]}
@@ -0,0 +1,185 @@
+//! Pad configuration: drive strength, hold and sleep mode
+//!
+//! The sleep mode configuration is applied by the hardware while the chip is in light sleep.
+//!
+//! While a pad is held its configuration and output level are latched and changes have no
+//! effect. Holding of the digital (non RTC) pads during deep sleep needs to be enabled separately
+//! via [set_deep_sleep_hold].
+
+use super::{modify_iomux_register, Pull};
+use crate::esp32::{IO_MUX, RTCCNTL, RTCIO};
+
+// Bits of the IO_MUX_x_REG registers
+const IO_MUX_MCU_OE: u32 = 1 << 0;
+const IO_MUX_SLP_SEL: u32 = 1 << 1;
+const IO_MUX_MCU_WPD: u32 = 1 << 2;
+const IO_MUX_MCU_WPU: u32 = 1 << 3;
+const IO_MUX_MCU_IE: u32 = 1 << 4;
+const IO_MUX_MCU_DRV_SHIFT: u32 = 5;
+const IO_MUX_MCU_DRV_MASK: u32 = 0x3 << IO_MUX_MCU_DRV_SHIFT;
+const IO_MUX_FUN_DRV_SHIFT: u32 = 10;
+const IO_MUX_FUN_DRV_MASK: u32 = 0x3 << IO_MUX_FUN_DRV_SHIFT;
+const IO_MUX_SLEEP_MASK: u32 = IO_MUX_MCU_OE
+    | IO_MUX_SLP_SEL
+    | IO_MUX_MCU_WPD
+    | IO_MUX_MCU_WPU
+    | IO_MUX_MCU_IE
+    | IO_MUX_MCU_DRV_MASK;
+
+// Bits of the RTC_CNTL_DIG_ISO_REG register
+const DIG_ISO_DG_PAD_AUTOHOLD_EN: u32 = 1 << 11;
+const DIG_ISO_DG_PAD_FORCE_UNHOLD: u32 = 1 << 14;
+
+/// Bit in the RTC_IO_DIG_PAD_HOLD_REG register of a GPIO
+///
+/// 0 for GPIOs which are RTC pads (these are held via the RTC IO registers) or don't exist.
+fn dig_pad_hold_bit(pin_number: u8) -> u32 {
+    match pin_number {
+        1 => 1 << 1,
+        3 => 1 << 0,
+        5 => 1 << 8,
+        6..=11 => 1 << (pin_number - 4),
+        16..=23 => 1 << (pin_number - 7),
+        _ => 0,
+    }
+}
+
+/// Output drive strength
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum DriveStrength {
+    /// About 5mA
+    I5mA = 0,
+    /// About 10mA
+    I10mA = 1,
+    /// About 20mA (default)
+    I20mA = 2,
+    /// About 40mA
+    I40mA = 3,
+}
+
+impl DriveStrength {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0x3 {
+            0 => DriveStrength::I5mA,
+            1 => DriveStrength::I10mA,
+            2 => DriveStrength::I20mA,
+            _ => DriveStrength::I40mA,
+        }
+    }
+}
+
+/// Configuration of a pad while the chip is in light sleep
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct SleepConfig {
+    pub input_enable: bool,
+    pub output_enable: bool,
+    pub pull: Pull,
+    pub drive_strength: DriveStrength,
+}
+
+impl SleepConfig {
+    pub fn input_enable(mut self, input_enable: bool) -> Self {
+        self.input_enable = input_enable;
+        self
+    }
+
+    pub fn output_enable(mut self, output_enable: bool) -> Self {
+        self.output_enable = output_enable;
+        self
+    }
+
+    pub fn pull(mut self, pull: Pull) -> Self {
+        self.pull = pull;
+        self
+    }
+
+    pub fn drive_strength(mut self, drive_strength: DriveStrength) -> Self {
+        self.drive_strength = drive_strength;
+        self
+    }
+}
+
+impl Default for SleepConfig {
+    /// Input and output disabled without pull resistors, to minimize the power consumption
+    fn default() -> SleepConfig {
+        SleepConfig {
+            input_enable: false,
+            output_enable: false,
+            pull: Pull::None,
+            drive_strength: DriveStrength::I20mA,
+        }
+    }
+}
+
+fn read_iomux_register(pin_number: u8) -> u32 {
+    // NOTE(unsafe) the offset is within the IO_MUX register block
+    unsafe {
+        core::ptr::read_volatile(
+            (IO_MUX::ptr() as *const u8).add(super::IO_MUX_OFFSETS[pin_number as usize])
+                as *const u32,
+        )
+    }
+}
+
+pub(super) fn set_drive_strength(pin_number: u8, strength: DriveStrength) {
+    modify_iomux_register(
+        pin_number,
+        IO_MUX_FUN_DRV_MASK,
+        (strength as u32) << IO_MUX_FUN_DRV_SHIFT,
+    );
+}
+
+pub(super) fn drive_strength(pin_number: u8) -> DriveStrength {
+    DriveStrength::from_bits(read_iomux_register(pin_number) >> IO_MUX_FUN_DRV_SHIFT)
+}
+
+pub(super) fn set_sleep_config(pin_number: u8, config: Option<SleepConfig>) {
+    let bits = match config {
+        None => 0,
+        Some(config) => {
+            let mut bits = IO_MUX_SLP_SEL | (config.drive_strength as u32) << IO_MUX_MCU_DRV_SHIFT;
+            if config.input_enable {
+                bits |= IO_MUX_MCU_IE;
+            }
+            if config.output_enable {
+                bits |= IO_MUX_MCU_OE;
+            }
+            match config.pull {
+                Pull::None => {}
+                Pull::Up => bits |= IO_MUX_MCU_WPU,
+                Pull::Down => bits |= IO_MUX_MCU_WPD,
+            }
+            bits
+        }
+    };
+    modify_iomux_register(pin_number, IO_MUX_SLEEP_MASK, bits);
+}
+
+/// Hold a digital (non RTC) pad
+pub(super) fn set_digital_hold(pin_number: u8, enable: bool) {
+    let rtcio = unsafe { &*RTCIO::ptr() };
+    let bit = dig_pad_hold_bit(pin_number);
+    xtensa_lx6_rt::interrupt::free(|_| {
+        rtcio.dig_pad_hold.modify(|r, w| unsafe {
+            if enable {
+                w.bits(r.bits() | bit)
+            } else {
+                w.bits(r.bits() & !bit)
+            }
+        })
+    });
+}
+
+/// Enable or disable holding of the held digital pads during deep sleep
+///
+/// RTC pads are always held during deep sleep when hold is enabled for them.
+pub fn set_deep_sleep_hold(enable: bool) {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+    rtccntl.dig_iso.modify(|r, w| unsafe {
+        if enable {
+            w.bits(r.bits() & !DIG_ISO_DG_PAD_FORCE_UNHOLD | DIG_ISO_DG_PAD_AUTOHOLD_EN)
+        } else {
+            w.bits(r.bits() & !DIG_ISO_DG_PAD_AUTOHOLD_EN)
+        }
+    });
+}
@@ -0,0 +1,215 @@
+//! Groups of pins accessed as a single word
+//!
+//! A [PinGroup] combines up to 32 pins of the same bank (GPIO0-31 or GPIO32-39) so a word can be
+//! written with one write to the OUT_W1TS and one write to the OUT_W1TC register, or read with one
+//! read of the IN register. Bit `n` of the word corresponds to the `n`th pin added via
+//! [PinGroup::with]. This can e.g. be used to bit-bang parallel LCD buses.
+//!
+//! Pins which are set are set before pins which are cleared, so for buses the data needs to be
+//! latched with a separate strobe pin.
+//!
+//! # Example
+//! ```
+//! let mut data = PinGroup::new()
+//!     .with(pins.gpio12.into_push_pull_output().degrade())?
+//!     .with(pins.gpio13.into_push_pull_output().degrade())?
+//!     .with(pins.gpio14.into_push_pull_output().degrade())?
+//!     .with(pins.gpio15.into_push_pull_output().degrade())?;
+//!
+//! data.write(0b1010);
+//! ```
+
+use core::marker::PhantomData;
+
+use super::{AnyPin, Error, Input, OpenDrain, Output, Pin};
+use crate::esp32::GPIO;
+
+/// Maximum number of pins in a group, pins are unique so the bank size is the limit
+const MAX_PINS: usize = 32;
+
+/// Group of pins of the same bank accessed as a single word
+pub struct PinGroup<MODE> {
+    /// true for GPIO32-39
+    bank1: bool,
+    /// Bit positions of the pins in the bank registers
+    positions: [u8; MAX_PINS],
+    len: usize,
+    /// Mask of all pins in the bank registers
+    mask: u32,
+    /// Shift if the pins are consecutive and in order, so no remapping of the bits is needed
+    shift: Option<u8>,
+    _mode: PhantomData<MODE>,
+}
+
+impl<MODE> PinGroup<MODE> {
+    /// Create an empty group
+    pub fn new() -> Self {
+        PinGroup {
+            bank1: false,
+            positions: [0; MAX_PINS],
+            len: 0,
+            mask: 0,
+            shift: None,
+            _mode: PhantomData,
+        }
+    }
+
+    /// Add a pin to the group, it corresponds to the next higher bit of the word
+    ///
+    /// All pins need to be in the same bank (GPIO0-31 or GPIO32-39).
+    pub fn with(mut self, pin: AnyPin<MODE>) -> Result<Self, Error> {
+        let pin_number = pin.pin_number();
+        let bank1 = pin_number >= 32;
+        let position = pin_number % 32;
+
+        if self.len == 0 {
+            self.bank1 = bank1;
+            self.shift = Some(position);
+        } else if bank1 != self.bank1 {
+            return Err(Error::DifferentBanks);
+        } else if self.shift.map(|shift| shift as usize + self.len) != Some(position as usize) {
+            self.shift = None;
+        }
+
+        self.positions[self.len] = position;
+        self.mask |= 1 << position;
+        self.len += 1;
+        Ok(self)
+    }
+
+    /// Number of pins in the group
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the pins of the group in order
+    pub fn release(self) -> impl Iterator<Item = AnyPin<MODE>> {
+        let bank = if self.bank1 { 32 } else { 0 };
+        let positions = self.positions;
+        (0..self.len).map(move |i| AnyPin::new(positions[i] + bank))
+    }
+
+    /// Convert a word into the bits in the bank registers
+    fn to_bank_bits(&self, word: u32) -> u32 {
+        match self.shift {
+            Some(shift) => (word << shift) & self.mask,
+            None => {
+                let mut bits = 0;
+                for (i, position) in self.positions[..self.len].iter().enumerate() {
+                    if word & (1 << i) != 0 {
+                        bits |= 1 << position;
+                    }
+                }
+                bits
+            }
+        }
+    }
+
+    /// Convert the bits in the bank registers into a word
+    fn from_bank_bits(&self, bits: u32) -> u32 {
+        match self.shift {
+            Some(shift) => (bits & self.mask) >> shift,
+            None => {
+                let mut word = 0;
+                for (i, position) in self.positions[..self.len].iter().enumerate() {
+                    if bits & (1 << position) != 0 {
+                        word |= 1 << i;
+                    }
+                }
+                word
+            }
+        }
+    }
+
+    fn read_input(&self) -> u32 {
+        let gpio = unsafe { &*GPIO::ptr() };
+        let bits = if self.bank1 {
+            gpio.in1.read().bits()
+        } else {
+            gpio.in_.read().bits()
+        };
+        self.from_bank_bits(bits)
+    }
+}
+
+impl<MODE> Default for PinGroup<MODE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<MODE> PinGroup<Output<MODE>> {
+    /// Set the output levels of all pins of the group
+    pub fn write(&mut self, word: u32) {
+        let gpio = unsafe { &*GPIO::ptr() };
+        let set = self.to_bank_bits(word);
+        let clear = self.mask & !set;
+        // NOTE(unsafe) atomic writes to stateless registers
+        unsafe {
+            if self.bank1 {
+                gpio.out1_w1ts.write(|w| w.bits(set));
+                gpio.out1_w1tc.write(|w| w.bits(clear));
+            } else {
+                gpio.out_w1ts.write(|w| w.bits(set));
+                gpio.out_w1tc.write(|w| w.bits(clear));
+            }
+        }
+    }
+
+    /// Set the pins of the group for which the bit in `word` is set
+    pub fn set_bits(&mut self, word: u32) {
+        let gpio = unsafe { &*GPIO::ptr() };
+        let set = self.to_bank_bits(word);
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe {
+            if self.bank1 {
+                gpio.out1_w1ts.write(|w| w.bits(set));
+            } else {
+                gpio.out_w1ts.write(|w| w.bits(set));
+            }
+        }
+    }
+
+    /// Clear the pins of the group for which the bit in `word` is set
+    pub fn clear_bits(&mut self, word: u32) {
+        let gpio = unsafe { &*GPIO::ptr() };
+        let clear = self.to_bank_bits(word);
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe {
+            if self.bank1 {
+                gpio.out1_w1tc.write(|w| w.bits(clear));
+            } else {
+                gpio.out_w1tc.write(|w| w.bits(clear));
+            }
+        }
+    }
+
+    /// Output levels of all pins of the group as last written
+    pub fn output(&self) -> u32 {
+        let gpio = unsafe { &*GPIO::ptr() };
+        let bits = if self.bank1 {
+            gpio.out1.read().bits()
+        } else {
+            gpio.out.read().bits()
+        };
+        self.from_bank_bits(bits)
+    }
+}
+
+impl PinGroup<Output<OpenDrain>> {
+    /// Input levels of all pins of the group
+    pub fn read(&self) -> u32 {
+        self.read_input()
+    }
+}
+
+impl<MODE> PinGroup<Input<MODE>> {
+    /// Input levels of all pins of the group
+    pub fn read(&self) -> u32 {
+        self.read_input()
+    }
+}
@@ -0,0 +1,284 @@
+//! RTC IO pins and deep sleep wakeup by pin level
+//!
+//! The 18 RTC capable GPIOs can be routed to the RTC IO via `into_rtc_input()` and
+//! `into_rtc_output()`. In these modes the pins are controlled by the RTC domain and stay
+//! functional during deep sleep.
+//!
+//! RTC inputs can wake the chip from deep sleep:
+//! - EXT0 wakes up when a single pin reaches the given level
+//! - EXT1 wakes up when any of the selected pins is high or when all of them are low
+//!
+//! EXT0 requires the RTC peripherals to stay powered during deep sleep. EXT1 also works with
+//! the RTC peripherals powered down, however the internal pulls are then not available.
+//!
+//! GPIO32 and GPIO33 double as the pins of the 32kHz crystal oscillator, which is controlled
+//! by the clock control via the crate internal `xtal32k_*` functions.
+
+use super::{
+    any_pin, matrix, modify_iomux_register, Pin, IO_MUX_MCU_SEL_GPIO, IO_MUX_MCU_SEL_MASK,
+};
+use crate::esp32::{RTCCNTL, RTCIO};
+use crate::sleep::{WAKEUP_ENA_SHIFT, WAKEUP_EXT0, WAKEUP_EXT1};
+use crate::units::MicroSeconds;
+
+/// GPIO number indexed by RTC GPIO number
+const RTC_TO_GPIO: [u8; 18] = [
+    36, 37, 38, 39, 34, 35, 25, 26, 33, 32, 4, 0, 2, 15, 13, 12, 14, 27,
+];
+
+/// Shift of the RTC GPIO bits in the RTC_GPIO_OUT, RTC_GPIO_ENABLE and RTC_GPIO_IN registers
+const RTC_GPIO_SHIFT: u32 = 14;
+
+// Bits of the RTC_IO_EXT_WAKEUP0_REG register
+const EXT_WAKEUP0_SEL_SHIFT: u32 = 27;
+const EXT_WAKEUP0_SEL_MASK: u32 = 0x1f << EXT_WAKEUP0_SEL_SHIFT;
+
+// Bits of the RTC_CNTL_EXT_WAKEUP_CONF_REG register
+const EXT_WAKEUP0_LV: u32 = 1 << 30;
+const EXT_WAKEUP1_LV: u32 = 1 << 31;
+
+// Bits of the RTC_CNTL_EXT_WAKEUP1_REG register
+const EXT_WAKEUP1_SEL_MASK: u32 = 0x3ffff;
+const EXT_WAKEUP1_STATUS_CLR: u32 = 1 << 18;
+
+// Bits of the RTC_IO_XTAL_32K_PAD_REG register
+const XTAL_32K_DBIAS_SHIFT: u32 = 1;
+const XTAL_32K_DRES_SHIFT: u32 = 3;
+const XTAL_32K_X32P_MUX_SEL: u32 = 1 << 17;
+const XTAL_32K_X32N_MUX_SEL: u32 = 1 << 18;
+const XTAL_32K_XPD: u32 = 1 << 19;
+const XTAL_32K_DAC_SHIFT: u32 = 20;
+const XTAL_32K_X32P_RUE: u32 = 1 << 22;
+const XTAL_32K_X32P_RDE: u32 = 1 << 23;
+const XTAL_32K_X32N_RUE: u32 = 1 << 27;
+const XTAL_32K_X32N_RDE: u32 = 1 << 28;
+const XTAL_32K_SETTINGS_MASK: u32 =
+    0x3 << XTAL_32K_DBIAS_SHIFT | 0x3 << XTAL_32K_DRES_SHIFT | 0x3 << XTAL_32K_DAC_SHIFT;
+
+/// GPIO connected to the XTAL_32K_P pin of the 32kHz crystal
+const XTAL_32K_P_GPIO: u8 = 32;
+/// GPIO connected to the XTAL_32K_N pin of the 32kHz crystal
+const XTAL_32K_N_GPIO: u8 = 33;
+
+/// Half period of the 32kHz bootstrap pulses
+const XTAL_32K_BOOTSTRAP_HALF_PERIOD: MicroSeconds = MicroSeconds(1_000_000 / 32_768 / 2);
+/// Time the pulls are disabled before powering up the 32kHz crystal after bootstrapping
+const XTAL_32K_BOOTSTRAP_TIME: MicroSeconds = MicroSeconds(7);
+
+/// Pin level triggering an EXT0 wakeup
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WakeupLevel {
+    Low,
+    High,
+}
+
+/// Condition of the selected pins triggering an EXT1 wakeup
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Ext1WakeupMode {
+    /// Wake up when all selected pins are low
+    AllLow,
+    /// Wake up when any of the selected pins is high
+    AnyHigh,
+}
+
+/// Pin configured as input of the RTC IO
+pub trait RTCPin: Pin {
+    /// RTC GPIO number of the pin
+    fn rtc_pin_number(&self) -> u8;
+}
+
+/// Set the output level of an RTC GPIO
+pub(super) fn set_output_level(rtc_pin_number: u8, high: bool) {
+    let rtcio = unsafe { &*RTCIO::ptr() };
+    let bits = 1 << (rtc_pin_number as u32 + RTC_GPIO_SHIFT);
+    // NOTE(unsafe) atomic write to a stateless register
+    unsafe {
+        if high {
+            rtcio.rtc_gpio_out_w1ts.write(|w| w.bits(bits));
+        } else {
+            rtcio.rtc_gpio_out_w1tc.write(|w| w.bits(bits));
+        }
+    }
+}
+
+/// Return true if the output level of an RTC GPIO is set high
+pub(super) fn is_output_level_high(rtc_pin_number: u8) -> bool {
+    let rtcio = unsafe { &*RTCIO::ptr() };
+    rtcio.rtc_gpio_out.read().bits() & (1 << (rtc_pin_number as u32 + RTC_GPIO_SHIFT)) != 0
+}
+
+/// Return true if the input level of an RTC GPIO is high
+pub(super) fn is_input_level_high(rtc_pin_number: u8) -> bool {
+    let rtcio = unsafe { &*RTCIO::ptr() };
+    rtcio.rtc_gpio_in.read().bits() & (1 << (rtc_pin_number as u32 + RTC_GPIO_SHIFT)) != 0
+}
+
+fn enable_wakeup_source(source: u32, enable: bool) {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+    xtensa_lx6_rt::interrupt::free(|_| {
+        rtccntl.wakeup_state.modify(|r, w| unsafe {
+            if enable {
+                w.bits(r.bits() | source << WAKEUP_ENA_SHIFT)
+            } else {
+                w.bits(r.bits() & !(source << WAKEUP_ENA_SHIFT))
+            }
+        })
+    });
+}
+
+/// Enable wakeup from deep sleep when the pin reaches the given level
+///
+/// Only a single pin can be used for EXT0, a previously configured pin is replaced.
+pub fn enable_ext0_wakeup(pin: &impl RTCPin, level: WakeupLevel) {
+    let rtcio = unsafe { &*RTCIO::ptr() };
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    xtensa_lx6_rt::interrupt::free(|_| {
+        rtcio.ext_wakeup0.modify(|r, w| unsafe {
+            w.bits(
+                r.bits() & !EXT_WAKEUP0_SEL_MASK
+                    | (pin.rtc_pin_number() as u32) << EXT_WAKEUP0_SEL_SHIFT,
+            )
+        });
+        rtccntl.ext_wakeup_conf.modify(|r, w| unsafe {
+            match level {
+                WakeupLevel::Low => w.bits(r.bits() & !EXT_WAKEUP0_LV),
+                WakeupLevel::High => w.bits(r.bits() | EXT_WAKEUP0_LV),
+            }
+        });
+    });
+
+    enable_wakeup_source(WAKEUP_EXT0, true);
+}
+
+/// Disable wakeup from deep sleep via EXT0
+pub fn disable_ext0_wakeup() {
+    enable_wakeup_source(WAKEUP_EXT0, false);
+}
+
+/// Enable wakeup from deep sleep via a combination of pins
+///
+/// The selected pins replace any previously configured EXT1 pins.
+pub fn enable_ext1_wakeup(pins: &[&dyn RTCPin], mode: Ext1WakeupMode) {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    let mask = pins
+        .iter()
+        .fold(0, |mask, pin| mask | 1 << pin.rtc_pin_number());
+
+    xtensa_lx6_rt::interrupt::free(|_| {
+        rtccntl.ext_wakeup1.modify(|r, w| unsafe {
+            w.bits(r.bits() & !EXT_WAKEUP1_SEL_MASK | mask | EXT_WAKEUP1_STATUS_CLR)
+        });
+        rtccntl.ext_wakeup_conf.modify(|r, w| unsafe {
+            match mode {
+                Ext1WakeupMode::AllLow => w.bits(r.bits() & !EXT_WAKEUP1_LV),
+                Ext1WakeupMode::AnyHigh => w.bits(r.bits() | EXT_WAKEUP1_LV),
+            }
+        });
+    });
+
+    enable_wakeup_source(WAKEUP_EXT1, true);
+}
+
+/// Disable wakeup from deep sleep via EXT1
+pub fn disable_ext1_wakeup() {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    enable_wakeup_source(WAKEUP_EXT1, false);
+    xtensa_lx6_rt::interrupt::free(|_| {
+        rtccntl
+            .ext_wakeup1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !EXT_WAKEUP1_SEL_MASK) })
+    });
+}
+
+/// GPIO mask (bit n for GPIOn) of the pins which caused the last EXT1 wakeup
+pub fn ext1_wakeup_status() -> u64 {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+    let status = rtccntl.ext_wakeup1_status.read().bits() & EXT_WAKEUP1_SEL_MASK;
+
+    RTC_TO_GPIO
+        .iter()
+        .enumerate()
+        .filter(|(rtc_pin, _)| status & (1 << rtc_pin) != 0)
+        .fold(0, |mask, (_, gpio)| mask | 1 << gpio)
+}
+
+fn modify_xtal32k_pad(clear: u32, set: u32) {
+    let rtcio = unsafe { &*RTCIO::ptr() };
+    xtensa_lx6_rt::interrupt::free(|_| {
+        rtcio
+            .xtal_32k_pad
+            .modify(|r, w| unsafe { w.bits(r.bits() & !clear | set) })
+    });
+}
+
+/// Return true if the 32kHz crystal oscillator is powered up
+pub(crate) fn is_xtal32k_powered_up() -> bool {
+    let rtcio = unsafe { &*RTCIO::ptr() };
+    rtcio.xtal_32k_pad.read().bits() & XTAL_32K_XPD != 0
+}
+
+/// Power up the 32kHz crystal oscillator with the given current, resistance and bias settings
+pub(crate) fn xtal32k_enable(dac: u8, dres: u8, dbias: u8) {
+    modify_xtal32k_pad(
+        XTAL_32K_X32P_RUE | XTAL_32K_X32P_RDE | XTAL_32K_X32N_RUE | XTAL_32K_X32N_RDE,
+        XTAL_32K_X32P_MUX_SEL | XTAL_32K_X32N_MUX_SEL,
+    );
+    modify_xtal32k_pad(
+        XTAL_32K_SETTINGS_MASK,
+        (dac as u32 & 0x3) << XTAL_32K_DAC_SHIFT
+            | (dres as u32 & 0x3) << XTAL_32K_DRES_SHIFT
+            | (dbias as u32 & 0x3) << XTAL_32K_DBIAS_SHIFT,
+    );
+    modify_xtal32k_pad(0, XTAL_32K_XPD);
+}
+
+/// Power down the 32kHz crystal oscillator
+pub(crate) fn xtal32k_disable() {
+    modify_xtal32k_pad(XTAL_32K_XPD, 0);
+}
+
+/// Help the 32kHz crystal to start up
+///
+/// The crystal pins are driven in antiphase at about 32kHz for the given number of cycles,
+/// after which the oscillator is powered up with the given (stronger) settings.
+pub(crate) fn xtal32k_bootstrap(
+    cycles: u32,
+    dac: u8,
+    dres: u8,
+    dbias: u8,
+    delay: impl Fn(MicroSeconds),
+) {
+    if cycles > 0 {
+        // route the pins to the GPIO matrix
+        modify_xtal32k_pad(XTAL_32K_X32P_MUX_SEL | XTAL_32K_X32N_MUX_SEL, 0);
+        for &pin_number in [XTAL_32K_P_GPIO, XTAL_32K_N_GPIO].iter() {
+            modify_iomux_register(pin_number, IO_MUX_MCU_SEL_MASK, IO_MUX_MCU_SEL_GPIO);
+            matrix::disconnect_output_signal(pin_number);
+        }
+
+        any_pin::set_output_level(XTAL_32K_P_GPIO, true);
+        any_pin::set_output_level(XTAL_32K_N_GPIO, false);
+        any_pin::enable_output(XTAL_32K_P_GPIO, true);
+        any_pin::enable_output(XTAL_32K_N_GPIO, true);
+
+        for _ in 0..cycles {
+            any_pin::set_output_level(XTAL_32K_P_GPIO, true);
+            any_pin::set_output_level(XTAL_32K_N_GPIO, false);
+            delay(XTAL_32K_BOOTSTRAP_HALF_PERIOD);
+            any_pin::set_output_level(XTAL_32K_P_GPIO, false);
+            any_pin::set_output_level(XTAL_32K_N_GPIO, true);
+            delay(XTAL_32K_BOOTSTRAP_HALF_PERIOD);
+        }
+
+        any_pin::enable_output(XTAL_32K_P_GPIO, false);
+        any_pin::enable_output(XTAL_32K_N_GPIO, false);
+    }
+
+    modify_xtal32k_pad(XTAL_32K_X32P_RUE | XTAL_32K_X32N_RDE, 0);
+    delay(XTAL_32K_BOOTSTRAP_TIME);
+
+    xtal32k_enable(dac, dres, dbias);
+}
@@ -0,0 +1,655 @@
+//! I2C peripheral control
+//!
+//! Controls the 2 I2C peripherals (I2C0, I2C1) in master mode. For slave mode see [slave].
+//!
+//! The SDA and SCL signals are routed via the GPIO matrix, so any output capable pin can be used.
+//! The pins need to be configured as open drain outputs. (External pull-up resistors are
+//! typically needed.)
+//!
+//! Transactions longer than the 32 byte FIFO or the 16 entry command list are automatically
+//! split in multiple parts.
+//!
+//! # TODO
+//! - 10-bit addressing in master mode
+
+use core::ptr::{read_volatile, write_volatile};
+
+use embedded_hal::blocking::i2c;
+
+use crate::esp32::{I2C0, I2C1};
+use crate::gpio::{self, InputSignal, OpenDrain, Output, OutputSignal};
+use crate::units::*;
+
+pub mod slave;
+
+/// Size of the transmit and receive FIFOs
+const I2C_FIFO_SIZE: usize = 32;
+
+/// Number of command registers
+const I2C_COMMAND_COUNT: usize = 16;
+
+/// Maximum number of bytes per command
+const I2C_MAX_BYTES_PER_COMMAND: usize = 255;
+
+/// Maximum value of the scl period registers (14 bits)
+const I2C_MAX_PERIOD: u32 = (1 << 14) - 1;
+
+/// Maximum value of the time out register (20 bits)
+const I2C_MAX_TIMEOUT: u32 = (1 << 20) - 1;
+
+/// Maximum supported bus frequency
+const I2C_MAX_FREQUENCY: Hertz = Hertz(1_000_000);
+
+// Raw interrupt bits
+const INT_END_DETECT: u32 = 1 << 3;
+const INT_ARBITRATION_LOST: u32 = 1 << 5;
+const INT_TRANS_COMPLETE: u32 = 1 << 7;
+const INT_TIME_OUT: u32 = 1 << 8;
+const INT_ACK_ERR: u32 = 1 << 10;
+const INT_ALL: u32 = 0x1fff;
+
+// Command register bits
+const COMMAND_DONE: u32 = 1 << 31;
+const COMMAND_ACK_CHECK_EN: u32 = 1 << 8;
+const COMMAND_ACK_VALUE_NACK: u32 = 1 << 10;
+const COMMAND_OP_CODE_SHIFT: u32 = 11;
+
+/// I2C error
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Error {
+    /// The address was not acknowledged
+    AddressNack,
+    /// A data byte was not acknowledged
+    DataNack,
+    /// Arbitration lost to another master
+    ArbitrationLost,
+    /// The bus is stuck or the transaction could not be completed
+    BusError,
+    /// A slave stretched the clock longer than the configured time out
+    Timeout,
+    /// Requested frequency too low
+    FrequencyTooLow,
+    /// Requested frequency too high
+    FrequencyTooHigh,
+}
+
+pub mod config {
+    use crate::units::*;
+
+    #[derive(Debug, Copy, Clone)]
+    pub struct Config {
+        /// Bus frequency
+        pub frequency: Hertz,
+        /// Maximum time the clock is allowed to be stretched by a slave
+        ///
+        /// The timeout is limited by hardware to 2^20 APB clock cycles (about 13ms at 80MHz).
+        pub timeout: MicroSeconds,
+    }
+
+    impl Config {
+        pub fn frequency(mut self, frequency: Hertz) -> Self {
+            self.frequency = frequency;
+            self
+        }
+
+        /// Standard mode: 100kHz
+        pub fn standard_mode(mut self) -> Self {
+            self.frequency = Hertz(100_000);
+            self
+        }
+
+        /// Fast mode: 400kHz
+        pub fn fast_mode(mut self) -> Self {
+            self.frequency = Hertz(400_000);
+            self
+        }
+
+        pub fn timeout(mut self, timeout: MicroSeconds) -> Self {
+            self.timeout = timeout;
+            self
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Config {
+            Config {
+                frequency: Hertz(100_000),
+                timeout: MicroSeconds(10_000),
+            }
+        }
+    }
+}
+
+pub trait Pins<I2C> {}
+pub trait PinSda<I2C>: gpio::Pin {}
+pub trait PinScl<I2C>: gpio::Pin {}
+
+impl<I2C, SDA, SCL> Pins<I2C> for (SDA, SCL)
+where
+    SDA: PinSda<I2C>,
+    SCL: PinScl<I2C>,
+{
+}
+
+macro_rules! impl_pins {
+    ($($pxi:ident),+) => {
+        $(
+            impl<I2C> PinSda<I2C> for gpio::$pxi<Output<OpenDrain>> {}
+            impl<I2C> PinScl<I2C> for gpio::$pxi<Output<OpenDrain>> {}
+        )+
+    };
+}
+
+impl_pins!(
+    Gpio0, Gpio1, Gpio2, Gpio3, Gpio4, Gpio5, Gpio6, Gpio7, Gpio8, Gpio9, Gpio10, Gpio11, Gpio12,
+    Gpio13, Gpio14, Gpio15, Gpio16, Gpio17, Gpio18, Gpio19, Gpio20, Gpio21, Gpio22, Gpio23, Gpio25,
+    Gpio26, Gpio27, Gpio32, Gpio33
+);
+
+/// Commands of the I2C command list
+#[derive(Copy, Clone)]
+enum Command {
+    Start,
+    Write { length: usize },
+    Read { length: usize, nack: bool },
+    Stop,
+    End,
+}
+
+impl Command {
+    fn bits(&self) -> u32 {
+        match *self {
+            Command::Start => 0 << COMMAND_OP_CODE_SHIFT,
+            Command::Write { length } => {
+                1 << COMMAND_OP_CODE_SHIFT | COMMAND_ACK_CHECK_EN | length as u32
+            }
+            Command::Read { length, nack } => {
+                2 << COMMAND_OP_CODE_SHIFT
+                    | if nack { COMMAND_ACK_VALUE_NACK } else { 0 }
+                    | length as u32
+            }
+            Command::Stop => 3 << COMMAND_OP_CODE_SHIFT,
+            Command::End => 4 << COMMAND_OP_CODE_SHIFT,
+        }
+    }
+}
+
+/// Operations of an I2C transaction
+enum Operation<'a> {
+    Start,
+    Address(u8),
+    Write(&'a [u8]),
+    Read(&'a mut [u8]),
+    Stop,
+}
+
+/// I2C abstraction
+///
+/// The APB frequency is locked as long as this structure exists.
+pub struct I2c<I2C, PINS> {
+    i2c: I2C,
+    pins: PINS,
+    clock_control: crate::clock_control::ClockControlConfig,
+    _apb_lock: crate::clock_control::dfs::LockAPB,
+}
+
+/// State of the command list and FIFO while building a transaction
+struct CommandList {
+    commands: usize,
+    tx_bytes: usize,
+    rx_bytes: usize,
+    /// Bit mask of commands which write an address
+    address_commands: u32,
+}
+
+impl CommandList {
+    fn new() -> Self {
+        CommandList {
+            commands: 0,
+            tx_bytes: 0,
+            rx_bytes: 0,
+            address_commands: 0,
+        }
+    }
+}
+
+macro_rules! halI2c {
+    ($(
+        $I2CX:ident: ($i2cX:ident, $i2c_extX:ident, $fifo_address:expr, $scl_signal:ident,
+            $sda_signal:ident),
+    )+) => {
+        $(
+            impl<SDA, SCL> I2c<$I2CX, (SDA, SCL)>
+            where
+                SDA: PinSda<$I2CX>,
+                SCL: PinScl<$I2CX>,
+            {
+                pub fn $i2cX(
+                    i2c: $I2CX,
+                    pins: (SDA, SCL),
+                    config: config::Config,
+                    clock_control: crate::clock_control::ClockControlConfig,
+                    dport: &mut esp32::DPORT,
+                ) -> Result<Self, Error> {
+                    let sda = pins.0.pin_number();
+                    let scl = pins.1.pin_number();
+                    gpio::connect_output_signal(sda, OutputSignal::$sda_signal);
+                    gpio::connect_input_signal(InputSignal::$sda_signal, sda);
+                    gpio::connect_output_signal(scl, OutputSignal::$scl_signal);
+                    gpio::connect_input_signal(InputSignal::$scl_signal, scl);
+
+                    let mut i2c = I2c {
+                        i2c,
+                        pins,
+                        clock_control,
+                        _apb_lock: clock_control.lock_apb_frequency(),
+                    };
+
+                    i2c.reset(dport).enable(dport).init().change_frequency(config.frequency)?;
+                    i2c.change_timeout(config.timeout);
+
+                    Ok(i2c)
+                }
+
+                /// Release the peripheral and pins
+                pub fn release(self) -> ($I2CX, (SDA, SCL)) {
+                    (self.i2c, self.pins)
+                }
+            }
+
+            impl<PINS> I2c<$I2CX, PINS> {
+                fn reset(&mut self, dport: &mut esp32::DPORT) -> &mut Self {
+                    dport.perip_rst_en.modify(|_, w| w.$i2c_extX().set_bit());
+                    dport.perip_rst_en.modify(|_, w| w.$i2c_extX().clear_bit());
+                    self
+                }
+
+                pub fn enable(&mut self, dport: &mut esp32::DPORT) -> &mut Self {
+                    dport.perip_clk_en.modify(|_, w| w.$i2c_extX().set_bit());
+                    dport.perip_rst_en.modify(|_, w| w.$i2c_extX().clear_bit());
+                    self
+                }
+
+                pub fn disable(&mut self, dport: &mut esp32::DPORT) -> &mut Self {
+                    dport.perip_clk_en.modify(|_, w| w.$i2c_extX().clear_bit());
+                    dport.perip_rst_en.modify(|_, w| w.$i2c_extX().set_bit());
+                    self
+                }
+
+                /// Initialize as master, open drain outputs, MSB first
+                fn init(&mut self) -> &mut Self {
+                    self.i2c.ctr.write(|w| {
+                        w.ms_mode()
+                            .set_bit()
+                            .sda_force_out()
+                            .set_bit()
+                            .scl_force_out()
+                            .set_bit()
+                            .clk_en()
+                            .set_bit()
+                    });
+
+                    // use the FIFO, without address configuration
+                    self.i2c.fifo_conf.modify(|_, w| {
+                        w.nonfifo_en()
+                            .clear_bit()
+                            .fifo_addr_cfg_en()
+                            .clear_bit()
+                    });
+
+                    // filter glitches shorter than 7 APB cycles
+                    self.i2c.scl_filter_cfg.write(|w| unsafe { w.bits(0x8 | 7) });
+                    self.i2c.sda_filter_cfg.write(|w| unsafe { w.bits(0x8 | 7) });
+
+                    self.i2c.int_ena.write(|w| unsafe { w.bits(0) });
+                    self.i2c.int_clr.write(|w| unsafe { w.bits(INT_ALL) });
+
+                    self.reset_fifo();
+                    self
+                }
+
+                /// Change the bus frequency
+                pub fn change_frequency<T: Into<Hertz> + Copy>(
+                    &mut self,
+                    frequency: T,
+                ) -> Result<&mut Self, Error> {
+                    let frequency: Hertz = frequency.into();
+
+                    if frequency == Hertz(0) {
+                        return Err(Error::FrequencyTooLow);
+                    }
+                    if frequency > I2C_MAX_FREQUENCY {
+                        return Err(Error::FrequencyTooHigh);
+                    }
+
+                    // the APB frequency is locked, so will not change
+                    let half_cycle = self.clock_control.apb_frequency() / frequency / 2;
+
+                    if half_cycle > I2C_MAX_PERIOD {
+                        return Err(Error::FrequencyTooLow);
+                    }
+                    if half_cycle < 8 {
+                        return Err(Error::FrequencyTooHigh);
+                    }
+
+                    unsafe {
+                        self.i2c.scl_low_period.write(|w| w.bits(half_cycle));
+                        self.i2c.scl_high_period.write(|w| w.bits(half_cycle));
+                        self.i2c.sda_hold.write(|w| w.bits(half_cycle / 2));
+                        self.i2c.sda_sample.write(|w| w.bits(half_cycle / 2));
+                        self.i2c.scl_start_hold.write(|w| w.bits(half_cycle));
+                        self.i2c.scl_rstart_setup.write(|w| w.bits(half_cycle));
+                        self.i2c.scl_stop_hold.write(|w| w.bits(half_cycle));
+                        self.i2c.scl_stop_setup.write(|w| w.bits(half_cycle));
+                    }
+
+                    Ok(self)
+                }
+
+                /// Get the actual bus frequency
+                pub fn frequency(&self) -> Hertz {
+                    let low = self.i2c.scl_low_period.read().bits();
+                    let high = self.i2c.scl_high_period.read().bits();
+                    self.clock_control.apb_frequency() / (low + high)
+                }
+
+                /// Change the maximum time the clock can be stretched by a slave
+                ///
+                /// The time is limited to the maximum supported by the hardware.
+                pub fn change_timeout<T: Into<MicroSeconds>>(&mut self, timeout: T) -> &mut Self {
+                    let cycles = (self.clock_control.apb_frequency() / Hertz(1_000_000)) as u64
+                        * u32::from(timeout.into()) as u64;
+                    let cycles = core::cmp::min(cycles, I2C_MAX_TIMEOUT as u64) as u32;
+
+                    self.i2c.to.write(|w| unsafe { w.bits(cycles) });
+                    self
+                }
+
+                /// Return true if the bus is busy
+                pub fn is_bus_busy(&self) -> bool {
+                    self.i2c.sr.read().bus_busy().bit_is_set()
+                }
+
+                fn reset_fifo(&mut self) {
+                    self.i2c
+                        .fifo_conf
+                        .modify(|_, w| w.tx_fifo_rst().set_bit().rx_fifo_rst().set_bit());
+                    self.i2c
+                        .fifo_conf
+                        .modify(|_, w| w.tx_fifo_rst().clear_bit().rx_fifo_rst().clear_bit());
+                }
+
+                fn write_fifo(&mut self, byte: u8) {
+                    // NOTE(unsafe) the FIFO needs to be accessed via the AHB address
+                    unsafe { write_volatile($fifo_address as *mut u32, byte as u32) };
+                }
+
+                fn read_fifo(&mut self) -> u8 {
+                    // NOTE(unsafe) the FIFO needs to be accessed via the AHB address
+                    unsafe { read_volatile($fifo_address as *const u32) as u8 }
+                }
+
+                fn write_command(&mut self, list: &mut CommandList, command: Command) {
+                    // NOTE(unsafe) the command registers are consecutive
+                    unsafe {
+                        write_volatile(
+                            self.i2c.comd0.as_ptr().add(list.commands),
+                            command.bits(),
+                        )
+                    };
+                    list.commands += 1;
+                }
+
+                /// Execute the commands in the command list and wait for the end or stop
+                fn execute(&mut self, list: &CommandList, last: bool) -> Result<(), Error> {
+                    self.i2c.int_clr.write(|w| unsafe { w.bits(INT_ALL) });
+                    self.i2c.ctr.modify(|_, w| w.trans_start().set_bit());
+
+                    let done = if last { INT_TRANS_COMPLETE } else { INT_END_DETECT };
+
+                    loop {
+                        let status = self.i2c.int_raw.read().bits();
+
+                        if status & INT_ACK_ERR != 0 {
+                            return Err(self.nack_error(list));
+                        }
+                        if status & INT_ARBITRATION_LOST != 0 {
+                            return Err(Error::ArbitrationLost);
+                        }
+                        if status & INT_TIME_OUT != 0 {
+                            return Err(Error::Timeout);
+                        }
+                        if status & done != 0 {
+                            return Ok(());
+                        }
+                    }
+                }
+
+                /// Determine if the address or a data byte was not acknowledged
+                fn nack_error(&self, list: &CommandList) -> Error {
+                    // find the first command which did not finish
+                    for i in 0..list.commands {
+                        let command =
+                            unsafe { read_volatile(self.i2c.comd0.as_ptr().add(i)) };
+                        if command & COMMAND_DONE == 0 {
+                            if list.address_commands & (1 << i) != 0 {
+                                return Error::AddressNack;
+                            }
+                            break;
+                        }
+                    }
+                    Error::DataNack
+                }
+
+                /// Bring the bus back in idle state after an error
+                fn recover(&mut self) {
+                    self.reset_fifo();
+                    let mut list = CommandList::new();
+                    self.write_command(&mut list, Command::Stop);
+                    // ignore errors: the stop condition is generated regardless
+                    let _ = self.execute(&list, true);
+                    self.i2c.int_clr.write(|w| unsafe { w.bits(INT_ALL) });
+                }
+
+                /// Execute the part of the transaction in the command list and copy the
+                /// received data. Returns the index of the read operation and position within
+                /// the operation of the next byte to be received.
+                fn flush(
+                    &mut self,
+                    list: &mut CommandList,
+                    operations: &mut [Operation],
+                    mut read_position: (usize, usize),
+                    last: bool,
+                ) -> Result<(usize, usize), Error> {
+                    if !last {
+                        self.write_command(list, Command::End);
+                    }
+                    self.execute(list, last)?;
+
+                    // copy received bytes to the read buffers in order
+                    for _ in 0..list.rx_bytes {
+                        loop {
+                            if let Operation::Read(buffer) = &mut operations[read_position.0] {
+                                if read_position.1 < buffer.len() {
+                                    buffer[read_position.1] = self.read_fifo();
+                                    read_position.1 += 1;
+                                    break;
+                                }
+                            }
+                            read_position = (read_position.0 + 1, 0);
+                        }
+                    }
+
+                    *list = CommandList::new();
+                    Ok(read_position)
+                }
+
+                /// Execute a transaction consisting of multiple operations
+                fn transaction(&mut self, operations: &mut [Operation]) -> Result<(), Error> {
+                    if self.is_bus_busy() {
+                        return Err(Error::BusError);
+                    }
+
+                    self.reset_fifo();
+
+                    let result = self.transaction_commands(operations);
+                    if result.is_err() {
+                        self.recover();
+                    }
+                    result
+                }
+
+                fn transaction_commands(&mut self, operations: &mut [Operation]) -> Result<(), Error> {
+                    let mut list = CommandList::new();
+                    let mut read_position = (0, 0);
+
+                    for index in 0..operations.len() {
+                        // always keep room for an end command
+                        if list.commands + 2 > I2C_COMMAND_COUNT {
+                            read_position = self.flush(&mut list, operations, read_position, false)?;
+                        }
+
+                        match &operations[index] {
+                            Operation::Start => self.write_command(&mut list, Command::Start),
+                            Operation::Stop => {
+                                self.write_command(&mut list, Command::Stop);
+                                self.flush(&mut list, operations, read_position, true)?;
+                                return Ok(());
+                            }
+                            Operation::Address(address) => {
+                                if list.tx_bytes + 1 > I2C_FIFO_SIZE {
+                                    read_position =
+                                        self.flush(&mut list, operations, read_position, false)?;
+                                }
+                                self.write_fifo(*address);
+                                list.tx_bytes += 1;
+                                list.address_commands |= 1 << list.commands;
+                                self.write_command(&mut list, Command::Write { length: 1 });
+                            }
+                            Operation::Write(bytes) => {
+                                let mut remaining = *bytes;
+                                while !remaining.is_empty() {
+                                    let mut length = core::cmp::min(
+                                        remaining.len(),
+                                        I2C_FIFO_SIZE - list.tx_bytes,
+                                    );
+                                    if length == 0 || list.commands + 2 > I2C_COMMAND_COUNT {
+                                        read_position = self.flush(
+                                            &mut list,
+                                            operations,
+                                            read_position,
+                                            false,
+                                        )?;
+                                        length = core::cmp::min(remaining.len(), I2C_FIFO_SIZE);
+                                    }
+                                    let length = core::cmp::min(length, I2C_MAX_BYTES_PER_COMMAND);
+
+                                    for byte in &remaining[..length] {
+                                        self.write_fifo(*byte);
+                                    }
+                                    list.tx_bytes += length;
+                                    self.write_command(&mut list, Command::Write { length });
+                                    remaining = &remaining[length..];
+                                }
+                            }
+                            Operation::Read(buffer) => {
+                                let mut remaining = buffer.len();
+                                while remaining > 0 {
+                                    let mut length = core::cmp::min(
+                                        remaining,
+                                        I2C_FIFO_SIZE - list.rx_bytes,
+                                    );
+                                    // need room for two reads (the last byte is nacked) and end
+                                    if length == 0 || list.commands + 3 > I2C_COMMAND_COUNT {
+                                        read_position = self.flush(
+                                            &mut list,
+                                            operations,
+                                            read_position,
+                                            false,
+                                        )?;
+                                        length = core::cmp::min(remaining, I2C_FIFO_SIZE);
+                                    }
+
+                                    if length == remaining {
+                                        // the last byte of a read is not acknowledged
+                                        if length > 1 {
+                                            self.write_command(
+                                                &mut list,
+                                                Command::Read { length: length - 1, nack: false },
+                                            );
+                                        }
+                                        self.write_command(
+                                            &mut list,
+                                            Command::Read { length: 1, nack: true },
+                                        );
+                                    } else {
+                                        self.write_command(
+                                            &mut list,
+                                            Command::Read { length, nack: false },
+                                        );
+                                    }
+                                    list.rx_bytes += length;
+                                    remaining -= length;
+                                }
+                            }
+                        }
+                    }
+
+                    Err(Error::BusError)
+                }
+            }
+
+            impl<PINS> i2c::Write for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+                    self.transaction(&mut [
+                        Operation::Start,
+                        Operation::Address(address << 1),
+                        Operation::Write(bytes),
+                        Operation::Stop,
+                    ])
+                }
+            }
+
+            impl<PINS> i2c::Read for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+                    self.transaction(&mut [
+                        Operation::Start,
+                        Operation::Address(address << 1 | 1),
+                        Operation::Read(buffer),
+                        Operation::Stop,
+                    ])
+                }
+            }
+
+            impl<PINS> i2c::WriteRead for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write_read(
+                    &mut self,
+                    address: u8,
+                    bytes: &[u8],
+                    buffer: &mut [u8],
+                ) -> Result<(), Self::Error> {
+                    self.transaction(&mut [
+                        Operation::Start,
+                        Operation::Address(address << 1),
+                        Operation::Write(bytes),
+                        Operation::Start,
+                        Operation::Address(address << 1 | 1),
+                        Operation::Read(buffer),
+                        Operation::Stop,
+                    ])
+                }
+            }
+        )+
+    }
+}
+
+halI2c! {
+    I2C0: (i2c0, i2c_ext0, 0x6001_301c, I2CEXT0_SCL, I2CEXT0_SDA),
+    I2C1: (i2c1, i2c_ext1, 0x6002_701c, I2CEXT1_SCL, I2CEXT1_SDA),
+}
@@ -0,0 +1,441 @@
+//! I2C slave mode
+//!
+//! Lets the ESP32 act as an I2C peripheral with a 7 or 10-bit address.
+//!
+//! The FIFOs are handled by [I2cSlave::handle_interrupt], which moves the data between the
+//! hardware FIFOs and a receive and transmit ring buffer per peripheral. It has to be called from
+//! the I2C_EXT0_INTR or I2C_EXT1_INTR interrupt handler defined by the application. The interrupt
+//! needs to be enabled via [interrupt::enable](crate::interrupt::enable) or
+//! [interrupt::enable_with_priority](crate::interrupt::enable_with_priority).
+//!
+//! The application can either poll the buffers via [I2cSlave::read] and [I2cSlave::write], or
+//! register a [Callback] which is called from the interrupt handler on every [Event].
+//!
+//! **Note: the ESP32 does not stretch the clock in slave mode. Data for a read by the master
+//! therefore needs to be queued before the master starts reading. When emulating registers,
+//! queue the register content from the callback on [Event::DataReceived].**
+//!
+//! # Example
+//! ```
+//! #[interrupt]
+//! fn I2C_EXT0_INTR() {
+//!     I2cSlave::<I2C0, ()>::handle_interrupt();
+//! }
+//!
+//! fn callback(event: Event, buffers: &mut Buffers) {
+//!     if let Event::DataReceived = event {
+//!         let mut register = [0u8; 1];
+//!         if buffers.read(&mut register) == 1 {
+//!             buffers.clear_transmit();
+//!             buffers.write(&REGISTERS[register[0] as usize..]);
+//!         }
+//!     }
+//! }
+//!
+//! let mut slave = I2cSlave::i2c0(dp.I2C0, (sda, scl), Config::default(), clock_control,
+//!     &mut dport);
+//! slave.set_callback(Some(callback));
+//! interrupt::enable(Interrupt::I2C_EXT0_INTR).unwrap();
+//! ```
+
+use core::ptr::{read_volatile, write_volatile};
+
+use super::{PinScl, PinSda, I2C_FIFO_SIZE, INT_ALL, INT_TRANS_COMPLETE};
+use crate::esp32::{I2C0, I2C1};
+use crate::gpio::{self, InputSignal, OutputSignal};
+use crate::ring_buffer::RingBuffer;
+
+// Raw interrupt bits only used in slave mode
+const INT_RX_FIFO_FULL: u32 = 1 << 0;
+const INT_TX_FIFO_EMPTY: u32 = 1 << 1;
+const INT_RX_FIFO_OVF: u32 = 1 << 2;
+
+/// Number of bytes in the receive FIFO before it is emptied by the interrupt
+const RX_FIFO_FULL_THRESHOLD: u32 = 24;
+/// Number of bytes in the transmit FIFO below which it is refilled by the interrupt
+const TX_FIFO_EMPTY_THRESHOLD: u32 = 8;
+
+/// Hold and sample time in APB cycles
+const SDA_TIMING: u32 = 10;
+
+/// Size of the receive and transmit buffers
+pub const SLAVE_BUFFER_SIZE: usize = 256;
+
+/// Slave address
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Address {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+pub mod config {
+    use super::Address;
+
+    #[derive(Debug, Copy, Clone)]
+    pub struct Config {
+        pub address: Address,
+    }
+
+    impl Config {
+        pub fn address(mut self, address: Address) -> Self {
+            self.address = address;
+            self
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Config {
+            Config {
+                address: Address::SevenBit(0x28),
+            }
+        }
+    }
+}
+
+/// Slave event passed to the callback
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Event {
+    /// Data has been written by the master and added to the receive buffer
+    DataReceived,
+    /// The transmit FIFO is running empty and no more data is queued
+    TransmitBufferEmpty,
+    /// A transaction has been completed (stop condition)
+    TransactionComplete,
+}
+
+/// Callback called from the interrupt handler
+pub type Callback = fn(Event, &mut Buffers);
+
+/// Access to the receive and transmit buffers
+pub struct Buffers {
+    receive: RingBuffer,
+    transmit: RingBuffer,
+    overrun: bool,
+}
+
+impl Buffers {
+    const fn empty() -> Self {
+        Buffers {
+            receive: RingBuffer::empty(),
+            transmit: RingBuffer::empty(),
+            overrun: false,
+        }
+    }
+
+    /// Read received data, returns the number of bytes read
+    pub fn read(&mut self, data: &mut [u8]) -> usize {
+        self.receive.read(data)
+    }
+
+    /// Number of bytes in the receive buffer
+    pub fn received(&self) -> usize {
+        self.receive.len()
+    }
+
+    /// Queue data to be read by the master, returns the number of bytes queued
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        self.transmit.write(data)
+    }
+
+    /// Remove all data from the transmit buffer
+    ///
+    /// Data already moved to the transmit FIFO will still be sent.
+    pub fn clear_transmit(&mut self) {
+        self.transmit.clear();
+    }
+
+    /// Return true if received data was lost because the receive buffer or FIFO was full
+    ///
+    /// Clears the overrun flag.
+    pub fn overrun(&mut self) -> bool {
+        core::mem::replace(&mut self.overrun, false)
+    }
+}
+
+struct State {
+    buffers: Buffers,
+    callback: Option<Callback>,
+    /// Set once the static buffer storage has been handed to the ring buffers
+    storage_taken: bool,
+}
+
+impl State {
+    const fn new() -> Self {
+        State {
+            buffers: Buffers::empty(),
+            callback: None,
+            storage_taken: false,
+        }
+    }
+
+    fn call(&mut self, event: Event) {
+        if let Some(callback) = self.callback {
+            callback(event, &mut self.buffers);
+        }
+    }
+}
+
+/// I2C slave abstraction
+///
+/// The APB frequency is locked as long as this structure exists.
+pub struct I2cSlave<I2C, PINS> {
+    i2c: I2C,
+    pins: PINS,
+    _apb_lock: crate::clock_control::dfs::LockAPB,
+}
+
+macro_rules! halI2cSlave {
+    ($(
+        $I2CX:ident: ($i2cX:ident, $i2c_extX:ident, $fifo_address:expr, $scl_signal:ident,
+            $sda_signal:ident, $STATE:ident, $BUFFERS:ident),
+    )+) => {
+        $(
+            static $STATE: spin::Mutex<State> = spin::Mutex::new(State::new());
+            static mut $BUFFERS: [[u8; SLAVE_BUFFER_SIZE]; 2] = [[0; SLAVE_BUFFER_SIZE]; 2];
+
+            impl<SDA, SCL> I2cSlave<$I2CX, (SDA, SCL)>
+            where
+                SDA: PinSda<$I2CX>,
+                SCL: PinScl<$I2CX>,
+            {
+                pub fn $i2cX(
+                    i2c: $I2CX,
+                    pins: (SDA, SCL),
+                    config: config::Config,
+                    clock_control: crate::clock_control::ClockControlConfig,
+                    dport: &mut esp32::DPORT,
+                ) -> Self {
+                    let sda = pins.0.pin_number();
+                    let scl = pins.1.pin_number();
+                    gpio::connect_output_signal(sda, OutputSignal::$sda_signal);
+                    gpio::connect_input_signal(InputSignal::$sda_signal, sda);
+                    gpio::connect_output_signal(scl, OutputSignal::$scl_signal);
+                    gpio::connect_input_signal(InputSignal::$scl_signal, scl);
+
+                    let mut slave = I2cSlave {
+                        i2c,
+                        pins,
+                        _apb_lock: clock_control.lock_apb_frequency(),
+                    };
+
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        let mut state = $STATE.lock();
+                        if !state.storage_taken {
+                            // NOTE(unsafe) the storage is only taken once, the ring buffers keep
+                            // it when the peripheral is released and reused
+                            let (receive, transmit) = unsafe { $BUFFERS.split_at_mut(1) };
+                            state.buffers.receive = RingBuffer::new(&mut receive[0]);
+                            state.buffers.transmit = RingBuffer::new(&mut transmit[0]);
+                            state.storage_taken = true;
+                        }
+                    });
+
+                    slave.reset(dport).enable(dport).init(config.address);
+                    slave
+                }
+
+                /// Release the peripheral and pins
+                ///
+                /// Any data remaining in the buffers is discarded and the callback is removed.
+                pub fn release(self) -> ($I2CX, (SDA, SCL)) {
+                    self.i2c.int_ena.write(|w| unsafe { w.bits(0) });
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        let mut state = $STATE.lock();
+                        state.callback = None;
+                        state.buffers.receive.clear();
+                        state.buffers.transmit.clear();
+                        state.buffers.overrun = false;
+                    });
+                    (self.i2c, self.pins)
+                }
+            }
+
+            impl<PINS> I2cSlave<$I2CX, PINS> {
+                fn reset(&mut self, dport: &mut esp32::DPORT) -> &mut Self {
+                    dport.perip_rst_en.modify(|_, w| w.$i2c_extX().set_bit());
+                    dport.perip_rst_en.modify(|_, w| w.$i2c_extX().clear_bit());
+                    self
+                }
+
+                pub fn enable(&mut self, dport: &mut esp32::DPORT) -> &mut Self {
+                    dport.perip_clk_en.modify(|_, w| w.$i2c_extX().set_bit());
+                    dport.perip_rst_en.modify(|_, w| w.$i2c_extX().clear_bit());
+                    self
+                }
+
+                pub fn disable(&mut self, dport: &mut esp32::DPORT) -> &mut Self {
+                    dport.perip_clk_en.modify(|_, w| w.$i2c_extX().clear_bit());
+                    dport.perip_rst_en.modify(|_, w| w.$i2c_extX().set_bit());
+                    self
+                }
+
+                /// Initialize as slave with the FIFO interrupts enabled
+                fn init(&mut self, address: Address) -> &mut Self {
+                    self.i2c.ctr.write(|w| {
+                        w.ms_mode()
+                            .clear_bit()
+                            .sda_force_out()
+                            .set_bit()
+                            .scl_force_out()
+                            .set_bit()
+                            .clk_en()
+                            .set_bit()
+                    });
+
+                    self.change_address(address);
+
+                    self.i2c.fifo_conf.modify(|_, w| unsafe {
+                        w.nonfifo_en()
+                            .clear_bit()
+                            .fifo_addr_cfg_en()
+                            .clear_bit()
+                            .rx_fifo_full_thrhd()
+                            .bits(RX_FIFO_FULL_THRESHOLD as u8)
+                            .tx_fifo_empty_thrhd()
+                            .bits(TX_FIFO_EMPTY_THRESHOLD as u8)
+                    });
+
+                    unsafe {
+                        self.i2c.sda_hold.write(|w| w.bits(SDA_TIMING));
+                        self.i2c.sda_sample.write(|w| w.bits(SDA_TIMING));
+                    }
+
+                    // filter glitches shorter than 7 APB cycles
+                    self.i2c.scl_filter_cfg.write(|w| unsafe { w.bits(0x8 | 7) });
+                    self.i2c.sda_filter_cfg.write(|w| unsafe { w.bits(0x8 | 7) });
+
+                    self.i2c
+                        .fifo_conf
+                        .modify(|_, w| w.tx_fifo_rst().set_bit().rx_fifo_rst().set_bit());
+                    self.i2c
+                        .fifo_conf
+                        .modify(|_, w| w.tx_fifo_rst().clear_bit().rx_fifo_rst().clear_bit());
+
+                    self.i2c.int_clr.write(|w| unsafe { w.bits(INT_ALL) });
+                    self.i2c.int_ena.write(|w| unsafe {
+                        w.bits(INT_RX_FIFO_FULL | INT_RX_FIFO_OVF | INT_TRANS_COMPLETE)
+                    });
+
+                    self
+                }
+
+                /// Change the slave address
+                pub fn change_address(&mut self, address: Address) -> &mut Self {
+                    let bits = match address {
+                        Address::SevenBit(address) => (address & 0x7f) as u32,
+                        Address::TenBit(address) => (address & 0x3ff) as u32 | 1 << 31,
+                    };
+                    self.i2c.slave_addr.write(|w| unsafe { w.bits(bits) });
+                    self
+                }
+
+                /// Set or remove the callback called from the interrupt handler
+                pub fn set_callback(&mut self, callback: Option<Callback>) {
+                    xtensa_lx6_rt::interrupt::free(|_| $STATE.lock().callback = callback);
+                }
+
+                /// Read received data, returns the number of bytes read
+                pub fn read(&mut self, data: &mut [u8]) -> usize {
+                    xtensa_lx6_rt::interrupt::free(|_| $STATE.lock().buffers.read(data))
+                }
+
+                /// Number of bytes in the receive buffer
+                pub fn received(&self) -> usize {
+                    xtensa_lx6_rt::interrupt::free(|_| $STATE.lock().buffers.received())
+                }
+
+                /// Queue data to be read by the master, returns the number of bytes queued
+                pub fn write(&mut self, data: &[u8]) -> usize {
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        let mut state = $STATE.lock();
+                        let count = state.buffers.write(data);
+                        Self::fill_transmit_fifo(&mut state.buffers.transmit);
+                        count
+                    })
+                }
+
+                /// Return true if received data was lost. Clears the overrun flag.
+                pub fn overrun(&mut self) -> bool {
+                    xtensa_lx6_rt::interrupt::free(|_| $STATE.lock().buffers.overrun())
+                }
+
+                /// Move queued data to the transmit FIFO and enable the transmit FIFO empty
+                /// interrupt if data remains
+                fn fill_transmit_fifo(transmit: &mut RingBuffer) {
+                    // NOTE(unsafe) only called with the state locked
+                    let i2c = unsafe { &*$I2CX::ptr() };
+
+                    let count = ((i2c.sr.read().bits() >> 18) & 0x3f) as usize;
+                    for _ in count..I2C_FIFO_SIZE {
+                        match transmit.pop() {
+                            // NOTE(unsafe) the FIFO needs to be accessed via the AHB address
+                            Some(byte) => unsafe {
+                                write_volatile($fifo_address as *mut u32, byte as u32)
+                            },
+                            None => break,
+                        }
+                    }
+
+                    if !transmit.is_empty() {
+                        i2c.int_ena
+                            .modify(|r, w| unsafe { w.bits(r.bits() | INT_TX_FIFO_EMPTY) });
+                    }
+                }
+
+                /// Handle the interrupt of the peripheral
+                ///
+                /// Needs to be called from the interrupt handler of the peripheral, the pins
+                /// type parameter is irrelevant, e.g. `I2cSlave::<I2C0, ()>::handle_interrupt()`.
+                pub fn handle_interrupt() {
+                    let i2c = unsafe { &*$I2CX::ptr() };
+
+                    let status = i2c.int_status.read().bits();
+                    i2c.int_clr.write(|w| unsafe { w.bits(status) });
+
+                    let mut state = $STATE.lock();
+
+                    if status & (INT_RX_FIFO_FULL | INT_RX_FIFO_OVF | INT_TRANS_COMPLETE) != 0 {
+                        let count = (i2c.sr.read().bits() >> 8) & 0x3f;
+                        for _ in 0..count {
+                            // NOTE(unsafe) the FIFO needs to be accessed via the AHB address
+                            let byte = unsafe { read_volatile($fifo_address as *const u32) };
+                            if !state.buffers.receive.push(byte as u8) {
+                                state.buffers.overrun = true;
+                            }
+                        }
+                        if count > 0 {
+                            state.call(Event::DataReceived);
+                        }
+                    }
+
+                    if status & INT_RX_FIFO_OVF != 0 {
+                        state.buffers.overrun = true;
+                    }
+
+                    if status & INT_TRANS_COMPLETE != 0 {
+                        state.call(Event::TransactionComplete);
+                    }
+
+                    if status & INT_TX_FIFO_EMPTY != 0 && state.buffers.transmit.is_empty() {
+                        state.call(Event::TransmitBufferEmpty);
+                    }
+
+                    Self::fill_transmit_fifo(&mut state.buffers.transmit);
+
+                    // no more data to send: stop the transmit FIFO empty interrupt
+                    if state.buffers.transmit.is_empty() {
+                        i2c.int_ena
+                            .modify(|r, w| unsafe { w.bits(r.bits() & !INT_TX_FIFO_EMPTY) });
+                    }
+                }
+            }
+        )+
+    }
+}
+
+halI2cSlave! {
+    I2C0: (i2c0, i2c_ext0, 0x6001_301c, I2CEXT0_SCL, I2CEXT0_SDA, I2C0_SLAVE_STATE,
+        I2C0_SLAVE_BUFFERS),
+    I2C1: (i2c1, i2c_ext1, 0x6002_701c, I2CEXT1_SCL, I2CEXT1_SDA, I2C1_SLAVE_STATE,
+        I2C1_SLAVE_BUFFERS),
+}
@@ -337,6 +337,25 @@ pub fn get_interrupt_status(core: Core) -> u128 {
     }
 }
 
+/// Return true if a peripheral interrupt is mapped to an enabled CPU interrupt on the current core
+#[ram]
+pub(crate) fn is_enabled(interrupt: Interrupt) -> bool {
+    if interrupt.nr() >= Interrupt::INTERNAL_TIMER0_INTR.nr() {
+        return false;
+    }
+    let cpu_interrupt = unsafe {
+        let base_reg = match crate::get_core() {
+            crate::Core::PRO => (*DPORT::ptr()).pro_mac_intr_map.as_ptr(),
+            crate::Core::APP => (*DPORT::ptr()).app_mac_intr_map.as_ptr(),
+        };
+        CPUInterrupt((*base_reg.add(interrupt.nr() as usize) & 0x1f) as usize)
+    };
+
+    // disabled interrupts (and unmapped ones after reset) are assigned to internal interrupts
+    cpu_interrupt_to_interrupt(cpu_interrupt).is_err()
+        && xtensa_lx6_rt::interrupt::get_mask() & (1 << cpu_interrupt.0) != 0
+}
+
 /// Map an interrupt to a CPU interrupt
 #[ram]
 fn map_interrupt(
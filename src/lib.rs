@@ -16,6 +16,7 @@
 
 #![no_std]
 #![feature(const_fn)]
+#![feature(llvm_asm)]
 #![cfg_attr(feature = "alloc", feature(allocator_api))]
 #![cfg_attr(feature = "alloc", feature(alloc_layout_extra))]
 
@@ -27,15 +28,25 @@ pub use proc_macros::interrupt;
 pub use proc_macros::ram;
 
 pub mod analog;
+pub mod brownout;
 pub mod clock_control;
+pub mod dma;
 pub mod dport;
 pub mod efuse;
 #[cfg(feature = "external_ram")]
 pub mod external_ram;
 pub mod gpio;
+pub mod i2c;
 pub mod interrupt;
 pub mod prelude;
+pub mod reset;
+mod ring_buffer;
+pub mod rtc;
 pub mod serial;
+pub mod sleep;
+pub mod spi;
+pub mod time;
+pub mod timer;
 pub mod units;
 
 #[cfg(feature = "alloc")]
@@ -70,9 +81,11 @@ pub unsafe extern "C" fn ESP32Reset() -> ! {
     // copying data from flash to various data segments is done by the bootloader
     // initialization to zero needs to be done by the application
 
-    // Initialize RTC RAM
-    xtensa_lx6_rt::zero_bss(&mut _rtc_fast_bss_start, &mut _rtc_fast_bss_end);
-    xtensa_lx6_rt::zero_bss(&mut _rtc_slow_bss_start, &mut _rtc_slow_bss_end);
+    // Initialize RTC RAM, unless it holds state retained during deep sleep
+    if reset::reset_reason(Core::PRO) != reset::ResetReason::DeepSleep {
+        xtensa_lx6_rt::zero_bss(&mut _rtc_fast_bss_start, &mut _rtc_fast_bss_end);
+        xtensa_lx6_rt::zero_bss(&mut _rtc_slow_bss_start, &mut _rtc_slow_bss_end);
+    }
 
     #[cfg(feature = "external_ram")]
     external_ram::init();
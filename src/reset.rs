@@ -0,0 +1,304 @@
+//! Reset reason and software reset
+//!
+//! The reason of the last reset is reported per core by [reset_reason]. After a wakeup from
+//! deep sleep, [wakeup_cause] reports the source which woke up the chip.
+//!
+//! The chip can be reset from software in several ways:
+//! - [software_reset] resets the complete digital system, including the peripherals
+//! - [software_reset_cpu] resets a single CPU, the peripherals keep their state
+//! - [restart] resets both CPUs in a controlled way, restarting the application via the
+//!     bootloader
+//! - [panic_restart] restarts like [restart], but the next boot reports [ResetReason::Panic]
+//!
+//! The hardware has no panic reset reason, so [panic_restart] stores a marker in RTC slow memory.
+//! A panic is therefore only reported if the panic handler of the application calls
+//! [panic_restart].
+//!
+//! # Example
+//! ```
+//! match reset::reset_reason(Core::PRO) {
+//!     ResetReason::DeepSleep => dprintln!("Woken up by {:?}", reset::wakeup_cause()),
+//!     ResetReason::BrownOut => dprintln!("Recovered from brown out"),
+//!     reason => dprintln!("Reset by {:?}", reason),
+//! }
+//! ```
+
+use crate::esp32::{DPORT, RTCCNTL};
+use crate::prelude::*;
+use crate::sleep::{
+    WAKEUP_CAUSE_MASK, WAKEUP_EXT0, WAKEUP_EXT1, WAKEUP_GPIO, WAKEUP_TIMER, WAKEUP_TOUCH,
+    WAKEUP_UART0, WAKEUP_UART1, WAKEUP_ULP,
+};
+use crate::Core;
+
+// Bits of the RTC_CNTL_RESET_STATE_REG register
+const RESET_CAUSE_PROCPU_SHIFT: u32 = 0;
+const RESET_CAUSE_APPCPU_SHIFT: u32 = 6;
+const RESET_CAUSE_MASK: u32 = 0x3f;
+
+// Reset reason as reported by RTC_CNTL_RESET_STATE_REG
+const RESET_POWERON: u32 = 1;
+const RESET_SW: u32 = 3;
+const RESET_OWDT: u32 = 4;
+const RESET_DEEPSLEEP: u32 = 5;
+const RESET_SDIO: u32 = 6;
+const RESET_TG0WDT_SYS: u32 = 7;
+const RESET_TG1WDT_SYS: u32 = 8;
+const RESET_RTCWDT_SYS: u32 = 9;
+const RESET_INTRUSION: u32 = 10;
+const RESET_TGWDT_CPU: u32 = 11;
+const RESET_SW_CPU: u32 = 12;
+const RESET_RTCWDT_CPU: u32 = 13;
+const RESET_EXT_CPU: u32 = 14;
+const RESET_RTCWDT_BROWN_OUT: u32 = 15;
+const RESET_RTCWDT_RTC: u32 = 16;
+
+// Recognition pattern of a restart after a panic
+const PANIC_MARKER: u32 = 0x5041_4e43;
+
+// must be uninitialized, because otherwise will be cleared on a reset
+#[ram(rtc_slow, uninitialized)]
+static mut RESTART_MARKER: u32 = 0;
+
+/// Reason of the last reset of a CPU
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResetReason {
+    /// Power on reset
+    PowerOn,
+    /// Software reset of the chip
+    Software,
+    /// Legacy watchdog reset of the chip
+    LegacyWatchdog,
+    /// Wakeup from deep sleep
+    DeepSleep,
+    /// Reset by the SLC module
+    Sdio,
+    /// Timer group 0 watchdog reset of the chip
+    Timg0Watchdog,
+    /// Timer group 1 watchdog reset of the chip
+    Timg1Watchdog,
+    /// RTC watchdog reset of the chip
+    RtcWatchdog,
+    /// Intrusion test reset of the CPU
+    Intrusion,
+    /// Timer group watchdog reset of the CPU
+    TimgWatchdogCpu,
+    /// Software reset of the CPU
+    SoftwareCpu,
+    /// RTC watchdog reset of the CPU
+    RtcWatchdogCpu,
+    /// APP CPU reset by the PRO CPU
+    ExternalCpu,
+    /// Brown out reset
+    BrownOut,
+    /// RTC watchdog reset of the chip including the RTC
+    RtcWatchdogRtc,
+    /// Restart via [panic_restart]
+    Panic,
+    /// Unknown reset reason
+    Unknown(u8),
+}
+
+impl From<u32> for ResetReason {
+    fn from(value: u32) -> Self {
+        match value {
+            RESET_POWERON => ResetReason::PowerOn,
+            RESET_SW => ResetReason::Software,
+            RESET_OWDT => ResetReason::LegacyWatchdog,
+            RESET_DEEPSLEEP => ResetReason::DeepSleep,
+            RESET_SDIO => ResetReason::Sdio,
+            RESET_TG0WDT_SYS => ResetReason::Timg0Watchdog,
+            RESET_TG1WDT_SYS => ResetReason::Timg1Watchdog,
+            RESET_RTCWDT_SYS => ResetReason::RtcWatchdog,
+            RESET_INTRUSION => ResetReason::Intrusion,
+            RESET_TGWDT_CPU => ResetReason::TimgWatchdogCpu,
+            RESET_SW_CPU => ResetReason::SoftwareCpu,
+            RESET_RTCWDT_CPU => ResetReason::RtcWatchdogCpu,
+            RESET_EXT_CPU => ResetReason::ExternalCpu,
+            RESET_RTCWDT_BROWN_OUT => ResetReason::BrownOut,
+            RESET_RTCWDT_RTC => ResetReason::RtcWatchdogRtc,
+            other => ResetReason::Unknown(other as u8),
+        }
+    }
+}
+
+/// Source which woke up the chip from sleep
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WakeupCause {
+    /// Not woken up from sleep (e.g. power on or other reset)
+    Undefined,
+    Ext0,
+    Ext1,
+    Gpio,
+    Timer,
+    Uart0,
+    Uart1,
+    Touchpad,
+    Ulp,
+}
+
+/// Reason of the last reset of the given core
+pub fn reset_reason(core: Core) -> ResetReason {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+    let shift = match core {
+        Core::PRO => RESET_CAUSE_PROCPU_SHIFT,
+        Core::APP => RESET_CAUSE_APPCPU_SHIFT,
+    };
+    let reason = (rtccntl.reset_state.read().bits() >> shift & RESET_CAUSE_MASK).into();
+
+    // the marker is only valid after a restart, as it survives all resets except power on
+    match reason {
+        ResetReason::SoftwareCpu
+            if unsafe { core::ptr::read_volatile(&RESTART_MARKER) } == PANIC_MARKER =>
+        {
+            ResetReason::Panic
+        }
+        reason => reason,
+    }
+}
+
+/// Source which woke up the chip from deep sleep
+///
+/// Returns [WakeupCause::Undefined] if the last reset was not a deep sleep wakeup.
+pub fn wakeup_cause() -> WakeupCause {
+    if reset_reason(Core::PRO) != ResetReason::DeepSleep {
+        return WakeupCause::Undefined;
+    }
+
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+    decode_wakeup_cause(rtccntl.wakeup_state.read().bits())
+}
+
+/// Decode the wakeup cause from the RTC_CNTL_WAKEUP_STATE_REG register
+pub(crate) fn decode_wakeup_cause(wakeup_state: u32) -> WakeupCause {
+    let cause = wakeup_state & WAKEUP_CAUSE_MASK;
+
+    if cause & WAKEUP_EXT0 != 0 {
+        WakeupCause::Ext0
+    } else if cause & WAKEUP_EXT1 != 0 {
+        WakeupCause::Ext1
+    } else if cause & WAKEUP_GPIO != 0 {
+        WakeupCause::Gpio
+    } else if cause & WAKEUP_TIMER != 0 {
+        WakeupCause::Timer
+    } else if cause & WAKEUP_UART0 != 0 {
+        WakeupCause::Uart0
+    } else if cause & WAKEUP_UART1 != 0 {
+        WakeupCause::Uart1
+    } else if cause & WAKEUP_TOUCH != 0 {
+        WakeupCause::Touchpad
+    } else if cause & WAKEUP_ULP != 0 {
+        WakeupCause::Ulp
+    } else {
+        WakeupCause::Undefined
+    }
+}
+
+/// Stall or unstall a core
+fn stall_core(core: Core, stall: bool) {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+    let (c1, c0) = if stall { (0x21, 0x02) } else { (0, 0) };
+
+    match core {
+        Core::PRO => {
+            rtccntl
+                .sw_cpu_stall
+                .modify(|_, w| unsafe { w.sw_stall_procpu_c1().bits(c1) });
+            rtccntl
+                .options0
+                .modify(|_, w| unsafe { w.sw_stall_procpu_c0().bits(c0) });
+        }
+        Core::APP => {
+            rtccntl
+                .sw_cpu_stall
+                .modify(|_, w| unsafe { w.sw_stall_appcpu_c1().bits(c1) });
+            rtccntl
+                .options0
+                .modify(|_, w| unsafe { w.sw_stall_appcpu_c0().bits(c0) });
+        }
+    }
+}
+
+/// Reset the complete digital system, including the peripherals
+///
+/// The reset reason after the reset is [ResetReason::Software].
+pub fn software_reset() -> ! {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+    rtccntl.options0.modify(|_, w| w.sw_sys_rst().set_bit());
+
+    loop {}
+}
+
+/// Reset a single CPU, the peripherals keep their state
+///
+/// The reset reason of the CPU after the reset is [ResetReason::SoftwareCpu]. When resetting
+/// the current core, this function does not return.
+pub fn software_reset_cpu(core: Core) {
+    if core == crate::get_core() {
+        set_restart_marker(0);
+        reset_cpu(core);
+        loop {}
+    }
+
+    reset_cpu(core);
+}
+
+fn reset_cpu(core: Core) {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+    match core {
+        Core::PRO => rtccntl.options0.modify(|_, w| w.sw_procpu_rst().set_bit()),
+        Core::APP => rtccntl.options0.modify(|_, w| w.sw_appcpu_rst().set_bit()),
+    }
+}
+
+fn set_restart_marker(marker: u32) {
+    unsafe { core::ptr::write_volatile(&mut RESTART_MARKER, marker) };
+}
+
+/// Restart the application
+///
+/// The other core is stalled and both CPUs are reset, after which the application is
+/// started again by the bootloader. The reset reason after the restart is
+/// [ResetReason::SoftwareCpu].
+pub fn restart() -> ! {
+    set_restart_marker(0);
+    restart_cpus()
+}
+
+/// Restart the application after a panic
+///
+/// Restarts like [restart], but the reset reason after the restart is [ResetReason::Panic].
+/// To be called from the panic handler of the application.
+pub fn panic_restart() -> ! {
+    set_restart_marker(PANIC_MARKER);
+    restart_cpus()
+}
+
+fn restart_cpus() -> ! {
+    let dport = unsafe { &*DPORT::ptr() };
+
+    xtensa_lx6_rt::interrupt::free(|_| {
+        // stall the other core first, so it does not run while the current core is reset
+        let other = crate::get_other_core();
+        stall_core(other, true);
+
+        // clear the entry point of the APP CPU, so it is not started before it is initialized
+        dport.appcpu_ctrl_d.write(|w| unsafe { w.bits(0) });
+
+        match crate::get_core() {
+            Core::PRO => {
+                // the APP CPU is stalled, so both CPUs can be reset
+                reset_cpu(Core::APP);
+                reset_cpu(Core::PRO);
+            }
+            Core::APP => {
+                // the PRO CPU needs to be reset and unstalled to run the bootloader
+                reset_cpu(Core::PRO);
+                stall_core(Core::PRO, false);
+                reset_cpu(Core::APP);
+            }
+        }
+    });
+
+    loop {}
+}
@@ -0,0 +1,102 @@
+//! Byte ring buffer on top of static storage
+//!
+//! Used to exchange data between interrupt handlers and the application. The buffer itself is not
+//! synchronized: it is expected to be wrapped in a mutex which is locked inside an
+//! [interrupt::free](xtensa_lx6_rt::interrupt::free) section.
+
+pub struct RingBuffer {
+    buffer: *mut u8,
+    capacity: usize,
+    read: usize,
+    len: usize,
+}
+
+// NOTE(unsafe) the storage is a static buffer exclusively owned by the ring buffer
+unsafe impl Send for RingBuffer {}
+
+impl RingBuffer {
+    /// Ring buffer without storage, used for static initialization
+    pub const fn empty() -> Self {
+        RingBuffer {
+            buffer: core::ptr::null_mut(),
+            capacity: 0,
+            read: 0,
+            len: 0,
+        }
+    }
+
+    pub fn new(buffer: &'static mut [u8]) -> Self {
+        RingBuffer {
+            buffer: buffer.as_mut_ptr(),
+            capacity: buffer.len(),
+            read: 0,
+            len: 0,
+        }
+    }
+
+    /// Number of bytes in the buffer
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.read = 0;
+        self.len = 0;
+    }
+
+    /// Add a byte, returns false if the buffer is full
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        // NOTE(unsafe) index is within the storage
+        unsafe { *self.buffer.add((self.read + self.len) % self.capacity) = byte };
+        self.len += 1;
+        true
+    }
+
+    /// Remove the oldest byte
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        // NOTE(unsafe) index is within the storage
+        let byte = unsafe { *self.buffer.add(self.read) };
+        self.read = (self.read + 1) % self.capacity;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    /// Add as many bytes as fit, returns the number of bytes added
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let mut count = 0;
+        for byte in data {
+            if !self.push(*byte) {
+                break;
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Remove up to `data.len()` bytes, returns the number of bytes removed
+    pub fn read(&mut self, data: &mut [u8]) -> usize {
+        let mut count = 0;
+        for byte in data.iter_mut() {
+            match self.pop() {
+                Some(value) => *byte = value,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+}
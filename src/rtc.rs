@@ -0,0 +1,178 @@
+//! RTC time and wall clock
+//!
+//! The RTC contains a 48-bit counter running from the slow RTC clock. It keeps counting
+//! during light and deep sleep and across all resets, except power on and RTC resets. The
+//! counter is converted to time using the (calibrated) slow RTC frequency from
+//! [ClockControlConfig::slow_rtc_frequency]. For accurate time keeping use the 32kHz Xtal as
+//! slow RTC source or calibrate the slow RTC clock via
+//! [ClockControlConfig::calibrate_slow_rtc_frequency].
+//!
+//! The wall clock time (microseconds since the UNIX epoch) is kept as reference point of the
+//! RTC counter in RTC slow memory, so it survives deep sleep. The RTC slow memory therefore must
+//! not be powered down during deep sleep.
+//!
+//! An alarm at a specific RTC counter value can be used to wake up from sleep via
+//! [Config::alarm_wakeup](crate::sleep::config::Config::alarm_wakeup).
+//!
+//! # Example
+//! ```
+//! if rtc::unix_time(clock_control_config).is_none() {
+//!     rtc::set_unix_time(1_600_000_000);
+//! }
+//!
+//! // wake up at the start of the next minute
+//! let next_minute = (rtc::unix_time(clock_control_config).unwrap() / 60 + 1) * 60;
+//! let alarm = rtc::unix_time_to_counter(clock_control_config, next_minute * 1_000_000).unwrap();
+//! sleep::deep_sleep(
+//!     sleep::config::Config::default().alarm_wakeup(alarm),
+//!     clock_control_config,
+//! );
+//! ```
+
+use crate::clock_control::ClockControlConfig;
+use crate::esp32::RTCCNTL;
+use crate::prelude::*;
+use core::mem::MaybeUninit;
+
+// Bits of the RTC_CNTL_TIME_UPDATE_REG register
+const TIME_UPDATE: u32 = 1 << 31;
+const TIME_VALID: u32 = 1 << 30;
+
+/// Maximum value of the 48-bit RTC counter
+pub const COUNTER_MAX: u64 = (1 << 48) - 1;
+
+// Recognition pattern of a valid wall clock reference
+const WALL_CLOCK_MAGIC: u32 = 0x5754_4331;
+
+const MICROSECONDS_PER_SECOND: u64 = 1_000_000;
+
+/// Reference point of the wall clock: the UNIX time at a specific RTC counter value
+#[derive(Copy, Clone)]
+struct WallClock {
+    magic: u32,
+    counter: u64,
+    unix_time_us: u64,
+}
+
+// must be uninitialized, because otherwise will be cleared on a reset
+#[ram(rtc_slow, uninitialized)]
+static mut WALL_CLOCK: MaybeUninit<WallClock> = MaybeUninit::uninit();
+
+/// Read the 48-bit RTC counter (in slow RTC clock cycles)
+pub fn counter() -> u64 {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    rtccntl
+        .time_update
+        .write(|w| unsafe { w.bits(TIME_UPDATE) });
+    while rtccntl.time_update.read().bits() & TIME_VALID == 0 {}
+
+    ((rtccntl.time1.read().bits() as u64 & 0xffff) << 32) | rtccntl.time0.read().bits() as u64
+}
+
+/// Convert a number of RTC counter ticks to microseconds
+fn ticks_to_us(ticks: u64, frequency: Hertz) -> u64 {
+    let frequency = u32::from(frequency) as u64;
+
+    // split to prevent overflow of the 48-bit counter times a million
+    ticks / frequency * MICROSECONDS_PER_SECOND
+        + ticks % frequency * MICROSECONDS_PER_SECOND / frequency
+}
+
+/// Convert microseconds to a number of RTC counter ticks
+fn us_to_ticks(time_us: u64, frequency: Hertz) -> u64 {
+    let frequency = u32::from(frequency) as u64;
+
+    time_us / MICROSECONDS_PER_SECOND * frequency
+        + time_us % MICROSECONDS_PER_SECOND * frequency / MICROSECONDS_PER_SECOND
+}
+
+/// Time since the RTC counter was reset in microseconds
+pub fn time_us(clock_control_config: ClockControlConfig) -> u64 {
+    ticks_to_us(counter(), clock_control_config.slow_rtc_frequency())
+}
+
+/// Get the wall clock reference if it has been set since the RTC counter was reset
+fn wall_clock(counter: u64) -> Option<WallClock> {
+    let wall_clock = unsafe { WALL_CLOCK.assume_init() };
+
+    if wall_clock.magic == WALL_CLOCK_MAGIC && wall_clock.counter <= counter {
+        Some(wall_clock)
+    } else {
+        None
+    }
+}
+
+/// Set the wall clock to the given number of microseconds since the UNIX epoch
+pub fn set_unix_time_us(unix_time_us: u64) {
+    xtensa_lx6_rt::interrupt::free(|_| unsafe {
+        WALL_CLOCK = MaybeUninit::new(WallClock {
+            magic: WALL_CLOCK_MAGIC,
+            counter: counter(),
+            unix_time_us,
+        })
+    });
+}
+
+/// Set the wall clock to the given number of seconds since the UNIX epoch
+pub fn set_unix_time(unix_time: u64) {
+    set_unix_time_us(unix_time * MICROSECONDS_PER_SECOND);
+}
+
+/// Microseconds since the UNIX epoch, None if the wall clock has not been set
+pub fn unix_time_us(clock_control_config: ClockControlConfig) -> Option<u64> {
+    let counter = counter();
+    let wall_clock = wall_clock(counter)?;
+
+    Some(
+        wall_clock.unix_time_us
+            + ticks_to_us(
+                counter - wall_clock.counter,
+                clock_control_config.slow_rtc_frequency(),
+            ),
+    )
+}
+
+/// Seconds since the UNIX epoch, None if the wall clock has not been set
+pub fn unix_time(clock_control_config: ClockControlConfig) -> Option<u64> {
+    Some(unix_time_us(clock_control_config)? / MICROSECONDS_PER_SECOND)
+}
+
+/// Clear the wall clock
+pub fn clear_unix_time() {
+    xtensa_lx6_rt::interrupt::free(|_| unsafe {
+        WALL_CLOCK = MaybeUninit::new(WallClock {
+            magic: 0,
+            counter: 0,
+            unix_time_us: 0,
+        })
+    });
+}
+
+/// Calculate the RTC counter value at the given time since the RTC counter was reset
+pub fn time_us_to_counter(clock_control_config: ClockControlConfig, time_us: u64) -> u64 {
+    us_to_ticks(time_us, clock_control_config.slow_rtc_frequency())
+}
+
+/// Calculate the RTC counter value at the given number of microseconds since the UNIX epoch
+///
+/// Returns None if the wall clock has not been set or if the time is in the past.
+pub fn unix_time_to_counter(
+    clock_control_config: ClockControlConfig,
+    unix_time_us: u64,
+) -> Option<u64> {
+    let counter = counter();
+    let wall_clock = wall_clock(counter)?;
+
+    let alarm = wall_clock.counter
+        + us_to_ticks(
+            unix_time_us.checked_sub(wall_clock.unix_time_us)?,
+            clock_control_config.slow_rtc_frequency(),
+        );
+
+    if alarm < counter || alarm > COUNTER_MAX {
+        None
+    } else {
+        Some(alarm)
+    }
+}
@@ -0,0 +1,485 @@
+//! Interrupt driven serial communication with ring buffers
+//!
+//! The interrupts are handled by [BufferedSerial::handle_interrupt], which has to be called from
+//! the UART0_INTR, UART1_INTR or UART2_INTR interrupt handler defined by the application. For a
+//! [BufferedSerial] it moves the data between the FIFOs of the peripheral and the receive and
+//! transmit ring buffers and queues the positions of detected patterns. In RS-485 mode it releases
+//! the driver enable once transmission is done.
+//! Events enabled via [Serial::listen] are passed to the callback set via [Serial::set_callback].
+//!
+//! The interrupt needs to be enabled via [interrupt::enable](crate::interrupt::enable) or
+//! [interrupt::enable_with_priority](crate::interrupt::enable_with_priority).
+//!
+//! # Example
+//! ```
+//! #[interrupt]
+//! fn UART2_INTR() {
+//!     BufferedSerial::<UART2, ()>::handle_interrupt();
+//! }
+//!
+//! static mut RX_BUFFER: [u8; 1024] = [0; 1024];
+//! static mut TX_BUFFER: [u8; 256] = [0; 256];
+//!
+//! let mut serial = serial.into_buffered(unsafe { &mut RX_BUFFER }, unsafe { &mut TX_BUFFER });
+//! interrupt::enable(Interrupt::UART2_INTR).unwrap();
+//!
+//! let mut data = [0u8; 64];
+//! let count = serial.read_buffer(&mut data);
+//! ```
+
+use core::convert::Infallible;
+
+use embedded_hal::serial;
+
+use super::{
+    config, Error, Event, Serial, Tx, AT_CMD_CHAR_NUM_SHIFT, INT_AT_CMD_CHAR_DET, INT_FRM_ERR,
+    INT_PARITY_ERR, INT_RS485_CLASH, INT_RXFIFO_FULL, INT_RXFIFO_OVF, INT_RXFIFO_TOUT,
+    INT_TXFIFO_EMPTY, INT_TX_BRK_DONE, INT_TX_DONE, UART_FIFO_SIZE,
+};
+use crate::esp32::{UART0, UART1, UART2};
+use crate::ring_buffer::RingBuffer;
+
+/// Receive FIFO full threshold used in buffered mode
+const BUFFERED_RX_FIFO_FULL_THRESHOLD: u8 = 96;
+/// Transmit FIFO empty threshold used in buffered mode
+const BUFFERED_TX_FIFO_EMPTY_THRESHOLD: u8 = 32;
+/// Receive timeout in byte times used in buffered mode
+const BUFFERED_RX_TIMEOUT: u8 = 10;
+/// Maximum number of queued pattern positions
+const PATTERN_QUEUE_SIZE: usize = 16;
+
+/// Callback called from the UART interrupt handler for each event listened to
+pub type Callback = fn(Event);
+
+/// Positions of detected patterns in the received data stream
+///
+/// The positions are counted from the start of buffered operation, so they stay valid while data
+/// is read from the receive buffer.
+struct PatternQueue {
+    positions: [usize; PATTERN_QUEUE_SIZE],
+    len: usize,
+}
+
+impl PatternQueue {
+    const fn new() -> Self {
+        PatternQueue {
+            positions: [0; PATTERN_QUEUE_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Add a position, drops the oldest position if the queue is full
+    fn push(&mut self, position: usize) {
+        if self.len == PATTERN_QUEUE_SIZE {
+            self.remove_first();
+        }
+        self.positions[self.len] = position;
+        self.len += 1;
+    }
+
+    fn first(&self) -> Option<usize> {
+        if self.len > 0 {
+            Some(self.positions[0])
+        } else {
+            None
+        }
+    }
+
+    fn remove_first(&mut self) {
+        if self.len > 0 {
+            self.positions.copy_within(1..self.len, 0);
+            self.len -= 1;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+struct State {
+    buffered: bool,
+    receive: RingBuffer,
+    transmit: RingBuffer,
+    /// Number of bytes added to the receive buffer (wrapping)
+    received_count: usize,
+    /// Number of bytes read from the receive buffer (wrapping)
+    read_count: usize,
+    patterns: PatternQueue,
+    error: Option<Error>,
+    callback: Option<Callback>,
+}
+
+impl State {
+    const fn new() -> Self {
+        State {
+            buffered: false,
+            receive: RingBuffer::empty(),
+            transmit: RingBuffer::empty(),
+            received_count: 0,
+            read_count: 0,
+            patterns: PatternQueue::new(),
+            error: None,
+            callback: None,
+        }
+    }
+
+    /// Reset the receive and transmit buffers and the pattern positions
+    fn reset(&mut self, receive: RingBuffer, transmit: RingBuffer) {
+        self.receive = receive;
+        self.transmit = transmit;
+        self.received_count = 0;
+        self.read_count = 0;
+        self.patterns.clear();
+        self.error = None;
+    }
+
+    /// Position of the oldest pattern relative to the next byte to be read
+    ///
+    /// Patterns of which the start has already been read are discarded.
+    fn pattern_position(&mut self) -> Option<usize> {
+        while let Some(position) = self.patterns.first() {
+            let offset = position.wrapping_sub(self.read_count);
+            if offset <= self.receive.len() {
+                return Some(offset);
+            }
+            self.patterns.remove_first();
+        }
+        None
+    }
+}
+
+/// Serial with interrupt driven receive and transmit ring buffers
+///
+/// The size of the ring buffers is determined by the storage passed to
+/// [Serial::into_buffered].
+pub struct BufferedSerial<UART, PINS> {
+    serial: Serial<UART, PINS>,
+}
+
+macro_rules! halBufferedUart {
+    ($(
+        $UARTX:ident: ($STATE:ident),
+    )+) => {
+        $(
+            static $STATE: spin::Mutex<State> = spin::Mutex::new(State::new());
+
+            impl<PINS> Serial<$UARTX, PINS> {
+                /// Set or remove the callback called from the interrupt handler
+                ///
+                /// The callback is called outside of the internal lock, so it can use the serial
+                /// functions.
+                pub fn set_callback(&mut self, callback: Option<Callback>) {
+                    xtensa_lx6_rt::interrupt::free(|_| $STATE.lock().callback = callback);
+                }
+
+                /// Convert into an interrupt driven serial with receive and transmit ring
+                /// buffers using the given storage
+                pub fn into_buffered(
+                    mut self,
+                    receive: &'static mut [u8],
+                    transmit: &'static mut [u8],
+                ) -> BufferedSerial<$UARTX, PINS> {
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        let mut state = $STATE.lock();
+                        state.reset(RingBuffer::new(receive), RingBuffer::new(transmit));
+                        state.buffered = true;
+                    });
+
+                    self.change_rx_fifo_full_threshold(BUFFERED_RX_FIFO_FULL_THRESHOLD)
+                        .change_tx_fifo_empty_threshold(BUFFERED_TX_FIFO_EMPTY_THRESHOLD)
+                        .change_rx_timeout(Some(BUFFERED_RX_TIMEOUT));
+
+                    for event in [
+                        Event::RxFifoFull,
+                        Event::RxTimeout,
+                        Event::RxFifoOverflow,
+                        Event::ParityError,
+                        Event::FrameError,
+                    ]
+                    .iter()
+                    {
+                        self.listen(*event);
+                    }
+
+                    BufferedSerial { serial: self }
+                }
+            }
+
+            impl<PINS> BufferedSerial<$UARTX, PINS> {
+                /// Stop interrupt driven operation and return the serial
+                ///
+                /// Waits until all data has been transmitted. Received data which has not been
+                /// read is discarded.
+                pub fn into_serial(mut self) -> Serial<$UARTX, PINS> {
+                    Self::drain_transmit_buffer();
+                    nb::block!(serial::Write::flush(&mut self.serial)).unwrap();
+
+                    for event in Event::ALL.iter() {
+                        self.serial.unlisten(*event);
+                    }
+
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        let mut state = $STATE.lock();
+                        state.buffered = false;
+                        state.reset(RingBuffer::empty(), RingBuffer::empty());
+                    });
+
+                    self.serial
+                }
+
+                /// Set or remove the callback called from the interrupt handler
+                pub fn set_callback(&mut self, callback: Option<Callback>) {
+                    self.serial.set_callback(callback);
+                }
+
+                /// Read received data, returns the number of bytes read
+                pub fn read_buffer(&mut self, data: &mut [u8]) -> usize {
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        let mut state = $STATE.lock();
+                        let count = state.receive.read(data);
+                        state.read_count = state.read_count.wrapping_add(count);
+                        count
+                    })
+                }
+
+                /// Queue data for transmission, returns the number of bytes queued
+                pub fn write_buffer(&mut self, data: &[u8]) -> usize {
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        let mut state = $STATE.lock();
+                        let count = state.transmit.write(data);
+                        Self::fill_tx_fifo(&mut state.transmit);
+                        count
+                    })
+                }
+
+                /// Number of bytes in the receive buffer
+                pub fn received(&self) -> usize {
+                    xtensa_lx6_rt::interrupt::free(|_| $STATE.lock().receive.len())
+                }
+
+                /// Send a break of `length` bit times (1-255) after all queued data
+                ///
+                /// Blocks until the break has been sent.
+                pub fn send_break(&mut self, length: u8) {
+                    Self::drain_transmit_buffer();
+                    self.serial.send_break(length);
+                }
+
+                /// Enable the detection of a repeated pattern character
+                ///
+                /// Previously queued pattern positions are discarded.
+                pub fn enable_pattern_detection(&mut self, pattern: config::Pattern) -> &mut Self {
+                    xtensa_lx6_rt::interrupt::free(|_| $STATE.lock().patterns.clear());
+                    self.serial.enable_pattern_detection(pattern);
+                    self
+                }
+
+                /// Disable the detection of the pattern character and discard the queued
+                /// positions
+                pub fn disable_pattern_detection(&mut self) -> &mut Self {
+                    self.serial.disable_pattern_detection();
+                    xtensa_lx6_rt::interrupt::free(|_| $STATE.lock().patterns.clear());
+                    self
+                }
+
+                /// Position of the oldest detected pattern in the receive buffer
+                ///
+                /// The position is the number of bytes to read before the first pattern
+                /// character. Up to 16 positions are queued, when more patterns are detected
+                /// the oldest positions are dropped.
+                pub fn pattern_position(&self) -> Option<usize> {
+                    xtensa_lx6_rt::interrupt::free(|_| $STATE.lock().pattern_position())
+                }
+
+                /// Return and remove the position of the oldest detected pattern
+                pub fn pop_pattern_position(&mut self) -> Option<usize> {
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        let mut state = $STATE.lock();
+                        let position = state.pattern_position();
+                        state.patterns.remove_first();
+                        position
+                    })
+                }
+
+                /// Return and clear the first receive error since the last call
+                pub fn take_error(&mut self) -> Option<Error> {
+                    xtensa_lx6_rt::interrupt::free(|_| $STATE.lock().error.take())
+                }
+
+                /// Move data from the receive FIFO to the receive buffer
+                fn drain_rx_fifo(state: &mut State) {
+                    let uart = unsafe { &*$UARTX::ptr() };
+                    while uart.status.read().rxfifo_cnt().bits() > 0 {
+                        if state.receive.push(uart.rx_fifo.read().bits()) {
+                            state.received_count = state.received_count.wrapping_add(1);
+                        } else {
+                            state.error.get_or_insert(Error::Overrun);
+                        }
+                    }
+                }
+
+                /// Wait until the transmit buffer is empty
+                ///
+                /// The data is moved to the transmit FIFO from here as well, so this does not
+                /// depend on the interrupt being enabled or handled.
+                fn drain_transmit_buffer() {
+                    while xtensa_lx6_rt::interrupt::free(|_| {
+                        let mut state = $STATE.lock();
+                        Self::fill_tx_fifo(&mut state.transmit);
+                        !state.transmit.is_empty()
+                    }) {}
+                }
+
+                /// Move data from the transmit buffer to the transmit FIFO and enable the
+                /// transmit FIFO empty interrupt as long as data remains
+                fn fill_tx_fifo(transmit: &mut RingBuffer) {
+                    let uart = unsafe { &*$UARTX::ptr() };
+                    if !transmit.is_empty() {
+                        Tx::<$UARTX>::set_driver_enable(true);
+                    }
+                    while uart.status.read().txfifo_cnt().bits() < UART_FIFO_SIZE {
+                        match transmit.pop() {
+                            Some(byte) => unsafe {
+                                uart.tx_fifo.write_with_zero(|w| w.bits(byte))
+                            },
+                            None => break,
+                        }
+                    }
+
+                    uart.int_ena.modify(|r, w| unsafe {
+                        if transmit.is_empty() {
+                            w.bits(r.bits() & !INT_TXFIFO_EMPTY)
+                        } else {
+                            w.bits(r.bits() | INT_TXFIFO_EMPTY)
+                        }
+                    });
+                }
+
+                /// Handle the interrupt of the peripheral
+                ///
+                /// Needs to be called from the interrupt handler of the peripheral, the pins
+                /// type parameter is irrelevant, e.g.
+                /// `BufferedSerial::<UART0, ()>::handle_interrupt()`.
+                pub fn handle_interrupt() {
+                    let uart = unsafe { &*$UARTX::ptr() };
+                    let status = uart.int_st.read().bits();
+
+                    let callback = {
+                        let mut state = $STATE.lock();
+
+                        if state.buffered {
+                            if status
+                                & (INT_RXFIFO_FULL
+                                    | INT_RXFIFO_TOUT
+                                    | INT_RXFIFO_OVF
+                                    | INT_AT_CMD_CHAR_DET)
+                                != 0
+                            {
+                                Self::drain_rx_fifo(&mut state);
+                            }
+                            if status & INT_AT_CMD_CHAR_DET != 0 {
+                                // the pattern characters are the last received bytes
+                                let count =
+                                    (uart.at_cmd_char.read().bits() >> AT_CMD_CHAR_NUM_SHIFT) as u8;
+                                let position =
+                                    state.received_count.wrapping_sub(count as usize);
+                                state.patterns.push(position);
+                            }
+                            if status & INT_RXFIFO_OVF != 0 {
+                                state.error.get_or_insert(Error::Overrun);
+                            }
+                            if status & INT_PARITY_ERR != 0 {
+                                state.error.get_or_insert(Error::Parity);
+                            }
+                            if status & INT_FRM_ERR != 0 {
+                                state.error.get_or_insert(Error::Framing);
+                            }
+                            if status & INT_RS485_CLASH != 0 {
+                                state.error.get_or_insert(Error::Collision);
+                            }
+                            if status & INT_TXFIFO_EMPTY != 0 {
+                                Self::fill_tx_fifo(&mut state.transmit);
+                            }
+                        }
+
+                        if status & INT_TX_BRK_DONE != 0 {
+                            Serial::<$UARTX, ()>::end_break();
+                        }
+                        if status & INT_TX_DONE != 0 && state.transmit.is_empty() {
+                            Tx::<$UARTX>::set_driver_enable(false);
+                        }
+
+                        state.callback
+                    };
+
+                    uart.int_clr.write(|w| unsafe { w.bits(status) });
+
+                    if let Some(callback) = callback {
+                        for event in Event::ALL.iter() {
+                            if status & event.bits() != 0 {
+                                callback(*event);
+                            }
+                        }
+                    }
+                }
+            }
+
+            impl<PINS> serial::Read<u8> for BufferedSerial<$UARTX, PINS> {
+                type Error = Error;
+
+                /// Read a byte from the receive buffer. Receive errors are reported once,
+                /// before the next byte.
+                fn read(&mut self) -> nb::Result<u8, Self::Error> {
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        let mut state = $STATE.lock();
+                        if let Some(error) = state.error.take() {
+                            return Err(nb::Error::Other(error));
+                        }
+                        let byte = state.receive.pop().ok_or(nb::Error::WouldBlock)?;
+                        state.read_count = state.read_count.wrapping_add(1);
+                        Ok(byte)
+                    })
+                }
+            }
+
+            impl<PINS> serial::Write<u8> for BufferedSerial<$UARTX, PINS> {
+                type Error = Infallible;
+
+                fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                    let empty =
+                        xtensa_lx6_rt::interrupt::free(|_| $STATE.lock().transmit.is_empty());
+                    if empty && self.serial.is_tx_idle() {
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+                    if self.write_buffer(&[byte]) == 1 {
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl<PINS> core::fmt::Write for BufferedSerial<$UARTX, PINS> {
+                fn write_str(&mut self, s: &str) -> core::fmt::Result {
+                    use embedded_hal::serial::Write;
+                    s.as_bytes()
+                        .iter()
+                        .try_for_each(|c| nb::block!(self.write(*c)))
+                        .map_err(|_| core::fmt::Error)
+                }
+            }
+        )+
+    }
+}
+
+halBufferedUart! {
+    UART0: (UART0_STATE),
+    UART1: (UART1_STATE),
+    UART2: (UART2_STATE),
+}
@@ -0,0 +1,1101 @@
+//! UART peripheral control
+//!
+//! Controls the 3 uart peripherals (UART0, UART1, UART2)
+//!
+//! **It currently depends on the clock to be configured with default settings.**
+//! (Tested for UART 0)
+//!
+//! Interrupt driven communication with ring buffers is provided by [BufferedSerial].
+//!
+//! The TX, RX, RTS and CTS signals are routed via the GPIO matrix to the pins passed as
+//! `(tx, rx)` or `(tx, rx, rts, cts)`, any output pin can be used for TX and RTS and any input
+//! pin for RX and CTS. Hardware flow control and RS-485 half duplex mode can be configured via
+//! [Config](config::Config).
+//!
+//! In RS-485 mode the RTS pin drives the driver enable (DE) input of the transceiver. It is
+//! asserted when data is written and deasserted by the UART interrupt handler when the
+//! transmission is done (or by flush if the UART interrupt is not enabled).
+//!
+//! Breaks can be sent via [send_break](Serial::send_break) and received breaks are reported via
+//! [Event::Break], which allows the implementation of LIN. The hardware can also detect a
+//! repeated pattern character surrounded by idle periods (like the `+++` escape sequence of
+//! AT-command modems), see [enable_pattern_detection](Serial::enable_pattern_detection). In
+//! buffered mode the positions of the detected patterns are queued by the interrupt handler.
+//!
+//! # TODO
+//! - Add all extra features esp32 supports (eg irda, etc. etc.)
+//! - Free APB lock when TX is idle (and no RX used)
+
+use core::convert::Infallible;
+use core::marker::PhantomData;
+
+use embedded_hal::serial;
+
+use crate::esp32::{UART0, UART1, UART2};
+use crate::gpio::{InputSignal, MatrixInputPin, MatrixOutputPin, OutputSignal};
+use crate::units::*;
+
+pub mod buffered;
+
+pub use buffered::BufferedSerial;
+
+const UART_FIFO_SIZE: u8 = 128;
+
+// Interrupt bits
+const INT_RXFIFO_FULL: u32 = 1 << 0;
+const INT_TXFIFO_EMPTY: u32 = 1 << 1;
+const INT_PARITY_ERR: u32 = 1 << 2;
+const INT_FRM_ERR: u32 = 1 << 3;
+const INT_RXFIFO_OVF: u32 = 1 << 4;
+const INT_BRK_DET: u32 = 1 << 7;
+const INT_RXFIFO_TOUT: u32 = 1 << 8;
+const INT_TX_BRK_DONE: u32 = 1 << 12;
+const INT_TX_DONE: u32 = 1 << 14;
+const INT_RS485_CLASH: u32 = 1 << 17;
+const INT_AT_CMD_CHAR_DET: u32 = 1 << 18;
+
+// Bits of the conf0 register
+const CONF0_SW_RTS: u32 = 1 << 6;
+const CONF0_TXD_BRK: u32 = 1 << 8;
+const CONF0_TX_FLOW_EN: u32 = 1 << 15;
+
+// Bits of the conf1 register
+const CONF1_RXFIFO_FULL_THRHD_MASK: u32 = 0x7f;
+const CONF1_TXFIFO_EMPTY_THRHD_SHIFT: u32 = 8;
+const CONF1_TXFIFO_EMPTY_THRHD_MASK: u32 = 0x7f << CONF1_TXFIFO_EMPTY_THRHD_SHIFT;
+const CONF1_RX_TOUT_THRHD_SHIFT: u32 = 24;
+const CONF1_RX_TOUT_THRHD_MASK: u32 = 0x7f << CONF1_RX_TOUT_THRHD_SHIFT;
+const CONF1_RX_FLOW_THRHD_SHIFT: u32 = 16;
+const CONF1_RX_FLOW_THRHD_MASK: u32 = 0x7f << CONF1_RX_FLOW_THRHD_SHIFT;
+const CONF1_RX_FLOW_EN: u32 = 1 << 23;
+const CONF1_RX_TOUT_EN: u32 = 1 << 31;
+
+// Bits of the rs485_conf register
+const RS485_CONF_EN: u32 = 1 << 0;
+const RS485_CONF_TX_RX_EN: u32 = 1 << 3;
+const RS485_CONF_RX_BUSY_TX_EN: u32 = 1 << 4;
+
+// Bits of the idle_conf register
+const IDLE_CONF_RX_IDLE_THRHD_MASK: u32 = 0x3ff;
+const IDLE_CONF_TX_IDLE_NUM_SHIFT: u32 = 10;
+const IDLE_CONF_TX_IDLE_NUM_MAX: u32 = 0x3ff;
+const IDLE_CONF_TX_IDLE_NUM_MASK: u32 = IDLE_CONF_TX_IDLE_NUM_MAX << IDLE_CONF_TX_IDLE_NUM_SHIFT;
+const IDLE_CONF_TX_BRK_NUM_SHIFT: u32 = 20;
+const IDLE_CONF_TX_BRK_NUM_MASK: u32 = 0xff << IDLE_CONF_TX_BRK_NUM_SHIFT;
+
+// Mask of the at_cmd_precnt, at_cmd_postcnt and at_cmd_gaptout registers
+const AT_CMD_COUNT_MASK: u32 = 0xff_ffff;
+// Bits of the at_cmd_char register
+const AT_CMD_CHAR_NUM_SHIFT: u32 = 8;
+
+/// Serial error
+#[derive(Debug)]
+pub enum Error {
+    /// Framing error
+    Framing,
+    /// Noise error
+    Noise,
+    /// RX buffer overrun
+    Overrun,
+    /// Parity check error
+    Parity,
+    /// Baudrate too low
+    BaudrateTooLow,
+    /// Baudrate too high
+    BaudrateTooHigh,
+    /// RTS flow control cannot be combined with RS-485 mode
+    InvalidConfig,
+    /// RS-485 collision detected
+    Collision,
+}
+
+/// Interrupt event
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Event {
+    /// The receive FIFO contains more bytes than the full threshold
+    RxFifoFull,
+    /// No data has been received for the receive timeout after the last byte
+    RxTimeout,
+    /// The transmit FIFO contains less bytes than the empty threshold
+    TxFifoEmpty,
+    /// Data has been received while the receive FIFO was full
+    RxFifoOverflow,
+    /// A break condition has been detected
+    Break,
+    /// A parity error has been detected
+    ParityError,
+    /// A frame error has been detected
+    FrameError,
+    /// A collision has been detected in RS-485 mode
+    Rs485Collision,
+    /// A break has been sent
+    BreakSent,
+    /// The pattern set via [enable_pattern_detection](Serial::enable_pattern_detection) has
+    /// been detected
+    PatternDetected,
+}
+
+impl Event {
+    const ALL: [Event; 10] = [
+        Event::RxFifoFull,
+        Event::RxTimeout,
+        Event::TxFifoEmpty,
+        Event::RxFifoOverflow,
+        Event::Break,
+        Event::ParityError,
+        Event::FrameError,
+        Event::Rs485Collision,
+        Event::BreakSent,
+        Event::PatternDetected,
+    ];
+
+    fn bits(self) -> u32 {
+        match self {
+            Event::RxFifoFull => INT_RXFIFO_FULL,
+            Event::RxTimeout => INT_RXFIFO_TOUT,
+            Event::TxFifoEmpty => INT_TXFIFO_EMPTY,
+            Event::RxFifoOverflow => INT_RXFIFO_OVF,
+            Event::Break => INT_BRK_DET,
+            Event::ParityError => INT_PARITY_ERR,
+            Event::FrameError => INT_FRM_ERR,
+            Event::Rs485Collision => INT_RS485_CLASH,
+            Event::BreakSent => INT_TX_BRK_DONE,
+            Event::PatternDetected => INT_AT_CMD_CHAR_DET,
+        }
+    }
+}
+
+pub mod config {
+    use crate::units::*;
+
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub enum DataBits {
+        DataBits5,
+        DataBits6,
+        DataBits7,
+        DataBits8,
+    }
+
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub enum Parity {
+        ParityNone,
+        ParityEven,
+        ParityOdd,
+    }
+
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub enum StopBits {
+        /// 1 stop bit
+        STOP1,
+        /// 1.5 stop bits
+        STOP1P5,
+        /// 2 stop bits
+        STOP2,
+    }
+
+    /// Hardware flow control
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub enum FlowControl {
+        /// No flow control
+        None,
+        /// RTS is deasserted when the receive FIFO contains more than `rx_threshold` bytes
+        Rts { rx_threshold: u8 },
+        /// Transmission is paused while CTS is deasserted
+        Cts,
+        /// Both RTS and CTS flow control
+        RtsCts { rx_threshold: u8 },
+    }
+
+    /// Operating mode
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub enum Mode {
+        /// Standard full duplex UART
+        Uart,
+        /// RS-485 half duplex, the RTS pin drives the driver enable of the transceiver
+        ///
+        /// With collision detection the receiver is enabled during transmission, so the
+        /// transmitted data is also received. A difference between the transmitted and received
+        /// data triggers [Rs485Collision](super::Event::Rs485Collision).
+        Rs485 { collision_detection: bool },
+    }
+
+    /// Pattern detection configuration
+    ///
+    /// A pattern consists of `count` times `character`, with at most `gap_timeout` between the
+    /// characters, preceded by an idle period of at least `pre_idle` and followed by an idle
+    /// period of at least `post_idle`. All times are in bit times (baudrate cycles).
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub struct Pattern {
+        pub character: u8,
+        pub count: u8,
+        pub gap_timeout: u32,
+        pub pre_idle: u32,
+        pub post_idle: u32,
+    }
+
+    impl Pattern {
+        /// Pattern of `count` times `character` with default timing
+        pub fn new(character: u8, count: u8) -> Self {
+            Pattern {
+                character,
+                count,
+                ..Default::default()
+            }
+        }
+
+        pub fn gap_timeout(mut self, gap_timeout: u32) -> Self {
+            self.gap_timeout = gap_timeout;
+            self
+        }
+
+        pub fn pre_idle(mut self, pre_idle: u32) -> Self {
+            self.pre_idle = pre_idle;
+            self
+        }
+
+        pub fn post_idle(mut self, post_idle: u32) -> Self {
+            self.post_idle = post_idle;
+            self
+        }
+    }
+
+    impl Default for Pattern {
+        /// `+++` escape sequence, with a maximum gap of one character (10 bit times) and no
+        /// required idle periods
+        fn default() -> Pattern {
+            Pattern {
+                character: b'+',
+                count: 3,
+                gap_timeout: 10,
+                pre_idle: 0,
+                post_idle: 0,
+            }
+        }
+    }
+
+    #[derive(Debug, Copy, Clone)]
+    pub struct Config {
+        pub baudrate: Hertz,
+        pub data_bits: DataBits,
+        pub parity: Parity,
+        pub stop_bits: StopBits,
+        pub flow_control: FlowControl,
+        pub mode: Mode,
+    }
+
+    impl Config {
+        pub fn baudrate(mut self, baudrate: Hertz) -> Self {
+            self.baudrate = baudrate;
+            self
+        }
+
+        pub fn parity_none(mut self) -> Self {
+            self.parity = Parity::ParityNone;
+            self
+        }
+
+        pub fn parity_even(mut self) -> Self {
+            self.parity = Parity::ParityEven;
+            self
+        }
+
+        pub fn parity_odd(mut self) -> Self {
+            self.parity = Parity::ParityOdd;
+            self
+        }
+
+        pub fn data_bits(mut self, data_bits: DataBits) -> Self {
+            self.data_bits = data_bits;
+            self
+        }
+
+        pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+            self.stop_bits = stop_bits;
+            self
+        }
+
+        pub fn flow_control(mut self, flow_control: FlowControl) -> Self {
+            self.flow_control = flow_control;
+            self
+        }
+
+        pub fn mode(mut self, mode: Mode) -> Self {
+            self.mode = mode;
+            self
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Config {
+            Config {
+                baudrate: Hertz(19_200),
+                data_bits: DataBits::DataBits8,
+                parity: Parity::ParityNone,
+                stop_bits: StopBits::STOP1,
+                flow_control: FlowControl::None,
+                mode: Mode::Uart,
+            }
+        }
+    }
+}
+
+pub trait Pins<UART> {
+    /// Connect the pins to the peripheral signals
+    #[doc(hidden)]
+    fn connect(&mut self) {}
+}
+pub trait PinTx<UART> {
+    #[doc(hidden)]
+    fn connect_tx(&mut self) {}
+}
+pub trait PinRx<UART> {
+    #[doc(hidden)]
+    fn connect_rx(&mut self) {}
+}
+pub trait PinRts<UART> {
+    #[doc(hidden)]
+    fn connect_rts(&mut self) {}
+}
+pub trait PinCts<UART> {
+    #[doc(hidden)]
+    fn connect_cts(&mut self) {}
+}
+
+impl<UART, TX, RX> Pins<UART> for (TX, RX)
+where
+    TX: PinTx<UART>,
+    RX: PinRx<UART>,
+{
+    fn connect(&mut self) {
+        self.0.connect_tx();
+        self.1.connect_rx();
+    }
+}
+
+impl<UART, TX, RX, RTS, CTS> Pins<UART> for (TX, RX, RTS, CTS)
+where
+    TX: PinTx<UART>,
+    RX: PinRx<UART>,
+    RTS: PinRts<UART>,
+    CTS: PinCts<UART>,
+{
+    fn connect(&mut self) {
+        self.0.connect_tx();
+        self.1.connect_rx();
+        self.2.connect_rts();
+        self.3.connect_cts();
+    }
+}
+
+/// A filler type for when the Tx pin is unnecessary
+pub struct NoTx;
+/// A filler type for when the Rx pin is unnecessary
+pub struct NoRx;
+/// A filler type for when the Rts pin is unnecessary
+pub struct NoRts;
+/// A filler type for when the Cts pin is unnecessary
+pub struct NoCts;
+
+impl<UART> PinTx<UART> for NoTx {}
+impl<UART> PinRx<UART> for NoRx {}
+impl<UART> PinRts<UART> for NoRts {}
+impl<UART> PinCts<UART> for NoCts {}
+
+/// GPIO matrix signals of the UART pins
+#[doc(hidden)]
+pub trait UartSignals {
+    const TX_SIGNAL: OutputSignal;
+    const RX_SIGNAL: InputSignal;
+    const RTS_SIGNAL: OutputSignal;
+    const CTS_SIGNAL: InputSignal;
+}
+
+impl UartSignals for UART0 {
+    const TX_SIGNAL: OutputSignal = OutputSignal::U0TXD;
+    const RX_SIGNAL: InputSignal = InputSignal::U0RXD;
+    const RTS_SIGNAL: OutputSignal = OutputSignal::U0RTS;
+    const CTS_SIGNAL: InputSignal = InputSignal::U0CTS;
+}
+
+impl UartSignals for UART1 {
+    const TX_SIGNAL: OutputSignal = OutputSignal::U1TXD;
+    const RX_SIGNAL: InputSignal = InputSignal::U1RXD;
+    const RTS_SIGNAL: OutputSignal = OutputSignal::U1RTS;
+    const CTS_SIGNAL: InputSignal = InputSignal::U1CTS;
+}
+
+impl UartSignals for UART2 {
+    const TX_SIGNAL: OutputSignal = OutputSignal::U2TXD;
+    const RX_SIGNAL: InputSignal = InputSignal::U2RXD;
+    const RTS_SIGNAL: OutputSignal = OutputSignal::U2RTS;
+    const CTS_SIGNAL: InputSignal = InputSignal::U2CTS;
+}
+
+impl<UART: UartSignals, PIN: MatrixOutputPin> PinTx<UART> for PIN {
+    fn connect_tx(&mut self) {
+        self.connect_peripheral_to_output(UART::TX_SIGNAL);
+    }
+}
+
+impl<UART: UartSignals, PIN: MatrixInputPin> PinRx<UART> for PIN {
+    fn connect_rx(&mut self) {
+        self.connect_input_to_peripheral(UART::RX_SIGNAL);
+    }
+}
+
+impl<UART: UartSignals, PIN: MatrixOutputPin> PinRts<UART> for PIN {
+    fn connect_rts(&mut self) {
+        self.connect_peripheral_to_output(UART::RTS_SIGNAL);
+    }
+}
+
+impl<UART: UartSignals, PIN: MatrixInputPin> PinCts<UART> for PIN {
+    fn connect_cts(&mut self) {
+        self.connect_input_to_peripheral(UART::CTS_SIGNAL);
+    }
+}
+
+/// Serial abstraction
+///
+pub struct Serial<UART, PINS> {
+    uart: UART,
+    pins: PINS,
+    clock_control: crate::clock_control::ClockControlConfig,
+    apb_lock: Option<crate::clock_control::dfs::LockAPB>,
+}
+
+/// Serial receiver
+pub struct Rx<UART> {
+    _uart: PhantomData<UART>,
+    _apb_lock: Option<crate::clock_control::dfs::LockAPB>,
+}
+
+/// Serial transmitter
+pub struct Tx<UART> {
+    _uart: PhantomData<UART>,
+    _apb_lock: Option<crate::clock_control::dfs::LockAPB>,
+}
+
+macro_rules! halUart {
+    ($(
+        $UARTX:ident: ($uartX:ident),
+    )+) => {
+        $(
+            impl<'a, PINS> Serial<$UARTX, PINS> {
+                pub fn $uartX(
+                    uart: $UARTX,
+                    mut pins: PINS,
+                    config: config::Config,
+                    clock_control:  crate::clock_control::ClockControlConfig,
+                    dport: &mut esp32::DPORT
+                ) -> Result<Self, Error>
+                where
+                    PINS: Pins<$UARTX>,
+                {
+                        pins.connect();
+
+                        let mut serial=Serial { uart, pins, clock_control, apb_lock:None };
+                        serial
+                            .reset(dport)
+                            .enable(dport)
+                            .change_stop_bits(config.stop_bits)
+                            .change_data_bits(config.data_bits)
+                            .change_parity(config.parity)
+                            .change_baudrate(config.baudrate)?
+                            .change_flow_control(config.flow_control)?
+                            .change_mode(config.mode)?;
+                        Ok(serial)
+                }
+
+                fn reset(&mut self, dport:&mut esp32::DPORT) -> &mut Self {
+                    dport.perip_rst_en.modify(|_,w| w.$uartX().set_bit());
+                    dport.perip_rst_en.modify(|_,w| w.$uartX().clear_bit());
+                    self
+                }
+
+                pub fn enable(&mut self, dport:&mut esp32::DPORT) -> &mut Self {
+                    dport.perip_clk_en.modify(|_,w| w.uart_mem().set_bit());
+                    dport.perip_clk_en.modify(|_,w| w.$uartX().set_bit());
+                    dport.perip_rst_en.modify(|_,w| w.$uartX().clear_bit());
+                    self
+                }
+
+                pub fn disable(&mut self, dport:&mut esp32::DPORT) -> &mut Self {
+                    dport.perip_clk_en.modify(|_,w| w.$uartX().clear_bit());
+                    dport.perip_rst_en.modify(|_,w| w.$uartX().set_bit());
+
+                    if     dport.perip_clk_en.read().uart0().bit_is_clear()
+                        && dport.perip_clk_en.read().uart1().bit_is_clear()
+                        && dport.perip_clk_en.read().uart2().bit_is_clear()
+                    {
+                        dport.perip_clk_en.modify(|_,w| w.uart_mem().clear_bit());
+                    }
+                    self
+                }
+
+
+                pub fn change_stop_bits(&mut self, stop_bits: config::StopBits) -> &mut Self {
+
+                    //workaround for hardware issue, when UART stop bit set as 2-bit mode.
+                    self.uart.rs485_conf.modify(|_,w|
+                        w.dl1_en().bit(stop_bits==config::StopBits::STOP2)
+                    );
+
+                    self.uart.conf0.modify(|_,w|
+                        match stop_bits {
+                            config::StopBits::STOP1 => w.stop_bit_num().stop_bits_1(),
+                            config::StopBits::STOP1P5 => w.stop_bit_num().stop_bits_1p5(),
+                            //workaround for hardware issue, when UART stop bit set as 2-bit mode.
+                            config::StopBits::STOP2 => w.stop_bit_num().stop_bits_1(),
+                        }
+                    );
+
+                    self
+                }
+
+                pub fn change_data_bits(&mut self, data_bits: config::DataBits) -> &mut Self {
+
+                    self.uart.conf0.modify(|_,w|
+                        match data_bits {
+                            config::DataBits::DataBits5 => w.bit_num().data_bits_5(),
+                            config::DataBits::DataBits6 => w.bit_num().data_bits_6(),
+                            config::DataBits::DataBits7 => w.bit_num().data_bits_7(),
+                            config::DataBits::DataBits8 => w.bit_num().data_bits_8(),
+                        }
+                    );
+
+                    self
+                }
+
+                pub fn change_parity(&mut self, parity: config::Parity) -> &mut Self {
+
+                    self.uart.conf0.modify(|_,w|
+                        match parity {
+                            config::Parity::ParityNone => w.parity_en().clear_bit(),
+                            config::Parity::ParityEven => w.parity_en().set_bit().parity().clear_bit(),
+                            config::Parity::ParityOdd => w.parity_en().set_bit().parity().set_bit(),
+                        }
+                    );
+
+                    self
+                }
+
+
+                /// Change the hardware flow control
+                ///
+                /// The RTS and CTS pins need to be passed to the constructor.
+                pub fn change_flow_control(
+                    &mut self,
+                    flow_control: config::FlowControl,
+                ) -> Result<&mut Self, Error> {
+                    let (rts, cts, threshold) = match flow_control {
+                        config::FlowControl::None => (false, false, 0),
+                        config::FlowControl::Rts { rx_threshold } => (true, false, rx_threshold),
+                        config::FlowControl::Cts => (false, true, 0),
+                        config::FlowControl::RtsCts { rx_threshold } => (true, true, rx_threshold),
+                    };
+
+                    if rts && self.uart.rs485_conf.read().bits() & RS485_CONF_EN != 0 {
+                        return Err(Error::InvalidConfig);
+                    }
+
+                    let threshold = (threshold.max(1).min(UART_FIFO_SIZE - 1)) as u32;
+                    self.uart.conf1.modify(|r, w| unsafe {
+                        let bits = r.bits() & !(CONF1_RX_FLOW_EN | CONF1_RX_FLOW_THRHD_MASK);
+                        if rts {
+                            w.bits(bits | CONF1_RX_FLOW_EN | threshold << CONF1_RX_FLOW_THRHD_SHIFT)
+                        } else {
+                            w.bits(bits)
+                        }
+                    });
+                    self.uart.conf0.modify(|r, w| unsafe {
+                        if cts {
+                            w.bits(r.bits() | CONF0_TX_FLOW_EN)
+                        } else {
+                            w.bits(r.bits() & !CONF0_TX_FLOW_EN)
+                        }
+                    });
+
+                    Ok(self)
+                }
+
+                /// Change between standard UART and RS-485 half duplex mode
+                ///
+                /// In RS-485 mode the RTS pin passed to the constructor drives the driver enable
+                /// of the transceiver. RTS flow control cannot be used in this mode.
+                pub fn change_mode(&mut self, mode: config::Mode) -> Result<&mut Self, Error> {
+                    match mode {
+                        config::Mode::Uart => {
+                            self.uart.rs485_conf.modify(|r, w| unsafe {
+                                w.bits(
+                                    r.bits()
+                                        & !(RS485_CONF_EN
+                                            | RS485_CONF_TX_RX_EN
+                                            | RS485_CONF_RX_BUSY_TX_EN),
+                                )
+                            });
+                            self.uart.int_ena.modify(|r, w| unsafe {
+                                w.bits(r.bits() & !(INT_TX_DONE | INT_RS485_CLASH))
+                            });
+                        }
+                        config::Mode::Rs485 { collision_detection } => {
+                            if self.uart.conf1.read().bits() & CONF1_RX_FLOW_EN != 0 {
+                                return Err(Error::InvalidConfig);
+                            }
+
+                            // driver disabled while not transmitting
+                            self.uart
+                                .conf0
+                                .modify(|r, w| unsafe { w.bits(r.bits() | CONF0_SW_RTS) });
+
+                            let tx_rx = if collision_detection { RS485_CONF_TX_RX_EN } else { 0 };
+                            self.uart.rs485_conf.modify(|r, w| unsafe {
+                                w.bits(
+                                    r.bits() & !RS485_CONF_TX_RX_EN
+                                        | RS485_CONF_EN
+                                        | RS485_CONF_RX_BUSY_TX_EN
+                                        | tx_rx,
+                                )
+                            });
+
+                            // deassert the driver enable from the interrupt handler
+                            let clash = if collision_detection { INT_RS485_CLASH } else { 0 };
+                            self.uart.int_ena.modify(|r, w| unsafe {
+                                w.bits(r.bits() & !INT_RS485_CLASH | INT_TX_DONE | clash)
+                            });
+                        }
+                    }
+
+                    Ok(self)
+                }
+
+
+                /// Change the baudrate.
+                ///
+                /// Will automatically select the clock source. WHen possible the reference clock (1MHz) will be used,
+                /// because this is constant when the clock source/frequency changes.
+                /// However if one of the clock frequencies is below 10MHz
+                /// or if the baudrate is above the reference clock or if the baudrate cannot be set within 1.5%
+                /// then use the APB clock.
+                pub fn change_baudrate <T: Into<Hertz> + Copy>(&mut self, baudrate: T) -> Result<&mut Self,Error> {
+                    let mut use_apb_frequency = false;
+
+                    // if APB frequency is <10MHz the ref clock is no longer accurate
+                    // or if the baudrate > Ref frequency then use the APB frequency
+                    if !self.clock_control.is_ref_clock_stable()
+                        || baudrate.into() > self.clock_control.ref_frequency()
+                    {
+                        use_apb_frequency = true;
+                    } else if baudrate.into() < self.clock_control.apb_frequency_apb_locked()/(1<<20-1) {
+                        // if baudrate is lower then can be achieved via the APB frequency
+                        use_apb_frequency = false;
+                    }
+                    else {
+                        let clk_div =
+                            (self.clock_control.ref_frequency() * 16 + baudrate.into() / 2) / baudrate.into();
+                        // if baudrate too high use APB clock
+                        if clk_div == 0 {
+                            use_apb_frequency = true
+                        } else {
+                            // if baudrate cannot be reached within 1.5% use APB frequency
+                            // use 203 as multiplier (2*101.5), because 1Mhz * 16 * 203 still fits in 2^32
+                            let calc_baudrate = (self.clock_control.ref_frequency() * 16 * 200) / clk_div;
+                            if calc_baudrate > baudrate.into() * 203
+                                || calc_baudrate < baudrate.into() * 197
+                            {
+                                use_apb_frequency = true;
+                            }
+                        }
+                    }
+
+                    self.change_baudrate_force_clock(baudrate, use_apb_frequency)
+                }
+
+                /// Change the baudrate choosing the reference or APB clock manually
+                pub fn change_baudrate_force_clock <T: Into<Hertz> + Copy>(&mut self, baudrate: T, use_apb_frequency: bool) -> Result<&mut Self,Error> {
+
+                    if let None=self.apb_lock  {
+                        if use_apb_frequency {
+                            self.apb_lock=Some(self.clock_control.lock_apb_frequency());
+                        }
+                    } else {
+                        if (!use_apb_frequency) {
+                            self.apb_lock=None;
+                        }
+                    }
+
+                    // set clock source
+                    self.uart.conf0.modify(|_, w| w.tick_ref_always_on().bit(use_apb_frequency));
+
+                    let sclk_freq = if use_apb_frequency {self.clock_control.apb_frequency_apb_locked()} else {self.clock_control.ref_frequency()};
+
+                    // calculate nearest divider
+                    let clk_div = (sclk_freq * 16 + baudrate.into()/2 ) / baudrate.into();
+
+                    if clk_div == 0 {
+                        return Err(Error::BaudrateTooHigh)
+                    }
+                    if clk_div > (1<<24)-1 {
+                        return Err(Error::BaudrateTooLow)
+                    }
+
+                    unsafe {
+                        self.uart.clkdiv.modify(|_, w| {
+                            w.clkdiv()
+                                .bits(clk_div >> 4)
+                                .clkdiv_frag()
+                                .bits((clk_div & 0xf) as u8)
+                        })
+                    };
+
+                    Ok(self)
+                }
+
+                pub fn is_clock_apb(& self) -> bool {
+                    self.uart.conf0.read().tick_ref_always_on().bit_is_set()
+                }
+
+                pub fn baudrate(& self) -> Hertz {
+                    let use_apb_frequency = self.uart.conf0.read().tick_ref_always_on().bit_is_set();
+                    let sclk_freq = if use_apb_frequency {self.clock_control.apb_frequency()} else {self.clock_control.ref_frequency()};
+                    let div = self.uart.clkdiv.read().clkdiv().bits()<<4 | (self.uart.clkdiv.read().clkdiv_frag().bits() as u32);
+
+                    // round to nearest integer baudrate
+                    (sclk_freq * 16 + Hertz(div / 2)) / div
+                }
+
+                /// Starts listening for an interrupt event
+                ///
+                /// The interrupt is handled by
+                /// [BufferedSerial::handle_interrupt],
+                /// which passes the events to the callback set via
+                /// [set_callback](Serial::set_callback).
+                pub fn listen(&mut self, event: Event) {
+                    self.uart.int_ena.modify(|r, w| unsafe { w.bits(r.bits() | event.bits()) });
+                }
+
+                /// Stop listening for an interrupt event
+                pub fn unlisten(&mut self, event: Event) {
+                    self.uart.int_ena.modify(|r, w| unsafe { w.bits(r.bits() & !event.bits()) });
+                }
+
+                /// Return true if the event has occurred (independent of listening to it)
+                pub fn is_interrupt_set(&self, event: Event) -> bool {
+                    self.uart.int_raw.read().bits() & event.bits() != 0
+                }
+
+                /// Clear the interrupt of an event
+                pub fn clear_interrupt(&mut self, event: Event) {
+                    self.uart.int_clr.write(|w| unsafe { w.bits(event.bits()) });
+                }
+
+                /// Set the number of bytes in the receive FIFO above which
+                /// [RxFifoFull](Event::RxFifoFull) is triggered (1-127)
+                pub fn change_rx_fifo_full_threshold(&mut self, threshold: u8) -> &mut Self {
+                    let threshold = (threshold.max(1).min(UART_FIFO_SIZE - 1)) as u32;
+                    self.uart.conf1.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !CONF1_RXFIFO_FULL_THRHD_MASK | threshold)
+                    });
+                    self
+                }
+
+                /// Set the number of bytes in the transmit FIFO below which
+                /// [TxFifoEmpty](Event::TxFifoEmpty) is triggered (0-127)
+                pub fn change_tx_fifo_empty_threshold(&mut self, threshold: u8) -> &mut Self {
+                    let threshold = (threshold.min(UART_FIFO_SIZE - 1)) as u32;
+                    self.uart.conf1.modify(|r, w| unsafe {
+                        w.bits(
+                            r.bits() & !CONF1_TXFIFO_EMPTY_THRHD_MASK
+                                | threshold << CONF1_TXFIFO_EMPTY_THRHD_SHIFT,
+                        )
+                    });
+                    self
+                }
+
+                /// Set the receive timeout in units of the time to receive one byte (1-127)
+                ///
+                /// After no data has been received for this time [RxTimeout](Event::RxTimeout) is
+                /// triggered. None disables the timeout.
+                pub fn change_rx_timeout(&mut self, timeout: Option<u8>) -> &mut Self {
+                    let bits = match timeout {
+                        Some(timeout) => {
+                            CONF1_RX_TOUT_EN
+                                | ((timeout.max(1).min(127)) as u32) << CONF1_RX_TOUT_THRHD_SHIFT
+                        }
+                        None => 0,
+                    };
+                    self.uart.conf1.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !(CONF1_RX_TOUT_EN | CONF1_RX_TOUT_THRHD_MASK) | bits)
+                    });
+                    self
+                }
+
+                /// Set the number of bit times the line needs to be high before the receiver
+                /// considers it idle (0-1023)
+                pub fn change_rx_idle_threshold(&mut self, bits: u16) -> &mut Self {
+                    let bits = (bits as u32).min(IDLE_CONF_RX_IDLE_THRHD_MASK);
+                    self.uart.idle_conf.modify(|r, w| unsafe {
+                        w.bits(r.bits() & !IDLE_CONF_RX_IDLE_THRHD_MASK | bits)
+                    });
+                    self
+                }
+
+                /// Set the number of idle bit times inserted between transmissions (0-1023)
+                pub fn change_tx_idle(&mut self, bits: u16) -> &mut Self {
+                    let bits = (bits as u32).min(IDLE_CONF_TX_IDLE_NUM_MAX);
+                    self.uart.idle_conf.modify(|r, w| unsafe {
+                        w.bits(
+                            r.bits() & !IDLE_CONF_TX_IDLE_NUM_MASK
+                                | bits << IDLE_CONF_TX_IDLE_NUM_SHIFT,
+                        )
+                    });
+                    self
+                }
+
+                /// Send a break of `length` bit times (1-255)
+                ///
+                /// The break is sent after the data in the transmit FIFO, this function blocks
+                /// until the break has been sent.
+                pub fn send_break(&mut self, length: u8) {
+                    let length = (length.max(1)) as u32;
+                    self.uart.idle_conf.modify(|r, w| unsafe {
+                        w.bits(
+                            r.bits() & !IDLE_CONF_TX_BRK_NUM_MASK
+                                | length << IDLE_CONF_TX_BRK_NUM_SHIFT,
+                        )
+                    });
+                    self.uart.int_clr.write(|w| unsafe { w.bits(INT_TX_BRK_DONE) });
+
+                    Tx::<$UARTX>::set_driver_enable(true);
+                    self.uart.conf0.modify(|r, w| unsafe { w.bits(r.bits() | CONF0_TXD_BRK) });
+
+                    // the interrupt handler ends the break when listening to BreakSent
+                    while self.uart.int_raw.read().bits() & INT_TX_BRK_DONE == 0
+                        && self.uart.conf0.read().bits() & CONF0_TXD_BRK != 0
+                    {}
+
+                    Self::end_break();
+
+                    // release the bus in RS-485 mode once the break has left the transmitter
+                    while !self.is_tx_idle() {}
+                    Tx::<$UARTX>::set_driver_enable(false);
+                }
+
+                /// Stop sending break
+                fn end_break() {
+                    let uart = unsafe { &*$UARTX::ptr() };
+                    uart.conf0.modify(|r, w| unsafe { w.bits(r.bits() & !CONF0_TXD_BRK) });
+                }
+
+                /// Enable the detection of a repeated pattern character
+                ///
+                /// Starts listening to [PatternDetected](Event::PatternDetected). In buffered
+                /// mode the positions of the detected patterns are queued, see
+                /// [BufferedSerial::pattern_position].
+                pub fn enable_pattern_detection(&mut self, pattern: config::Pattern) -> &mut Self {
+                    self.uart.at_cmd_precnt.write(|w| unsafe {
+                        w.bits(pattern.pre_idle.min(AT_CMD_COUNT_MASK))
+                    });
+                    self.uart.at_cmd_postcnt.write(|w| unsafe {
+                        w.bits(pattern.post_idle.min(AT_CMD_COUNT_MASK))
+                    });
+                    self.uart.at_cmd_gaptout.write(|w| unsafe {
+                        w.bits(pattern.gap_timeout.max(1).min(AT_CMD_COUNT_MASK))
+                    });
+                    self.uart.at_cmd_char.write(|w| unsafe {
+                        w.bits(
+                            pattern.character as u32
+                                | (pattern.count.max(1) as u32) << AT_CMD_CHAR_NUM_SHIFT,
+                        )
+                    });
+
+                    self.clear_interrupt(Event::PatternDetected);
+                    self.listen(Event::PatternDetected);
+                    self
+                }
+
+                /// Disable the detection of the pattern character
+                pub fn disable_pattern_detection(&mut self) -> &mut Self {
+                    self.unlisten(Event::PatternDetected);
+                    self.clear_interrupt(Event::PatternDetected);
+                    self
+                }
+
+                /// Return true if the receiver is idle
+                pub fn is_rx_idle(& self) -> bool {
+                    self.uart.status.read().st_urx_out().is_rx_idle()
+                }
+
+                /// Return true if the transmitter is idle
+                pub fn is_tx_idle(& self) -> bool {
+                    self.uart.status.read().st_utx_out().is_tx_idle()
+                }
+
+                pub fn split(self) -> (Tx<$UARTX>, Rx<$UARTX>) {
+                    (
+
+                        Tx {
+                            _uart: PhantomData,
+                            _apb_lock: if let None=self.apb_lock { None } else { Some(self.clock_control.lock_apb_frequency()) }
+                        },
+                        Rx {
+                            _uart: PhantomData,
+                            _apb_lock: if let None=self.apb_lock { None } else { Some(self.clock_control.lock_apb_frequency()) }
+                        },
+                    )
+                }
+
+                pub fn release(self) -> ($UARTX, PINS) {
+                    (self.uart, self.pins)
+                }
+
+            }
+
+            impl<'a, PINS> serial::Read<u8> for Serial<$UARTX, PINS> {
+                type Error = Infallible;
+
+                fn read(&mut self) -> nb::Result<u8, Self::Error> {
+                    let mut rx: Rx<$UARTX> = Rx {
+                        _uart: PhantomData,
+                        _apb_lock: if let None=self.apb_lock { None } else { Some(self.clock_control.lock_apb_frequency()) }
+                    };
+                    rx.read()
+                }
+            }
+
+
+            impl  Rx<$UARTX> {
+                pub fn count(& self) -> u8 {
+                    unsafe {
+                            (*$UARTX::ptr()).status.read().rxfifo_cnt().bits()
+                        }
+                }
+
+                pub fn is_idle(& self) -> bool {
+                    unsafe {
+                            (*$UARTX::ptr()).status.read().st_urx_out().is_rx_idle()
+                        }
+                }
+            }
+
+            impl  Tx<$UARTX> {
+                pub fn count(& self) -> u8 {
+                    unsafe {
+                            (*$UARTX::ptr()).status.read().txfifo_cnt().bits()
+                        }
+                }
+
+                pub fn is_idle(& self) -> bool {
+                    unsafe {
+                            (*$UARTX::ptr()).status.read().st_utx_out().is_tx_idle()
+                        }
+                }
+            }
+
+            impl serial::Read<u8> for Rx<$UARTX> {
+                type Error = Infallible;
+
+                fn read(&mut self) -> nb::Result<u8, Self::Error> {
+
+                    if self.count()==0 {
+                        Err(nb::Error::WouldBlock)
+                    } else {
+                        Ok(unsafe { (*$UARTX::ptr()).rx_fifo.read().bits() })
+                    }
+                }
+            }
+
+            impl<'a, PINS> serial::Write<u8> for Serial<$UARTX, PINS> {
+                type Error = Infallible;
+
+                fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                    let mut tx: Tx<$UARTX> = Tx {
+                        _uart: PhantomData,
+                        _apb_lock: if let None=self.apb_lock { None } else { Some(self.clock_control.lock_apb_frequency()) }
+                    };
+                    tx.flush()
+                }
+
+                fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+                    let mut tx: Tx<$UARTX> = Tx {
+                        _uart: PhantomData,
+                        _apb_lock: if let None=self.apb_lock { None } else { Some(self.clock_control.lock_apb_frequency()) }
+                    };
+                    tx.write(byte)
+                }
+            }
+
+            impl Tx<$UARTX> {
+                /// In RS-485 mode assert or deassert the driver enable (RTS)
+                fn set_driver_enable(enable: bool) {
+                    let uart = unsafe { &*$UARTX::ptr() };
+                    if uart.rs485_conf.read().bits() & RS485_CONF_EN != 0 {
+                        uart.conf0.modify(|r, w| unsafe {
+                            if enable {
+                                w.bits(r.bits() & !CONF0_SW_RTS)
+                            } else {
+                                w.bits(r.bits() | CONF0_SW_RTS)
+                            }
+                        });
+                    }
+                }
+            }
+
+            impl serial::Write<u8> for Tx<$UARTX> {
+                type Error = Infallible;
+
+                fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                    if self.is_idle() && self.count() == 0 {
+                        Self::set_driver_enable(false);
+                        Ok(())
+                    }
+                    else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+                    if self.count()<UART_FIFO_SIZE {
+                        Self::set_driver_enable(true);
+                        unsafe { (*$UARTX::ptr()).tx_fifo.write_with_zero(|w| { w.bits(byte)}) }
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl<PINS> core::fmt::Write for Serial<$UARTX, PINS>
+            {
+                fn write_str(&mut self, s: &str) -> core::fmt::Result {
+                    use embedded_hal::serial::Write;
+                    s.as_bytes()
+                        .iter()
+                        .try_for_each(|c| nb::block!(self.write(*c)))
+                        .map_err(|_| core::fmt::Error)
+                }
+            }
+
+
+        )+
+    }
+}
+
+impl<UART> core::fmt::Write for Tx<UART>
+where
+    Tx<UART>: embedded_hal::serial::Write<u8>,
+{
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        use embedded_hal::serial::Write;
+        s.as_bytes()
+            .iter()
+            .try_for_each(|c| nb::block!(self.write(*c)))
+            .map_err(|_| core::fmt::Error)
+    }
+}
+
+halUart! {
+    UART0: (uart0),
+    UART1: (uart1),
+    UART2: (uart2),
+}
@@ -0,0 +1,407 @@
+//! Deep and light sleep
+//!
+//! In deep sleep the CPUs, most of the RAM and all digital peripherals are powered down.
+//! Only the RTC domain stays (partially) powered. Waking up resets the chip, after which
+//! [wakeup_cause] and [reset_reason](crate::reset::reset_reason) tell why the application was
+//! started.
+//!
+//! In light sleep the CPUs are clock gated and the state of the chip is retained. Execution
+//! continues after [light_sleep] returns. Light sleep is entered automatically from
+//! [ClockControlConfig::idle] when enabled and no [LockAwake](crate::clock_control::dfs::LockAwake)
+//! is held.
+//!
+//! The following wakeup sources are supported:
+//! - RTC timer: configured via [Config::timer_wakeup](config::Config::timer_wakeup) or
+//!     [Config::alarm_wakeup](config::Config::alarm_wakeup)
+//! - EXT0/EXT1: configured via [enable_ext0_wakeup](crate::gpio::enable_ext0_wakeup) and
+//!     [enable_ext1_wakeup](crate::gpio::enable_ext1_wakeup) on pins in RTC input mode
+//! - Touch pad: configured via [Config::touchpad_wakeup](config::Config::touchpad_wakeup),
+//!     the touch pad state machine needs to be set up and started by the application
+//! - ULP coprocessor: configured via [Config::ulp_wakeup](config::Config::ulp_wakeup),
+//!     the ULP program needs to be loaded and started by the application
+//! - GPIO (light sleep only): configured via [Config::gpio_wakeup](config::Config::gpio_wakeup)
+//!     and the `enable_wakeup` method on the input pins
+//!
+//! State can be kept across deep sleep in statics in the RTC memories. The `zeroed` RTC statics
+//! are not cleared when waking up from deep sleep.
+//!
+//! # Example
+//! ```
+//! #[ram(rtc_slow, zeroed)]
+//! static mut BOOT_COUNT: u32 = 0;
+//!
+//! if sleep::wakeup_cause() == WakeupCause::Undefined {
+//!     // first boot
+//! }
+//! unsafe { BOOT_COUNT += 1 };
+//!
+//! sleep::deep_sleep(
+//!     sleep::config::Config::default().timer_wakeup(10.s()),
+//!     clock_control_config,
+//! );
+//! ```
+
+use crate::clock_control::ClockControlConfig;
+use crate::esp32::RTCCNTL;
+use crate::reset::decode_wakeup_cause;
+use crate::units::*;
+
+pub use crate::reset::{wakeup_cause, WakeupCause};
+
+// Bits of the RTC_CNTL_OPTIONS0_REG register
+const OPTIONS0_XTL_FORCE_PU: u32 = 1 << 13;
+const OPTIONS0_BIAS_FORCE_NOSLEEP: u32 = 1 << 16;
+
+// Bits of the RTC_CNTL_STATE0_REG register
+const STATE0_SLEEP_EN: u32 = 1 << 31;
+
+// Bits of the RTC_CNTL_SLP_TIMER1_REG register
+const SLP_TIMER1_MAIN_TIMER_ALARM_EN: u32 = 1 << 16;
+
+// Bits of the RTC_CNTL_INT_RAW_REG and RTC_CNTL_INT_CLR_REG registers
+const INT_SLP_WAKEUP: u32 = 1 << 0;
+const INT_SLP_REJECT: u32 = 1 << 1;
+
+// Bits of the RTC_CNTL_ANA_CONF_REG register
+const ANA_CONF_TXRF_I2C_PU: u32 = 1 << 27;
+const ANA_CONF_RFRX_PBUS_PU: u32 = 1 << 28;
+const ANA_CONF_CKGEN_I2C_PU: u32 = 1 << 30;
+const ANA_CONF_PLL_I2C_PU: u32 = 1 << 31;
+
+// Bits of the RTC_CNTL_PWC_REG register
+const PWC_FASTMEM_FORCE_NOISO: u32 = 1 << 0;
+const PWC_SLOWMEM_FORCE_NOISO: u32 = 1 << 2;
+const PWC_FASTMEM_FOLW_CPU: u32 = 1 << 6;
+const PWC_SLOWMEM_FOLW_CPU: u32 = 1 << 9;
+const PWC_FASTMEM_FORCE_PU: u32 = 1 << 13;
+const PWC_FASTMEM_PD_EN: u32 = 1 << 14;
+const PWC_SLOWMEM_FORCE_PU: u32 = 1 << 16;
+const PWC_SLOWMEM_PD_EN: u32 = 1 << 17;
+const PWC_PD_EN: u32 = 1 << 20;
+
+// Bits of the RTC_CNTL_DIG_PWC_REG register
+const DIG_PWC_DG_WRAP_FORCE_PD: u32 = 1 << 19;
+const DIG_PWC_DG_WRAP_FORCE_PU: u32 = 1 << 20;
+const DIG_PWC_DG_WRAP_PD_EN: u32 = 1 << 31;
+
+// Bits of the RTC_CNTL_DIG_ISO_REG register
+const DIG_ISO_DG_PAD_FORCE_NOISO: u32 = 1 << 12;
+const DIG_ISO_DG_PAD_FORCE_ISO: u32 = 1 << 13;
+
+// Bits of the RTC_CNTL_WAKEUP_STATE_REG register
+pub(crate) const WAKEUP_CAUSE_MASK: u32 = 0x7ff;
+pub(crate) const WAKEUP_ENA_SHIFT: u32 = 20;
+const WAKEUP_ENA_MASK: u32 = 0xfff << WAKEUP_ENA_SHIFT;
+
+// Wakeup trigger bits, used in both the cause and enable fields
+pub(crate) const WAKEUP_EXT0: u32 = 1 << 0;
+pub(crate) const WAKEUP_EXT1: u32 = 1 << 1;
+pub(crate) const WAKEUP_GPIO: u32 = 1 << 2;
+pub(crate) const WAKEUP_TIMER: u32 = 1 << 3;
+pub(crate) const WAKEUP_UART0: u32 = 1 << 6;
+pub(crate) const WAKEUP_UART1: u32 = 1 << 7;
+pub(crate) const WAKEUP_TOUCH: u32 = 1 << 8;
+pub(crate) const WAKEUP_ULP: u32 = 1 << 9;
+
+pub mod config {
+    use crate::units::*;
+
+    /// Power state of a domain during sleep
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub enum PowerMode {
+        /// Keep powered when required by the enabled wakeup sources or for retaining state
+        Auto,
+        /// Keep powered
+        On,
+        /// Power down
+        Off,
+    }
+
+    /// Sleep configuration
+    ///
+    /// In [PowerMode::Auto]:
+    /// - the RTC peripherals are powered in light sleep, in deep sleep only when EXT0, touch pad
+    ///     or ULP wakeup is enabled
+    /// - the RTC slow and fast memory are powered to retain the RTC statics
+    /// - the 40MHz Xtal is powered down
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub struct Config {
+        pub timer: Option<MilliSeconds>,
+        pub alarm: Option<u64>,
+        pub gpio: bool,
+        pub touchpad: bool,
+        pub ulp: bool,
+        pub rtc_peripherals: PowerMode,
+        pub rtc_slow_memory: PowerMode,
+        pub rtc_fast_memory: PowerMode,
+        pub xtal: PowerMode,
+    }
+
+    impl Config {
+        /// Wake up after the given time
+        pub fn timer_wakeup<T: Into<MilliSeconds>>(mut self, time: T) -> Self {
+            self.timer = Some(time.into());
+            self
+        }
+
+        /// Wake up when the RTC counter reaches the given value
+        ///
+        /// See [rtc::unix_time_to_counter](crate::rtc::unix_time_to_counter) to wake up at a
+        /// specific wall clock time. When combined with a timer wakeup, the earliest is used.
+        pub fn alarm_wakeup(mut self, counter: u64) -> Self {
+            self.alarm = Some(counter);
+            self
+        }
+
+        /// Wake up when a GPIO with wakeup enabled reaches its level (light sleep only)
+        pub fn gpio_wakeup(mut self) -> Self {
+            self.gpio = true;
+            self
+        }
+
+        /// Wake up on touch pad events
+        pub fn touchpad_wakeup(mut self) -> Self {
+            self.touchpad = true;
+            self
+        }
+
+        /// Wake up when triggered by the ULP coprocessor
+        pub fn ulp_wakeup(mut self) -> Self {
+            self.ulp = true;
+            self
+        }
+
+        /// Power mode of the RTC peripherals (RTC IO, touch pads, ULP coprocessor)
+        pub fn rtc_peripherals(mut self, mode: PowerMode) -> Self {
+            self.rtc_peripherals = mode;
+            self
+        }
+
+        /// Power mode of the RTC slow memory
+        pub fn rtc_slow_memory(mut self, mode: PowerMode) -> Self {
+            self.rtc_slow_memory = mode;
+            self
+        }
+
+        /// Power mode of the RTC fast memory
+        pub fn rtc_fast_memory(mut self, mode: PowerMode) -> Self {
+            self.rtc_fast_memory = mode;
+            self
+        }
+
+        /// Power mode of the 40MHz Xtal
+        pub fn xtal(mut self, mode: PowerMode) -> Self {
+            self.xtal = mode;
+            self
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Config {
+            Config {
+                timer: None,
+                alarm: None,
+                gpio: false,
+                touchpad: false,
+                ulp: false,
+                rtc_peripherals: PowerMode::Auto,
+                rtc_slow_memory: PowerMode::Auto,
+                rtc_fast_memory: PowerMode::Auto,
+                xtal: PowerMode::Auto,
+            }
+        }
+    }
+}
+
+use config::PowerMode;
+
+/// Set and clear bits of a register
+macro_rules! modify_bits {
+    ($reg:expr, $set:expr, $clear:expr $(,)?) => {
+        $reg.modify(|r, w| unsafe { w.bits(r.bits() & !($clear) | ($set)) })
+    };
+}
+
+/// Configure a RTC memory to stay powered or be powered down during deep sleep
+fn configure_memory(on: bool, pd_en: u32, force_pu: u32, force_noiso: u32) {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+    if on {
+        modify_bits!(rtccntl.pwc, force_pu | force_noiso, pd_en);
+    } else {
+        modify_bits!(rtccntl.pwc, pd_en, force_pu | force_noiso);
+    }
+}
+
+/// Set the timer alarm and return the enabled wakeup sources
+fn configure_wakeup(config: &config::Config, slow_rtc_frequency: Hertz) -> u32 {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    // EXT0 and EXT1 are enabled in the register by the gpio module
+    let mut wakeup = (rtccntl.wakeup_state.read().bits() & WAKEUP_ENA_MASK) >> WAKEUP_ENA_SHIFT
+        & (WAKEUP_EXT0 | WAKEUP_EXT1);
+
+    let timer = config.timer.map(|time| {
+        crate::rtc::counter()
+            + u32::from(time) as u64 * u32::from(slow_rtc_frequency) as u64 / 1_000
+    });
+    let alarm = match (timer, config.alarm) {
+        (Some(timer), Some(alarm)) => Some(core::cmp::min(timer, alarm)),
+        (timer, alarm) => timer.or(alarm),
+    };
+
+    if let Some(alarm) = alarm {
+        rtccntl
+            .slp_timer0
+            .write(|w| unsafe { w.bits(alarm as u32) });
+        rtccntl.slp_timer1.write(|w| unsafe {
+            w.bits((alarm >> 32) as u32 & 0xffff | SLP_TIMER1_MAIN_TIMER_ALARM_EN)
+        });
+
+        wakeup |= WAKEUP_TIMER;
+    }
+    if config.gpio {
+        wakeup |= WAKEUP_GPIO;
+    }
+    if config.touchpad {
+        wakeup |= WAKEUP_TOUCH;
+    }
+    if config.ulp {
+        wakeup |= WAKEUP_ULP;
+    }
+
+    wakeup
+}
+
+/// Configure which RTC domains stay powered during sleep
+fn configure_power_domains(config: &config::Config, wakeup: u32, deep_sleep: bool) {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    // in light sleep the RTC IO state is kept by default
+    let rtc_peripherals = match config.rtc_peripherals {
+        PowerMode::Auto => !deep_sleep || wakeup & (WAKEUP_EXT0 | WAKEUP_TOUCH | WAKEUP_ULP) != 0,
+        mode => mode == PowerMode::On,
+    };
+    if rtc_peripherals {
+        modify_bits!(rtccntl.pwc, 0, PWC_PD_EN);
+    } else {
+        modify_bits!(rtccntl.pwc, PWC_PD_EN, 0);
+    }
+
+    modify_bits!(rtccntl.pwc, 0, PWC_SLOWMEM_FOLW_CPU | PWC_FASTMEM_FOLW_CPU);
+    configure_memory(
+        config.rtc_slow_memory != PowerMode::Off,
+        PWC_SLOWMEM_PD_EN,
+        PWC_SLOWMEM_FORCE_PU,
+        PWC_SLOWMEM_FORCE_NOISO,
+    );
+    configure_memory(
+        config.rtc_fast_memory != PowerMode::Off,
+        PWC_FASTMEM_PD_EN,
+        PWC_FASTMEM_FORCE_PU,
+        PWC_FASTMEM_FORCE_NOISO,
+    );
+
+    if config.xtal == PowerMode::On {
+        modify_bits!(rtccntl.options0, OPTIONS0_XTL_FORCE_PU, 0);
+    } else {
+        modify_bits!(rtccntl.options0, 0, OPTIONS0_XTL_FORCE_PU);
+    }
+}
+
+/// Start the sleep state machine
+fn start_sleep(wakeup: u32) {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    modify_bits!(
+        rtccntl.wakeup_state,
+        wakeup << WAKEUP_ENA_SHIFT,
+        WAKEUP_ENA_MASK,
+    );
+    rtccntl.slp_reject_conf.write(|w| unsafe { w.bits(0) });
+
+    rtccntl
+        .int_clr
+        .write(|w| unsafe { w.bits(INT_SLP_WAKEUP | INT_SLP_REJECT) });
+    modify_bits!(rtccntl.state0, STATE0_SLEEP_EN, 0);
+}
+
+/// Enter deep sleep
+///
+/// The chip resets when woken up, so this function never returns. Without any wakeup source
+/// enabled, the chip only wakes up by a reset.
+pub fn deep_sleep(config: config::Config, clock_control_config: ClockControlConfig) -> ! {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    xtensa_lx6_rt::interrupt::free(|_| {
+        // GPIO wakeup is not available in deep sleep
+        let wakeup =
+            configure_wakeup(&config, clock_control_config.slow_rtc_frequency()) & !WAKEUP_GPIO;
+        configure_power_domains(&config, wakeup, true);
+
+        // power down the digital domain
+        modify_bits!(
+            rtccntl.dig_iso,
+            0,
+            DIG_ISO_DG_PAD_FORCE_ISO | DIG_ISO_DG_PAD_FORCE_NOISO,
+        );
+        modify_bits!(
+            rtccntl.dig_pwc,
+            DIG_PWC_DG_WRAP_PD_EN,
+            DIG_PWC_DG_WRAP_FORCE_PU | DIG_PWC_DG_WRAP_FORCE_PD,
+        );
+        modify_bits!(rtccntl.options0, 0, OPTIONS0_BIAS_FORCE_NOSLEEP);
+        modify_bits!(
+            rtccntl.ana_conf,
+            0,
+            ANA_CONF_CKGEN_I2C_PU
+                | ANA_CONF_PLL_I2C_PU
+                | ANA_CONF_RFRX_PBUS_PU
+                | ANA_CONF_TXRF_I2C_PU,
+        );
+
+        // no deep sleep wake stub
+        rtccntl.store6.write(|w| unsafe { w.bits(0) });
+
+        start_sleep(wakeup);
+    });
+
+    loop {}
+}
+
+/// Enter light sleep and return the source which woke up the chip
+///
+/// The CPU is clocked from the Xtal during sleep. On wakeup the CPU frequency is restored
+/// according to the held DFS locks and the DFS callbacks are called.
+///
+/// The state of the RAM, the peripherals and the GPIOs is preserved. Interrupts are handled
+/// after wakeup. Without any wakeup source enabled, the chip does not wake up.
+pub fn light_sleep(
+    config: config::Config,
+    clock_control_config: ClockControlConfig,
+) -> WakeupCause {
+    clock_control_config.light_sleep(config)
+}
+
+/// Enter light sleep, the caller has to switch the CPU to the Xtal and disable interrupts
+pub(crate) fn light_sleep_start(config: &config::Config, slow_rtc_frequency: Hertz) -> WakeupCause {
+    let rtccntl = unsafe { &*RTCCNTL::ptr() };
+
+    let wakeup = configure_wakeup(config, slow_rtc_frequency);
+    configure_power_domains(config, wakeup, false);
+
+    // keep the digital domain powered
+    modify_bits!(
+        rtccntl.dig_pwc,
+        0,
+        DIG_PWC_DG_WRAP_PD_EN | DIG_PWC_DG_WRAP_FORCE_PD,
+    );
+
+    start_sleep(wakeup);
+
+    while rtccntl.int_raw.read().bits() & (INT_SLP_WAKEUP | INT_SLP_REJECT) == 0 {}
+    rtccntl
+        .int_clr
+        .write(|w| unsafe { w.bits(INT_SLP_WAKEUP | INT_SLP_REJECT) });
+
+    decode_wakeup_cause(rtccntl.wakeup_state.read().bits())
+}
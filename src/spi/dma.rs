@@ -0,0 +1,264 @@
+//! SPI DMA transfers
+//!
+//! Transfers the data directly from and to a [DmaBuffer](crate::dma::DmaBuffer) without copying
+//! via the 64 byte data buffer of the peripheral.
+//!
+//! Transfers can be done in a blocking way, or started in the background after which the
+//! returned [SpiDmaTransfer] can be used to wait for completion and to get back the buffer.
+//!
+//! # Example
+//! ```
+//! let channels = SpiDmaChannels::take().unwrap();
+//! let mut spi = spi.with_dma(channels.channel1, &mut dport);
+//! let buffer = DmaBuffer::new(4096).unwrap();
+//!
+//! let transfer = spi.start_write(buffer).unwrap();
+//! // do other work
+//! let (spi, buffer) = transfer.wait();
+//! ```
+
+use crate::dma::{self, DmaBuffer, SpiDmaChannel};
+use crate::esp32::{SPI2, SPI3};
+
+/// SPI with DMA support
+pub struct SpiDma<SPI, PINS, CH> {
+    spi: super::Spi<SPI, PINS>,
+    channel: CH,
+}
+
+/// An SPI DMA transfer running in the background
+///
+/// When dropped, it waits for the transfer to finish before releasing the buffer.
+pub struct SpiDmaTransfer<SPI, PINS, CH>
+where
+    SpiDma<SPI, PINS, CH>: DmaTransfer,
+{
+    spi: Option<SpiDma<SPI, PINS, CH>>,
+    buffer: Option<DmaBuffer>,
+}
+
+/// Status of a DMA transfer
+#[doc(hidden)]
+pub trait DmaTransfer {
+    fn is_busy(&self) -> bool;
+}
+
+impl<SPI, PINS, CH> SpiDmaTransfer<SPI, PINS, CH>
+where
+    SpiDma<SPI, PINS, CH>: DmaTransfer,
+{
+    /// Return true if the transfer has finished
+    pub fn is_done(&self) -> bool {
+        !self.spi.as_ref().unwrap().is_busy()
+    }
+
+    /// Wait for the transfer to finish and return the SPI and the buffer
+    pub fn wait(mut self) -> (SpiDma<SPI, PINS, CH>, DmaBuffer) {
+        while !self.is_done() {}
+        (self.spi.take().unwrap(), self.buffer.take().unwrap())
+    }
+}
+
+impl<SPI, PINS, CH> Drop for SpiDmaTransfer<SPI, PINS, CH>
+where
+    SpiDma<SPI, PINS, CH>: DmaTransfer,
+{
+    fn drop(&mut self) {
+        if let Some(spi) = &self.spi {
+            while spi.is_busy() {}
+        }
+    }
+}
+
+macro_rules! halSpiDma {
+    ($(
+        $SPIX:ident: ($spiX_dma_chan_sel:ident),
+    )+) => {
+        $(
+            impl<PINS> super::Spi<$SPIX, PINS> {
+                /// Use DMA for transfers on the given channel
+                pub fn with_dma<CH: SpiDmaChannel>(
+                    self,
+                    channel: CH,
+                    dport: &mut esp32::DPORT,
+                ) -> SpiDma<$SPIX, PINS, CH> {
+                    dport.perip_clk_en.modify(|_, w| w.spi_dma().set_bit());
+                    dport.perip_rst_en.modify(|_, w| w.spi_dma().clear_bit());
+                    dport
+                        .spi_dma_chan_sel
+                        .modify(|_, w| unsafe { w.$spiX_dma_chan_sel().bits(CH::NUMBER) });
+
+                    self.spi.dma_conf.modify(|_, w| {
+                        w.outdscr_burst_en()
+                            .set_bit()
+                            .indscr_burst_en()
+                            .set_bit()
+                            .out_data_burst_en()
+                            .set_bit()
+                    });
+
+                    SpiDma { spi: self, channel }
+                }
+            }
+
+            impl<PINS, CH> DmaTransfer for SpiDma<$SPIX, PINS, CH> {
+                fn is_busy(&self) -> bool {
+                    self.spi.is_busy()
+                }
+            }
+
+            impl<PINS, CH: SpiDmaChannel> SpiDma<$SPIX, PINS, CH> {
+                /// Stop using DMA and return the SPI and DMA channel
+                pub fn release(self, dport: &mut esp32::DPORT) -> (super::Spi<$SPIX, PINS>, CH) {
+                    dport
+                        .spi_dma_chan_sel
+                        .modify(|_, w| unsafe { w.$spiX_dma_chan_sel().bits(0) });
+                    (self.spi, self.channel)
+                }
+
+                /// Return true if a transfer is in progress
+                pub fn is_busy(&self) -> bool {
+                    self.spi.is_busy()
+                }
+
+                /// Check if the buffer can be used for a transfer
+                fn check_buffer(buffer: &DmaBuffer) -> Result<(), dma::Error> {
+                    if buffer.is_empty() {
+                        return Err(dma::Error::EmptyBuffer);
+                    }
+                    if buffer.len() > dma::MAX_TRANSFER_SIZE {
+                        return Err(dma::Error::BufferTooLarge);
+                    }
+                    Ok(())
+                }
+
+                /// Setup the descriptors and start writing the buffer
+                fn start_tx(&mut self, buffer: &DmaBuffer) -> Result<(), dma::Error> {
+                    Self::check_buffer(buffer)?;
+                    self.spi.wait_transfer();
+
+                    let (tx_descriptors, _) = self.channel.descriptors();
+                    let tx = dma::fill_tx_descriptors(tx_descriptors, buffer)?;
+
+                    self.start(tx, None, buffer.len());
+                    Ok(())
+                }
+
+                /// Setup the descriptors and start writing the buffer while receiving into it
+                fn start_rx_tx(&mut self, buffer: &mut DmaBuffer) -> Result<(), dma::Error> {
+                    Self::check_buffer(buffer)?;
+                    self.spi.wait_transfer();
+
+                    let (tx_descriptors, rx_descriptors) = self.channel.descriptors();
+                    let tx = dma::fill_tx_descriptors(tx_descriptors, buffer)?;
+                    let rx = dma::fill_rx_descriptors(rx_descriptors, buffer)?;
+
+                    self.start(tx, Some(rx), buffer.len());
+                    Ok(())
+                }
+
+                /// Start the transfer with the filled descriptors
+                fn start(
+                    &mut self,
+                    tx: *const dma::Descriptor,
+                    rx: Option<*const dma::Descriptor>,
+                    len: usize,
+                ) {
+                    let spi = &self.spi.spi;
+
+                    // reset DMA state machines and FIFOs
+                    spi.dma_conf.modify(|_, w| {
+                        w.in_rst()
+                            .set_bit()
+                            .out_rst()
+                            .set_bit()
+                            .ahbm_fifo_rst()
+                            .set_bit()
+                            .ahbm_rst()
+                            .set_bit()
+                    });
+                    spi.dma_conf.modify(|_, w| {
+                        w.in_rst()
+                            .clear_bit()
+                            .out_rst()
+                            .clear_bit()
+                            .ahbm_fifo_rst()
+                            .clear_bit()
+                            .ahbm_rst()
+                            .clear_bit()
+                    });
+
+                    spi.dma_out_link.write(|w| unsafe {
+                        w.outlink_addr()
+                            .bits(tx as u32 & 0xfffff)
+                            .outlink_start()
+                            .set_bit()
+                    });
+                    if let Some(rx) = rx {
+                        spi.dma_in_link.write(|w| unsafe {
+                            w.inlink_addr()
+                                .bits(rx as u32 & 0xfffff)
+                                .inlink_start()
+                                .set_bit()
+                        });
+                    }
+
+                    self.spi.start_transfer(len * 8, true, rx.is_some());
+                }
+
+                /// Write the buffer and simultaneously replace its content with the received data.
+                /// Blocks until finished.
+                pub fn transfer(&mut self, buffer: &mut DmaBuffer) -> Result<(), dma::Error> {
+                    self.start_rx_tx(buffer)?;
+                    self.spi.wait_transfer();
+                    Ok(())
+                }
+
+                /// Write the buffer. Blocks until finished.
+                pub fn write(&mut self, buffer: &DmaBuffer) -> Result<(), dma::Error> {
+                    self.start_tx(buffer)?;
+                    self.spi.wait_transfer();
+                    Ok(())
+                }
+
+                /// Start writing the buffer and simultaneously replace its content with the
+                /// received data.
+                ///
+                /// On error the SPI and buffer are returned.
+                pub fn start_transfer(
+                    mut self,
+                    mut buffer: DmaBuffer,
+                ) -> Result<SpiDmaTransfer<$SPIX, PINS, CH>, (dma::Error, Self, DmaBuffer)> {
+                    match self.start_rx_tx(&mut buffer) {
+                        Ok(()) => Ok(SpiDmaTransfer {
+                            spi: Some(self),
+                            buffer: Some(buffer),
+                        }),
+                        Err(err) => Err((err, self, buffer)),
+                    }
+                }
+
+                /// Start writing the buffer.
+                ///
+                /// On error the SPI and buffer are returned.
+                pub fn start_write(
+                    mut self,
+                    buffer: DmaBuffer,
+                ) -> Result<SpiDmaTransfer<$SPIX, PINS, CH>, (dma::Error, Self, DmaBuffer)> {
+                    match self.start_tx(&buffer) {
+                        Ok(()) => Ok(SpiDmaTransfer {
+                            spi: Some(self),
+                            buffer: Some(buffer),
+                        }),
+                        Err(err) => Err((err, self, buffer)),
+                    }
+                }
+            }
+        )+
+    }
+}
+
+halSpiDma! {
+    SPI2: (spi_spi2_dma_chan_sel),
+    SPI3: (spi_spi3_dma_chan_sel),
+}
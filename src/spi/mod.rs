@@ -0,0 +1,520 @@
+//! SPI master peripheral control
+//!
+//! Controls the 2 general purpose SPI peripherals (SPI2 = HSPI, SPI3 = VSPI) in master mode.
+//!
+//! The pins are connected directly via the IO_MUX, so only the dedicated pins can be used:
+//!
+//! | Peripheral | SCLK   | MOSI   | MISO   | CS     |
+//! |------------|--------|--------|--------|--------|
+//! | SPI2/HSPI  | GPIO14 | GPIO13 | GPIO12 | GPIO15 |
+//! | SPI3/VSPI  | GPIO18 | GPIO23 | GPIO19 | GPIO5  |
+//!
+//! The pins need to be configured as alternate function 2.
+//!
+//! Transfers are done via the 64 byte data buffer (W0-W15) of the peripheral,
+//! longer transfers are automatically split. For large transfers DMA can be used,
+//! see the [dma] submodule.
+//!
+//! # TODO
+//! - Slave mode
+//! - Dual and quad modes
+//! - Command, address and dummy phases
+
+use core::convert::Infallible;
+use core::ptr::{read_volatile, write_volatile};
+
+use embedded_hal::spi;
+
+use crate::esp32::{SPI2, SPI3};
+use crate::gpio::{Alternate, Gpio12, Gpio13, Gpio14, Gpio15, Gpio18, Gpio19, Gpio23, Gpio5, AF2};
+use crate::units::*;
+
+pub mod dma;
+
+/// Size of the data buffer in bytes
+const SPI_BUFFER_SIZE: usize = 64;
+
+/// Maximum value of the clock pre divider
+const SPI_MAX_PRE_DIVIDER: u32 = 8192;
+
+/// Maximum value of the clock divider
+const SPI_MAX_DIVIDER: u32 = 64;
+
+/// SPI error
+#[derive(Debug)]
+pub enum Error {
+    /// Requested frequency too low
+    FrequencyTooLow,
+    /// Requested frequency too high
+    FrequencyTooHigh,
+}
+
+pub mod config {
+    use crate::units::*;
+    pub use embedded_hal::spi::{Mode, Phase, Polarity, MODE_0, MODE_1, MODE_2, MODE_3};
+
+    /// Order in which the bits are transferred
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub enum BitOrder {
+        /// Most significant bit first
+        MSBFirst,
+        /// Least significant bit first
+        LSBFirst,
+    }
+
+    /// Hardware chip select behavior
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub enum ChipSelect {
+        /// Chip select is active low
+        ActiveLow,
+        /// Chip select is active high
+        ActiveHigh,
+        /// Chip select is not driven by the peripheral (e.g. when driven manually via a GPIO)
+        Disabled,
+    }
+
+    #[derive(Debug, Copy, Clone)]
+    pub struct Config {
+        pub baudrate: Hertz,
+        pub mode: Mode,
+        pub bit_order: BitOrder,
+        pub chip_select: ChipSelect,
+        /// Number of SPI clock cycles chip select is activated before the transfer
+        pub cs_setup_cycles: u8,
+        /// Number of SPI clock cycles chip select is kept active after the transfer
+        pub cs_hold_cycles: u8,
+    }
+
+    impl Config {
+        pub fn baudrate(mut self, baudrate: Hertz) -> Self {
+            self.baudrate = baudrate;
+            self
+        }
+
+        pub fn mode(mut self, mode: Mode) -> Self {
+            self.mode = mode;
+            self
+        }
+
+        pub fn bit_order(mut self, bit_order: BitOrder) -> Self {
+            self.bit_order = bit_order;
+            self
+        }
+
+        pub fn chip_select(mut self, chip_select: ChipSelect) -> Self {
+            self.chip_select = chip_select;
+            self
+        }
+
+        pub fn cs_setup_cycles(mut self, cycles: u8) -> Self {
+            self.cs_setup_cycles = cycles;
+            self
+        }
+
+        pub fn cs_hold_cycles(mut self, cycles: u8) -> Self {
+            self.cs_hold_cycles = cycles;
+            self
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Config {
+            Config {
+                baudrate: Hertz(1_000_000),
+                mode: MODE_0,
+                bit_order: BitOrder::MSBFirst,
+                chip_select: ChipSelect::ActiveLow,
+                cs_setup_cycles: 0,
+                cs_hold_cycles: 0,
+            }
+        }
+    }
+}
+
+pub trait Pins<SPI> {}
+pub trait PinSclk<SPI> {}
+pub trait PinMosi<SPI> {}
+pub trait PinMiso<SPI> {}
+pub trait PinCs<SPI> {
+    /// Is the hardware chip select in use
+    const ENABLED: bool = true;
+}
+
+impl<SPI, SCLK, MOSI, MISO, CS> Pins<SPI> for (SCLK, MOSI, MISO, CS)
+where
+    SCLK: PinSclk<SPI>,
+    MOSI: PinMosi<SPI>,
+    MISO: PinMiso<SPI>,
+    CS: PinCs<SPI>,
+{
+}
+
+/// A filler type for when the MOSI pin is unnecessary
+pub struct NoMosi;
+/// A filler type for when the MISO pin is unnecessary
+pub struct NoMiso;
+/// A filler type for when the hardware CS pin is unnecessary
+pub struct NoCs;
+
+impl PinMosi<SPI2> for NoMosi {}
+impl PinMiso<SPI2> for NoMiso {}
+impl PinCs<SPI2> for NoCs {
+    const ENABLED: bool = false;
+}
+impl PinMosi<SPI3> for NoMosi {}
+impl PinMiso<SPI3> for NoMiso {}
+impl PinCs<SPI3> for NoCs {
+    const ENABLED: bool = false;
+}
+
+impl PinSclk<SPI2> for Gpio14<Alternate<AF2>> {}
+impl PinMosi<SPI2> for Gpio13<Alternate<AF2>> {}
+impl PinMiso<SPI2> for Gpio12<Alternate<AF2>> {}
+impl PinCs<SPI2> for Gpio15<Alternate<AF2>> {}
+
+impl PinSclk<SPI3> for Gpio18<Alternate<AF2>> {}
+impl PinMosi<SPI3> for Gpio23<Alternate<AF2>> {}
+impl PinMiso<SPI3> for Gpio19<Alternate<AF2>> {}
+impl PinCs<SPI3> for Gpio5<Alternate<AF2>> {}
+
+/// SPI abstraction
+///
+/// The APB frequency is locked as long as this structure exists.
+pub struct Spi<SPI, PINS> {
+    spi: SPI,
+    pins: PINS,
+    clock_control: crate::clock_control::ClockControlConfig,
+    _apb_lock: crate::clock_control::dfs::LockAPB,
+}
+
+macro_rules! halSpi {
+    ($(
+        $SPIX:ident: ($spiX:ident),
+    )+) => {
+        $(
+            impl<SCLK, MOSI, MISO, CS> Spi<$SPIX, (SCLK, MOSI, MISO, CS)> {
+                pub fn $spiX(
+                    spi: $SPIX,
+                    pins: (SCLK, MOSI, MISO, CS),
+                    config: config::Config,
+                    clock_control: crate::clock_control::ClockControlConfig,
+                    dport: &mut esp32::DPORT
+                ) -> Result<Self, Error>
+                where
+                    (SCLK, MOSI, MISO, CS): Pins<$SPIX>,
+                    CS: PinCs<$SPIX>,
+                {
+                    let mut spi = Spi {
+                        spi,
+                        pins,
+                        clock_control,
+                        _apb_lock: clock_control.lock_apb_frequency(),
+                    };
+
+                    spi
+                        .reset(dport)
+                        .enable(dport)
+                        .init()
+                        .change_mode(config.mode)
+                        .change_bit_order(config.bit_order)
+                        .change_chip_select(
+                            if CS::ENABLED { config.chip_select } else { config::ChipSelect::Disabled },
+                            config.cs_setup_cycles,
+                            config.cs_hold_cycles,
+                        )
+                        .change_baudrate(config.baudrate)?;
+
+                    Ok(spi)
+                }
+
+                /// Release the peripheral and pins
+                pub fn release(self) -> ($SPIX, (SCLK, MOSI, MISO, CS)) {
+                    (self.spi, self.pins)
+                }
+            }
+
+            impl<PINS> Spi<$SPIX, PINS> {
+                fn reset(&mut self, dport: &mut esp32::DPORT) -> &mut Self {
+                    dport.perip_rst_en.modify(|_, w| w.$spiX().set_bit());
+                    dport.perip_rst_en.modify(|_, w| w.$spiX().clear_bit());
+                    self
+                }
+
+                pub fn enable(&mut self, dport: &mut esp32::DPORT) -> &mut Self {
+                    dport.perip_clk_en.modify(|_, w| w.$spiX().set_bit());
+                    dport.perip_rst_en.modify(|_, w| w.$spiX().clear_bit());
+                    self
+                }
+
+                pub fn disable(&mut self, dport: &mut esp32::DPORT) -> &mut Self {
+                    dport.perip_clk_en.modify(|_, w| w.$spiX().clear_bit());
+                    dport.perip_rst_en.modify(|_, w| w.$spiX().set_bit());
+                    self
+                }
+
+                /// Initialize peripheral as master in full duplex mode without
+                /// command, address and dummy phases
+                fn init(&mut self) -> &mut Self {
+                    self.spi.slave.write(|w| unsafe { w.bits(0) });
+                    self.spi.ctrl.write(|w| unsafe { w.bits(0) });
+                    self.spi.user.write(|w| {
+                        w.doutdin()
+                            .set_bit()
+                            .usr_mosi()
+                            .set_bit()
+                            .usr_miso()
+                            .set_bit()
+                    });
+                    self.spi.user1.write(|w| unsafe { w.bits(0) });
+                    self.spi.user2.write(|w| unsafe { w.bits(0) });
+                    self.spi.pin.write(|w| w.cs1_dis().set_bit().cs2_dis().set_bit());
+                    self
+                }
+
+                /// Change the SPI mode (clock polarity and phase)
+                pub fn change_mode(&mut self, mode: spi::Mode) -> &mut Self {
+                    let idle_high = mode.polarity == spi::Polarity::IdleHigh;
+                    let second_edge = mode.phase == spi::Phase::CaptureOnSecondTransition;
+
+                    self.spi.pin.modify(|_, w| w.ck_idle_edge().bit(idle_high));
+                    self.spi
+                        .user
+                        .modify(|_, w| w.ck_out_edge().bit(idle_high != second_edge));
+
+                    // delay the sampling of the input to compensate for the pad delays
+                    self.spi.ctrl2.modify(|_, w| unsafe {
+                        w.miso_delay_mode()
+                            .bits(if idle_high != second_edge { 1 } else { 2 })
+                            .miso_delay_num()
+                            .bits(0)
+                            .mosi_delay_mode()
+                            .bits(0)
+                            .mosi_delay_num()
+                            .bits(0)
+                    });
+
+                    self
+                }
+
+                /// Change the bit order for both transmission and reception
+                pub fn change_bit_order(&mut self, bit_order: config::BitOrder) -> &mut Self {
+                    let lsb_first = bit_order == config::BitOrder::LSBFirst;
+                    self.spi
+                        .ctrl
+                        .modify(|_, w| w.wr_bit_order().bit(lsb_first).rd_bit_order().bit(lsb_first));
+                    self
+                }
+
+                /// Change the chip select behavior and setup & hold times (in SPI clock cycles)
+                pub fn change_chip_select(
+                    &mut self,
+                    chip_select: config::ChipSelect,
+                    setup_cycles: u8,
+                    hold_cycles: u8,
+                ) -> &mut Self {
+                    self.spi.pin.modify(|_, w| unsafe {
+                        w.cs0_dis()
+                            .bit(chip_select == config::ChipSelect::Disabled)
+                            .master_cs_pol()
+                            .bits((chip_select == config::ChipSelect::ActiveHigh) as u8)
+                    });
+
+                    self.spi.user.modify(|_, w| {
+                        w.cs_setup()
+                            .bit(setup_cycles > 0)
+                            .cs_hold()
+                            .bit(hold_cycles > 0)
+                    });
+
+                    self.spi.ctrl2.modify(|_, w| unsafe {
+                        w.setup_time()
+                            .bits(setup_cycles.saturating_sub(1) & 0xf)
+                            .hold_time()
+                            .bits(hold_cycles.saturating_sub(1) & 0xf)
+                    });
+
+                    self
+                }
+
+                /// Change the SPI clock frequency
+                ///
+                /// The nearest achievable frequency is selected.
+                pub fn change_baudrate<T: Into<Hertz> + Copy>(
+                    &mut self,
+                    baudrate: T,
+                ) -> Result<&mut Self, Error> {
+                    let apb_frequency = self.clock_control.apb_frequency_apb_locked();
+                    let baudrate: Hertz = baudrate.into();
+
+                    if baudrate == Hertz(0) {
+                        return Err(Error::FrequencyTooLow);
+                    }
+                    if baudrate > apb_frequency {
+                        return Err(Error::FrequencyTooHigh);
+                    }
+                    if baudrate
+                        < apb_frequency / (SPI_MAX_PRE_DIVIDER * SPI_MAX_DIVIDER)
+                    {
+                        return Err(Error::FrequencyTooLow);
+                    }
+
+                    if baudrate == apb_frequency {
+                        self.spi.clock.write(|w| w.clk_equ_sysclk().set_bit());
+                        return Ok(self);
+                    }
+
+                    // find the combination of pre divider and divider with the smallest error
+                    let mut best = (1, 2, Hertz(u32::max_value()));
+                    for n in 2..=SPI_MAX_DIVIDER {
+                        let pre = core::cmp::min(
+                            SPI_MAX_PRE_DIVIDER,
+                            core::cmp::max(1, (apb_frequency / n + baudrate / 2) / baudrate),
+                        );
+                        let actual = apb_frequency / (pre * n);
+                        let error = if actual > baudrate {
+                            actual - baudrate
+                        } else {
+                            baudrate - actual
+                        };
+                        if error <= best.2 {
+                            best = (pre, n, error);
+                        }
+                    }
+                    let (pre, n, _) = best;
+
+                    // 50% duty cycle
+                    let h = core::cmp::max(1, (n + 1) / 2);
+
+                    self.spi.clock.write(|w| unsafe {
+                        w.clk_equ_sysclk()
+                            .clear_bit()
+                            .clkdiv_pre()
+                            .bits((pre - 1) as u16)
+                            .clkcnt_n()
+                            .bits((n - 1) as u8)
+                            .clkcnt_h()
+                            .bits((h - 1) as u8)
+                            .clkcnt_l()
+                            .bits((n - 1) as u8)
+                    });
+
+                    Ok(self)
+                }
+
+                /// Get the actual SPI clock frequency
+                pub fn baudrate(&self) -> Hertz {
+                    let apb_frequency = self.clock_control.apb_frequency_apb_locked();
+                    let clock = self.spi.clock.read();
+
+                    if clock.clk_equ_sysclk().bit_is_set() {
+                        apb_frequency
+                    } else {
+                        apb_frequency
+                            / ((clock.clkdiv_pre().bits() as u32 + 1)
+                                * (clock.clkcnt_n().bits() as u32 + 1))
+                    }
+                }
+
+                /// Return true if a transfer is in progress
+                pub fn is_busy(&self) -> bool {
+                    self.spi.cmd.read().usr().bit_is_set()
+                }
+
+                /// Start a transfer of a number of bits, reading and/or writing
+                fn start_transfer(&mut self, bits: usize, write: bool, read: bool) {
+                    self.spi
+                        .user
+                        .modify(|_, w| w.usr_mosi().bit(write).usr_miso().bit(read));
+                    self.spi
+                        .mosi_dlen
+                        .write(|w| unsafe { w.usr_mosi_dbitlen().bits(bits as u32 - 1) });
+                    self.spi
+                        .miso_dlen
+                        .write(|w| unsafe { w.usr_miso_dbitlen().bits(bits as u32 - 1) });
+                    self.spi.cmd.modify(|_, w| w.usr().set_bit());
+                }
+
+                /// Wait for the current transfer to finish
+                fn wait_transfer(&self) {
+                    while self.is_busy() {}
+                }
+
+                /// Copy bytes into the data buffer
+                fn write_buffer(&mut self, bytes: &[u8]) {
+                    let buffer = self.spi.w0.as_ptr();
+                    for (i, chunk) in bytes.chunks(4).enumerate() {
+                        let mut word = [0u8; 4];
+                        word[..chunk.len()].copy_from_slice(chunk);
+                        unsafe { write_volatile(buffer.add(i), u32::from_le_bytes(word)) };
+                    }
+                }
+
+                /// Copy bytes from the data buffer
+                fn read_buffer(&self, bytes: &mut [u8]) {
+                    let buffer = self.spi.w0.as_ptr();
+                    for (i, chunk) in bytes.chunks_mut(4).enumerate() {
+                        let word = unsafe { read_volatile(buffer.add(i)) }.to_le_bytes();
+                        let len = chunk.len();
+                        chunk.copy_from_slice(&word[..len]);
+                    }
+                }
+            }
+
+            impl<PINS> spi::FullDuplex<u8> for Spi<$SPIX, PINS> {
+                type Error = Infallible;
+
+                fn read(&mut self) -> nb::Result<u8, Self::Error> {
+                    if self.is_busy() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    let mut byte = [0u8; 1];
+                    self.read_buffer(&mut byte);
+                    Ok(byte[0])
+                }
+
+                fn send(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+                    if self.is_busy() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    self.write_buffer(&[byte]);
+                    self.start_transfer(8, true, true);
+                    Ok(())
+                }
+            }
+
+            impl<PINS> embedded_hal::blocking::spi::Transfer<u8> for Spi<$SPIX, PINS> {
+                type Error = Infallible;
+
+                fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+                    self.wait_transfer();
+                    for chunk in words.chunks_mut(SPI_BUFFER_SIZE) {
+                        self.write_buffer(chunk);
+                        self.start_transfer(chunk.len() * 8, true, true);
+                        self.wait_transfer();
+                        self.read_buffer(chunk);
+                    }
+                    Ok(words)
+                }
+            }
+
+            impl<PINS> embedded_hal::blocking::spi::Write<u8> for Spi<$SPIX, PINS> {
+                type Error = Infallible;
+
+                fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+                    self.wait_transfer();
+                    for chunk in words.chunks(SPI_BUFFER_SIZE) {
+                        self.write_buffer(chunk);
+                        self.start_transfer(chunk.len() * 8, true, false);
+                        self.wait_transfer();
+                    }
+                    Ok(())
+                }
+            }
+        )+
+    }
+}
+
+halSpi! {
+    SPI2: (spi2),
+    SPI3: (spi3),
+}
@@ -0,0 +1,586 @@
+//! Monotonic system time
+//!
+//! The system time is kept by the LACT (legacy) timer of TIMG0, which is started when the clock
+//! configuration is frozen. The timer counts at about 1MHz and the time is kept in nanoseconds
+//! since the clock configuration was frozen.
+//!
+//! The timer is clocked by the APB clock. The APB frequency is not locked: on every frequency
+//! change the prescaler is updated via a DFS callback. During light sleep the timer is stopped,
+//! the time is advanced by the time slept as measured by the RTC counter.
+//!
+//! [Instant] and [Duration] interoperate with the units [NanoSeconds], [MicroSeconds],
+//! [MilliSeconds] and [Seconds]. [Delay] implements the
+//! [DelayMs](embedded_hal::blocking::delay::DelayMs) and
+//! [DelayUs](embedded_hal::blocking::delay::DelayUs) traits.
+//!
+//! # Interrupts
+//! [Delay] arms the alarm of the LACT timer and waits for its interrupt instead of spinning. The
+//! alarm interrupt is handled by [handle_interrupt], which has to be called from the
+//! TG0_LACT_LEVEL_INTR interrupt handler defined by the application. The interrupt needs to be
+//! enabled via [interrupt::enable](crate::interrupt::enable) or
+//! [interrupt::enable_with_priority](crate::interrupt::enable_with_priority) on the core using
+//! the delay. When the interrupt is not enabled on the current core, or the delay is used in a
+//! critical section or interrupt handler, the delay polls the system time instead.
+//!
+//! **Note: there is only a single alarm. When delays run on both cores at the same time, the
+//! alarm of one core overwrites the alarm of the other, which then wakes up late (or only on
+//! another interrupt). Use the delay on one core only or enable other interrupts on both.**
+//!
+//! # Example
+//! ```
+//! #[interrupt]
+//! fn TG0_LACT_LEVEL_INTR() {
+//!     time::handle_interrupt();
+//! }
+//!
+//! interrupt::enable(Interrupt::TG0_LACT_LEVEL_INTR).unwrap();
+//!
+//! let start = time::Instant::now(clock_control_config);
+//! let mut delay = time::Delay::new(clock_control_config);
+//! delay.delay_ms(100u32);
+//! let elapsed = MilliSeconds::try_from(start.elapsed()).unwrap();
+//! ```
+//!
+//! *Note: the LACT timer is independent of the general purpose timers of TIMG0.*
+
+use core::convert::TryFrom;
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::clock_control::ClockControlConfig;
+use crate::esp32::TIMG0;
+use crate::interrupt::TG0_LACT_LEVEL_INTR;
+use crate::units::*;
+
+// Target frequency of the timer
+const TIMER_FREQUENCY: Hertz = Hertz(1_000_000);
+/// Minimum value of the prescaler
+const TIMER_MIN_DIVIDER: u32 = 2;
+/// Maximum value of the prescaler
+const TIMER_MAX_DIVIDER: u32 = 65536;
+
+// Bits of the TIMG_LACTCONFIG_REG register
+const CONFIG_EN: u32 = 1 << 31;
+const CONFIG_INCREASE: u32 = 1 << 30;
+const CONFIG_DIVIDER_SHIFT: u32 = 13;
+const CONFIG_DIVIDER_MASK: u32 = 0xffff << CONFIG_DIVIDER_SHIFT;
+const CONFIG_LEVEL_INT_EN: u32 = 1 << 11;
+const CONFIG_ALARM_EN: u32 = 1 << 10;
+
+// Bit of the LACT timer in the TIMG_INT_*_TIMERS_REG registers
+const INT_LACT: u32 = 1 << 3;
+
+// Interrupt level bits of the PS register
+const PS_INTLEVEL_MASK: u32 = 0xf;
+
+// Minimum delay for which light sleep is attempted
+const DELAY_IDLE_MIN: Duration = Duration::from_millis(2);
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Time errors
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Error {
+    /// Duration does not fit in the unit
+    Overflow,
+}
+
+/// A span of time with nanosecond resolution
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Default, Debug)]
+pub struct Duration(u64);
+
+impl Duration {
+    /// Create a duration from nanoseconds
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Duration(nanos)
+    }
+
+    /// Create a duration from microseconds
+    pub const fn from_micros(micros: u64) -> Self {
+        Duration(micros * 1_000)
+    }
+
+    /// Create a duration from milliseconds
+    pub const fn from_millis(millis: u64) -> Self {
+        Duration(millis * 1_000_000)
+    }
+
+    /// Create a duration from seconds
+    pub const fn from_secs(secs: u64) -> Self {
+        Duration(secs * NANOSECONDS_PER_SECOND)
+    }
+
+    /// Whole nanoseconds of the duration
+    pub const fn as_nanos(&self) -> u64 {
+        self.0
+    }
+
+    /// Whole microseconds of the duration
+    pub const fn as_micros(&self) -> u64 {
+        self.0 / 1_000
+    }
+
+    /// Whole milliseconds of the duration
+    pub const fn as_millis(&self) -> u64 {
+        self.0 / 1_000_000
+    }
+
+    /// Whole seconds of the duration
+    pub const fn as_secs(&self) -> u64 {
+        self.0 / NANOSECONDS_PER_SECOND
+    }
+
+    /// Add two durations, None on overflow
+    pub fn checked_add(self, rhs: Duration) -> Option<Duration> {
+        Some(Duration(self.0.checked_add(rhs.0)?))
+    }
+
+    /// Subtract two durations, None if the result would be negative
+    pub fn checked_sub(self, rhs: Duration) -> Option<Duration> {
+        Some(Duration(self.0.checked_sub(rhs.0)?))
+    }
+
+    /// Subtract two durations, zero if the result would be negative
+    pub fn saturating_sub(self, rhs: Duration) -> Duration {
+        Duration(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl core::ops::Add for Duration {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output {
+        Duration(self.0 + rhs.0)
+    }
+}
+
+impl core::ops::Sub for Duration {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Duration(self.0 - rhs.0)
+    }
+}
+
+impl core::ops::AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 += rhs.0;
+    }
+}
+
+impl core::ops::SubAssign for Duration {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl core::ops::Mul<u32> for Duration {
+    type Output = Self;
+    fn mul(self, rhs: u32) -> Self::Output {
+        Duration(self.0 * rhs as u64)
+    }
+}
+
+impl core::ops::Div<u32> for Duration {
+    type Output = Self;
+    fn div(self, rhs: u32) -> Self::Output {
+        Duration(self.0 / rhs as u64)
+    }
+}
+
+/// A point in time of the monotonic system time
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Debug)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// The current time
+    ///
+    /// The clock control configuration is only taken as proof that the clock configuration is
+    /// frozen and therefore the system time is running.
+    pub fn now(clock_control_config: ClockControlConfig) -> Self {
+        let _ = clock_control_config;
+        Instant(now())
+    }
+
+    /// Time since the clock configuration was frozen
+    pub fn duration_since_start(&self) -> Duration {
+        Duration(self.0)
+    }
+
+    /// Time elapsed since an earlier instant, zero if the instant is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Time elapsed since this instant
+    pub fn elapsed(&self) -> Duration {
+        Instant(now()).duration_since(*self)
+    }
+
+    /// Add a duration, None on overflow
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        Some(Instant(self.0.checked_add(duration.0)?))
+    }
+
+    /// Subtract a duration, None if before the start of the system time
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        Some(Instant(self.0.checked_sub(duration.0)?))
+    }
+}
+
+impl core::ops::Add<Duration> for Instant {
+    type Output = Self;
+    fn add(self, rhs: Duration) -> Self::Output {
+        Instant(self.0 + rhs.0)
+    }
+}
+
+impl core::ops::Sub<Duration> for Instant {
+    type Output = Self;
+    fn sub(self, rhs: Duration) -> Self::Output {
+        Instant(self.0 - rhs.0)
+    }
+}
+
+impl core::ops::AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.0 += rhs.0;
+    }
+}
+
+impl core::ops::SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl core::ops::Sub for Instant {
+    type Output = Duration;
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
+
+/// implements conversions and arithmetic between the time units and Duration
+macro_rules! unit {
+    ($($quantity:ident: $factor:expr,)+) => {
+        $(
+            impl From<$quantity> for Duration {
+                fn from(x: $quantity) -> Self {
+                    Duration(x.0 as u64 * $factor)
+                }
+            }
+
+            impl TryFrom<Duration> for $quantity {
+                type Error = Error;
+                fn try_from(x: Duration) -> Result<Self, Self::Error> {
+                    let value = x.0 / $factor;
+                    if value > u32::max_value() as u64 {
+                        Err(Error::Overflow)
+                    } else {
+                        Ok($quantity(value as u32))
+                    }
+                }
+            }
+
+            impl core::ops::Add<$quantity> for Instant {
+                type Output = Self;
+                fn add(self, rhs: $quantity) -> Self::Output {
+                    self + Duration::from(rhs)
+                }
+            }
+
+            impl core::ops::Sub<$quantity> for Instant {
+                type Output = Self;
+                fn sub(self, rhs: $quantity) -> Self::Output {
+                    self - Duration::from(rhs)
+                }
+            }
+        )+
+    };
+}
+
+unit!(
+    NanoSeconds: 1,
+    MicroSeconds: 1_000,
+    MilliSeconds: 1_000_000,
+    Seconds: NANOSECONDS_PER_SECOND,
+);
+
+/// State of the system time at the last frequency change
+struct State {
+    clock_control_config: ClockControlConfig,
+    frequency: Hertz,
+    counter: u64,
+    time: u64,
+    /// Time of the armed alarm
+    alarm: Option<u64>,
+}
+
+static STATE: spin::Mutex<Option<State>> = spin::Mutex::new(None);
+
+fn timg() -> &'static esp32::timg::RegisterBlock {
+    // NOTE(unsafe) only the LACT registers are accessed
+    unsafe { &*TIMG0::ptr() }
+}
+
+/// Read the counter of the timer
+fn counter() -> u64 {
+    let timg = timg();
+    // latch the counter value
+    timg.lactupdate.write(|w| unsafe { w.bits(0) });
+    ((timg.lacthi.read().bits() as u64) << 32) | timg.lactlo.read().bits() as u64
+}
+
+/// Set the prescaler of the timer to get as close as possible to the target frequency and
+/// return the resulting frequency
+fn set_divider(apb_frequency: Hertz) -> Hertz {
+    let divider = match apb_frequency / TIMER_FREQUENCY {
+        divider if divider < TIMER_MIN_DIVIDER => TIMER_MIN_DIVIDER,
+        divider if divider > TIMER_MAX_DIVIDER => TIMER_MAX_DIVIDER,
+        divider => divider,
+    };
+    // 65536 is encoded as 0
+    let bits = (divider & 0xffff) << CONFIG_DIVIDER_SHIFT;
+
+    // the prescaler should only be changed when the timer is disabled
+    let timg = timg();
+    timg.lactconfig
+        .modify(|r, w| unsafe { w.bits(r.bits() & !CONFIG_EN) });
+    timg.lactconfig
+        .modify(|r, w| unsafe { w.bits((r.bits() & !CONFIG_DIVIDER_MASK) | bits) });
+    timg.lactconfig
+        .modify(|r, w| unsafe { w.bits(r.bits() | CONFIG_EN) });
+
+    apb_frequency / divider
+}
+
+/// Convert a number of timer ticks to nanoseconds
+fn ticks_to_ns(ticks: u64, frequency: Hertz) -> u64 {
+    let frequency = u32::from(frequency) as u64;
+
+    // split to prevent overflow
+    ticks / frequency * NANOSECONDS_PER_SECOND
+        + ticks % frequency * NANOSECONDS_PER_SECOND / frequency
+}
+
+/// Convert nanoseconds to a number of timer ticks, rounded up
+fn ns_to_ticks(ns: u64, frequency: Hertz) -> u64 {
+    let frequency = u32::from(frequency) as u64;
+
+    // split to prevent overflow
+    ns / NANOSECONDS_PER_SECOND * frequency
+        + (ns % NANOSECONDS_PER_SECOND * frequency + NANOSECONDS_PER_SECOND - 1)
+            / NANOSECONDS_PER_SECOND
+}
+
+/// Arm the alarm of the timer at the given time
+///
+/// The alarm only triggers when the counter reaches the alarm value, so an alarm which has
+/// already passed is moved to the near future.
+fn set_alarm(state: &mut State, time: u64) {
+    let timg = timg();
+    let mut alarm = state.counter + ns_to_ticks(time.saturating_sub(state.time), state.frequency);
+    let mut margin = 1;
+
+    loop {
+        unsafe {
+            timg.lactalarmlo.write(|w| w.bits(alarm as u32));
+            timg.lactalarmhi.write(|w| w.bits((alarm >> 32) as u32));
+        }
+        timg.lactconfig
+            .modify(|r, w| unsafe { w.bits(r.bits() | CONFIG_ALARM_EN | CONFIG_LEVEL_INT_EN) });
+
+        let counter = counter();
+        if counter < alarm {
+            break;
+        }
+        alarm = counter + margin;
+        margin *= 2;
+    }
+
+    // the register is shared with the general purpose timers and the watchdog
+    xtensa_lx6_rt::interrupt::free(|_| {
+        timg.int_ena_timers
+            .modify(|r, w| unsafe { w.bits(r.bits() | INT_LACT) })
+    });
+    state.alarm = Some(time);
+}
+
+/// Handle the alarm interrupt of the LACT timer
+///
+/// Needs to be called from the TG0_LACT_LEVEL_INTR interrupt handler.
+pub fn handle_interrupt() {
+    timg().int_clr_timers.write(|w| unsafe { w.bits(INT_LACT) });
+
+    if let Some(state) = &mut *STATE.lock() {
+        state.alarm = None;
+    }
+}
+
+/// Start the system time, called when the clock configuration is frozen
+pub(crate) fn init(
+    clock_control_config: ClockControlConfig,
+) -> Result<(), crate::clock_control::Error> {
+    let timg = timg();
+
+    xtensa_lx6_rt::interrupt::free(|_| {
+        unsafe {
+            timg.lactconfig.write(|w| w.bits(CONFIG_INCREASE));
+            timg.lactloadlo.write(|w| w.bits(0));
+            timg.lactloadhi.write(|w| w.bits(0));
+            timg.lactload.write(|w| w.bits(0));
+        }
+        let frequency = set_divider(clock_control_config.apb_frequency());
+
+        *STATE.lock() = Some(State {
+            clock_control_config,
+            frequency,
+            counter: counter(),
+            time: 0,
+            alarm: None,
+        });
+    });
+
+    clock_control_config.add_callback(&update_frequency)
+}
+
+/// Update the prescaler after a change of the APB frequency
+///
+/// The ticks counted between the actual frequency change and this callback are converted with
+/// the previous frequency.
+fn update_frequency() {
+    xtensa_lx6_rt::interrupt::free(|_| {
+        if let Some(state) = &mut *STATE.lock() {
+            let counter = counter();
+            state.time += ticks_to_ns(counter - state.counter, state.frequency);
+            state.frequency = set_divider(state.clock_control_config.apb_frequency());
+            state.counter = counter();
+
+            // the alarm value depends on the frequency
+            if let Some(alarm) = state.alarm {
+                set_alarm(state, alarm);
+            }
+        }
+    });
+}
+
+/// Advance the system time by the time the timer was stopped during light sleep
+pub(crate) fn advance(rtc_ticks: u64, slow_rtc_frequency: Hertz) {
+    xtensa_lx6_rt::interrupt::free(|_| {
+        if let Some(state) = &mut *STATE.lock() {
+            state.time += ticks_to_ns(rtc_ticks, slow_rtc_frequency);
+
+            // the alarm value depends on the time at the reference counter value
+            if let Some(alarm) = state.alarm {
+                set_alarm(state, alarm);
+            }
+        }
+    });
+}
+
+/// Nanoseconds since the clock configuration was frozen
+///
+/// The state is always set, as the system time is started before the clock control
+/// configuration is handed out.
+fn now() -> u64 {
+    xtensa_lx6_rt::interrupt::free(|_| {
+        let state = STATE.lock();
+        let state = state.as_ref().unwrap();
+        state.time + ticks_to_ns(counter() - state.counter, state.frequency)
+    })
+}
+
+/// Current interrupt level of the CPU (PS.INTLEVEL)
+fn interrupt_level() -> u32 {
+    let ps: u32;
+    unsafe { llvm_asm!("rsr.ps $0" : "=r"(ps) ::: "volatile") };
+    ps & PS_INTLEVEL_MASK
+}
+
+/// Wait for an interrupt, at the latest until the given time
+///
+/// Returns immediately if the alarm interrupt cannot wake up the CPU: when it is not enabled on
+/// the current core or when interrupts are masked (in a critical section or interrupt handler).
+fn wait_until(time: u64) {
+    if interrupt_level() != 0 || !crate::interrupt::is_enabled(TG0_LACT_LEVEL_INTR) {
+        return;
+    }
+
+    // mask the interrupts while arming, so an alarm triggering before waiting is not lost
+    let _ps: u32;
+    unsafe { llvm_asm!("rsil $0, 15" : "=r"(_ps) ::: "volatile") };
+
+    if let Some(state) = &mut *STATE.lock() {
+        set_alarm(state, time);
+    }
+
+    // waiti restores the interrupt level of the caller (0) while waiting, so an alarm which
+    // triggered after it was armed wakes up the CPU immediately
+    unsafe { llvm_asm!("waiti 0" :::: "volatile") };
+}
+
+/// Delay based on the system time
+///
+/// Unlike [sleep](crate::clock_control::sleep), which counts CPU cycles, the delay stays
+/// correct across DFS transitions. The CPU waits for the alarm interrupt of the LACT timer
+/// instead of spinning, if that interrupt is enabled (see the module documentation). For longer
+/// delays the CPU is put in light sleep via [ClockControlConfig::idle] if enabled and no awake
+/// lock is held.
+#[derive(Copy, Clone)]
+pub struct Delay {
+    clock_control_config: ClockControlConfig,
+}
+
+impl Delay {
+    /// Create a new delay
+    pub fn new(clock_control_config: ClockControlConfig) -> Self {
+        Delay {
+            clock_control_config,
+        }
+    }
+
+    /// Wait for the given time
+    pub fn delay<T: Into<Duration>>(&mut self, time: T) {
+        let end = Instant::now(self.clock_control_config) + time.into();
+
+        loop {
+            let now = Instant::now(self.clock_control_config);
+            if now >= end {
+                break;
+            }
+
+            let remaining = end - now;
+            if remaining >= DELAY_IDLE_MIN
+                && self
+                    .clock_control_config
+                    .idle(Some(MilliSeconds(remaining.as_millis() as u32)))
+                    .is_some()
+            {
+                continue;
+            }
+
+            // polls if the alarm interrupt cannot be used
+            wait_until(end.0);
+        }
+    }
+}
+
+/// implements the embedded hal delay traits for the primitive types
+macro_rules! delay {
+    ($($primitive:ty),+) => {
+        $(
+            impl DelayMs<$primitive> for Delay {
+                fn delay_ms(&mut self, ms: $primitive) {
+                    self.delay(Duration::from_millis(ms as u64));
+                }
+            }
+
+            impl DelayUs<$primitive> for Delay {
+                fn delay_us(&mut self, us: $primitive) {
+                    self.delay(Duration::from_micros(us as u64));
+                }
+            }
+        )+
+    };
+}
+
+delay!(u8, u16, u32);
@@ -0,0 +1,415 @@
+//! Timer Group general purpose timers
+//!
+//! Each of the two timer groups (TIMG0, TIMG1) contains two 64-bit general purpose timers, which
+//! are clocked by the APB clock via a 16-bit prescaler, and a main watchdog timer
+//! ([TimerGroupWatchdog]).
+//!
+//! The timers implement the [CountDown](embedded_hal::timer::CountDown),
+//! [Periodic](embedded_hal::timer::Periodic) and [Cancel](embedded_hal::timer::Cancel) traits.
+//! The APB frequency is locked while a timer is running.
+//!
+//! # Interrupts
+//! Alarms can raise a level interrupt ([Event::TimeOut], TG*_T*_LEVEL_INTR) or an edge interrupt
+//! ([Event::TimeOutEdge], TG*_T*_EDGE_INTR). The interrupt needs to be mapped via
+//! [interrupt::enable](crate::interrupt::enable) or
+//! [interrupt::enable_with_priority](crate::interrupt::enable_with_priority).
+//! The level interrupt needs to be cleared via [Timer::clear_interrupt] in the handler.
+//!
+//! # Example
+//! ```
+//! let (mut timer0, mut timer1, mut watchdog) = Timer::timg1(dp.TIMG1, clkcntrl_config);
+//!
+//! timer0.start(1.s());
+//! loop {
+//!     block!(timer0.wait()).unwrap();
+//! }
+//! ```
+//!
+//! *Note: TIMG0 is also used for the calibration of the RTC clocks and its LACT timer keeps the
+//! [system time](crate::time). These are independent of the general purpose timers.*
+
+use core::marker::PhantomData;
+
+use embedded_hal::timer::{Cancel, CountDown, Periodic};
+
+use crate::clock_control::watchdog::TimerGroupWatchdog;
+use crate::clock_control::{dfs, ClockControlConfig};
+use crate::esp32::{TIMG0, TIMG1};
+use crate::units::*;
+
+/// Minimum value of the prescaler
+const TIMER_MIN_DIVIDER: u32 = 2;
+/// Maximum value of the prescaler
+const TIMER_MAX_DIVIDER: u32 = 65536;
+
+// Bits of the timer configuration register
+const CONFIG_EN: u32 = 1 << 31;
+const CONFIG_INCREASE: u32 = 1 << 30;
+const CONFIG_AUTORELOAD: u32 = 1 << 29;
+const CONFIG_DIVIDER_SHIFT: u32 = 13;
+const CONFIG_DIVIDER_MASK: u32 = 0xffff << CONFIG_DIVIDER_SHIFT;
+const CONFIG_EDGE_INT_EN: u32 = 1 << 12;
+const CONFIG_LEVEL_INT_EN: u32 = 1 << 11;
+const CONFIG_ALARM_EN: u32 = 1 << 10;
+
+/// Timer errors
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Error {
+    /// Prescaler outside of the supported range of 2 to 65536
+    InvalidDivider,
+    /// Timer is not running
+    TimerNotRunning,
+}
+
+/// Timer interrupt event
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Event {
+    /// Level interrupt on alarm (TG*_T*_LEVEL_INTR)
+    TimeOut,
+    /// Edge interrupt on alarm (TG*_T*_EDGE_INTR)
+    TimeOutEdge,
+}
+
+/// First timer of a timer group
+pub struct Timer0;
+/// Second timer of a timer group
+pub struct Timer1;
+
+/// General purpose timer
+pub struct Timer<TIMG, INST> {
+    clock_control: ClockControlConfig,
+    _apb_lock: Option<dfs::LockAPB>,
+    _timg: PhantomData<TIMG>,
+    _inst: PhantomData<INST>,
+}
+
+macro_rules! halTimerGroup {
+    ($(
+        $TIMGX:ident: $timgX:ident,
+    )+) => {
+        $(
+            impl Timer<$TIMGX, Timer0> {
+                /// Split the timer group into its two general purpose timers and its watchdog
+                ///
+                /// The timers are stopped and their prescalers set to the minimum value.
+                /// The watchdog is left untouched, as it may have been started by the bootloader.
+                pub fn $timgX(
+                    _timg: $TIMGX,
+                    clock_control: ClockControlConfig,
+                ) -> (
+                    Timer<$TIMGX, Timer0>,
+                    Timer<$TIMGX, Timer1>,
+                    TimerGroupWatchdog<$TIMGX>,
+                ) {
+                    let mut timer0 = Timer {
+                        clock_control,
+                        _apb_lock: None,
+                        _timg: PhantomData,
+                        _inst: PhantomData,
+                    };
+                    let mut timer1 = Timer {
+                        clock_control,
+                        _apb_lock: None,
+                        _timg: PhantomData,
+                        _inst: PhantomData,
+                    };
+
+                    timer0.init();
+                    timer1.init();
+
+                    (timer0, timer1, TimerGroupWatchdog::new(clock_control))
+                }
+
+                /// Stop both timers and release the timer group
+                ///
+                /// The watchdog is not changed.
+                pub fn release(
+                    mut self,
+                    mut timer1: Timer<$TIMGX, Timer1>,
+                    _watchdog: TimerGroupWatchdog<$TIMGX>,
+                ) -> $TIMGX {
+                    self.init();
+                    timer1.init();
+
+                    // NOTE(unsafe) both timers have been consumed, so the timer group is not used
+                    unsafe { esp32::Peripherals::steal().$TIMGX }
+                }
+            }
+        )+
+    }
+}
+
+halTimerGroup! {
+    TIMG0: timg0,
+    TIMG1: timg1,
+}
+
+macro_rules! halTimer {
+    ($(
+        $TIMGX:ident, $TIMERX:ident: (
+            $config:ident, $lo:ident, $hi:ident, $update:ident, $alarmlo:ident, $alarmhi:ident,
+            $loadlo:ident, $loadhi:ident, $load:ident, $int_bit:expr
+        ),
+    )+) => {
+        $(
+            impl Timer<$TIMGX, $TIMERX> {
+                fn timg(&self) -> &esp32::timg::RegisterBlock {
+                    // NOTE(unsafe) only the registers of this timer are accessed
+                    unsafe { &*$TIMGX::ptr() }
+                }
+
+                /// Stop the timer, disable its interrupts and reset the configuration
+                fn init(&mut self) {
+                    self.timg().$config.write(|w| unsafe {
+                        w.bits(CONFIG_INCREASE | TIMER_MIN_DIVIDER << CONFIG_DIVIDER_SHIFT)
+                    });
+                    self.unlisten(Event::TimeOut);
+                    self.clear_interrupt();
+                    self._apb_lock = None;
+                }
+
+                fn modify_config(&mut self, set: u32, clear: u32) {
+                    self.timg()
+                        .$config
+                        .modify(|r, w| unsafe { w.bits((r.bits() & !clear) | set) });
+                }
+
+                /// Set the prescaler (2-65536)
+                ///
+                /// The timer is clocked with the APB frequency divided by this value.
+                pub fn set_divider(&mut self, divider: u32) -> Result<&mut Self, Error> {
+                    if divider < TIMER_MIN_DIVIDER || divider > TIMER_MAX_DIVIDER {
+                        return Err(Error::InvalidDivider);
+                    }
+                    // 65536 is encoded as 0
+                    let bits = (divider & 0xffff) << CONFIG_DIVIDER_SHIFT;
+
+                    // the prescaler should only be changed when the timer is disabled
+                    let enabled = self.is_enabled();
+                    self.modify_config(0, CONFIG_EN);
+                    self.modify_config(bits, CONFIG_DIVIDER_MASK);
+                    if enabled {
+                        self.modify_config(CONFIG_EN, 0);
+                    }
+                    Ok(self)
+                }
+
+                /// Get the prescaler
+                pub fn divider(&self) -> u32 {
+                    match (self.timg().$config.read().bits() & CONFIG_DIVIDER_MASK)
+                        >> CONFIG_DIVIDER_SHIFT
+                    {
+                        0 => TIMER_MAX_DIVIDER,
+                        divider => divider,
+                    }
+                }
+
+                /// Frequency with which the timer counts
+                ///
+                /// Only stable while the APB frequency is locked, e.g. while the timer is running.
+                pub fn frequency(&self) -> Hertz {
+                    self.clock_control.apb_frequency() / self.divider()
+                }
+
+                /// Start the counter (without changing the alarm)
+                ///
+                /// Locks the APB frequency until the timer is stopped.
+                pub fn enable(&mut self) -> &mut Self {
+                    if self._apb_lock.is_none() {
+                        self._apb_lock = Some(self.clock_control.lock_apb_frequency());
+                    }
+                    self.modify_config(CONFIG_EN, 0);
+                    self
+                }
+
+                /// Stop the counter and the alarm
+                pub fn disable(&mut self) -> &mut Self {
+                    self.modify_config(0, CONFIG_EN | CONFIG_ALARM_EN);
+                    self._apb_lock = None;
+                    self
+                }
+
+                /// Return true if the counter is running
+                pub fn is_enabled(&self) -> bool {
+                    self.timg().$config.read().bits() & CONFIG_EN != 0
+                }
+
+                /// Set the counting direction
+                pub fn set_increasing(&mut self, increasing: bool) -> &mut Self {
+                    if increasing {
+                        self.modify_config(CONFIG_INCREASE, 0);
+                    } else {
+                        self.modify_config(0, CONFIG_INCREASE);
+                    }
+                    self
+                }
+
+                /// Reload the counter with the load value when the alarm is triggered
+                pub fn set_autoreload(&mut self, autoreload: bool) -> &mut Self {
+                    if autoreload {
+                        self.modify_config(CONFIG_AUTORELOAD, 0);
+                    } else {
+                        self.modify_config(0, CONFIG_AUTORELOAD);
+                    }
+                    self
+                }
+
+                /// Get the current counter value
+                pub fn value(&self) -> u64 {
+                    let timg = self.timg();
+                    // latch the counter value
+                    timg.$update.write(|w| unsafe { w.bits(0) });
+                    (timg.$hi.read().bits() as u64) << 32 | timg.$lo.read().bits() as u64
+                }
+
+                /// Set the counter value
+                ///
+                /// This value is also used when the counter is reloaded by the alarm.
+                pub fn set_value(&mut self, value: u64) -> &mut Self {
+                    let timg = self.timg();
+                    unsafe {
+                        timg.$loadlo.write(|w| w.bits(value as u32));
+                        timg.$loadhi.write(|w| w.bits((value >> 32) as u32));
+                        timg.$load.write(|w| w.bits(0));
+                    }
+                    self
+                }
+
+                /// Set the alarm value and enable the alarm
+                ///
+                /// The alarm is disabled by hardware when triggered, unless autoreload is used.
+                /// Use [enable_alarm](Self::enable_alarm) to enable the alarm again.
+                pub fn set_alarm(&mut self, value: u64) -> &mut Self {
+                    let timg = self.timg();
+                    unsafe {
+                        timg.$alarmlo.write(|w| w.bits(value as u32));
+                        timg.$alarmhi.write(|w| w.bits((value >> 32) as u32));
+                    }
+                    self.enable_alarm()
+                }
+
+                /// Get the alarm value
+                pub fn alarm(&self) -> u64 {
+                    let timg = self.timg();
+                    (timg.$alarmhi.read().bits() as u64) << 32 | timg.$alarmlo.read().bits() as u64
+                }
+
+                /// (Re-)enable the alarm
+                pub fn enable_alarm(&mut self) -> &mut Self {
+                    self.modify_config(CONFIG_ALARM_EN, 0);
+                    self
+                }
+
+                /// Return true if the alarm is enabled
+                pub fn is_alarm_enabled(&self) -> bool {
+                    self.timg().$config.read().bits() & CONFIG_ALARM_EN != 0
+                }
+
+                /// Enable an interrupt on alarm
+                pub fn listen(&mut self, event: Event) {
+                    match event {
+                        Event::TimeOut => self.modify_config(CONFIG_LEVEL_INT_EN, 0),
+                        Event::TimeOutEdge => self.modify_config(CONFIG_EDGE_INT_EN, 0),
+                    }
+                    // the register is shared with the other timers and the watchdog
+                    xtensa_lx6_rt::interrupt::free(|_| {
+                        self.timg()
+                            .int_ena_timers
+                            .modify(|r, w| unsafe { w.bits(r.bits() | $int_bit) })
+                    });
+                }
+
+                /// Disable an interrupt on alarm
+                pub fn unlisten(&mut self, event: Event) {
+                    match event {
+                        Event::TimeOut => self.modify_config(0, CONFIG_LEVEL_INT_EN),
+                        Event::TimeOutEdge => self.modify_config(0, CONFIG_EDGE_INT_EN),
+                    }
+                    let config = self.timg().$config.read().bits();
+                    if config & (CONFIG_LEVEL_INT_EN | CONFIG_EDGE_INT_EN) == 0 {
+                        xtensa_lx6_rt::interrupt::free(|_| {
+                            self.timg()
+                                .int_ena_timers
+                                .modify(|r, w| unsafe { w.bits(r.bits() & !$int_bit) })
+                        });
+                    }
+                }
+
+                /// Return true if the alarm has been triggered since the last clear
+                pub fn is_interrupt_set(&self) -> bool {
+                    self.timg().int_raw_timers.read().bits() & $int_bit != 0
+                }
+
+                /// Clear the alarm interrupt
+                pub fn clear_interrupt(&mut self) {
+                    self.timg()
+                        .int_clr_timers
+                        .write(|w| unsafe { w.bits($int_bit) });
+                }
+
+                /// Convert a time to a number of timer ticks at the current frequency
+                fn ticks(&self, time: MicroSeconds) -> u64 {
+                    u32::from(self.clock_control.apb_frequency()) as u64 * u32::from(time) as u64
+                        / (self.divider() as u64 * 1_000_000)
+                }
+            }
+
+            /// Start a count down, which restarts automatically after expiry
+            impl CountDown for Timer<$TIMGX, $TIMERX> {
+                type Time = MicroSeconds;
+
+                fn start<T: Into<Self::Time>>(&mut self, timeout: T) {
+                    self.disable();
+
+                    // lock the APB frequency before calculating the number of ticks
+                    self._apb_lock = Some(self.clock_control.lock_apb_frequency());
+                    let ticks = self.ticks(timeout.into());
+
+                    self.modify_config(
+                        CONFIG_INCREASE | CONFIG_AUTORELOAD | CONFIG_LEVEL_INT_EN,
+                        0,
+                    );
+                    self.set_value(0);
+                    self.clear_interrupt();
+                    self.set_alarm(ticks);
+                    self.enable();
+                }
+
+                fn wait(&mut self) -> nb::Result<(), void::Void> {
+                    if !self.is_interrupt_set() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    self.clear_interrupt();
+                    self.enable_alarm();
+                    Ok(())
+                }
+            }
+
+            impl Periodic for Timer<$TIMGX, $TIMERX> {}
+
+            impl Cancel for Timer<$TIMGX, $TIMERX> {
+                type Error = Error;
+
+                fn cancel(&mut self) -> Result<(), Self::Error> {
+                    if !self.is_enabled() {
+                        return Err(Error::TimerNotRunning);
+                    }
+                    self.disable();
+                    self.clear_interrupt();
+                    Ok(())
+                }
+            }
+        )+
+    }
+}
+
+halTimer! {
+    TIMG0, Timer0: (t0config, t0lo, t0hi, t0update, t0alarmlo, t0alarmhi, t0loadlo, t0loadhi,
+        t0load, 1 << 0),
+    TIMG0, Timer1: (t1config, t1lo, t1hi, t1update, t1alarmlo, t1alarmhi, t1loadlo, t1loadhi,
+        t1load, 1 << 1),
+    TIMG1, Timer0: (t0config, t0lo, t0hi, t0update, t0alarmlo, t0alarmhi, t0loadlo, t0loadhi,
+        t0load, 1 << 0),
+    TIMG1, Timer1: (t1config, t1lo, t1hi, t1update, t1alarmlo, t1alarmhi, t1loadlo, t1loadhi,
+        t1load, 1 << 1),
+}